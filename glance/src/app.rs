@@ -6,7 +6,8 @@ use adw::subclass::prelude::*;
 use gtk::gio;
 use gtk::glib;
 
-use crate::window::GlanceWindow;
+use crate::config::{GlanceConfig, MatchingProfile, SecurityLevel, Sensitivity};
+use crate::window::{GlanceWindow, present_dialog};
 
 mod imp {
     use super::*;
@@ -104,8 +105,15 @@ impl GlanceApplication {
         }
     }
     
+    /// The window dialogs should be attached to, even when focus has moved
+    /// to another application or monitor
+    fn parent_window(&self) -> Option<gtk::Window> {
+        self.active_window()
+            .or_else(|| self.windows().into_iter().next())
+    }
+    
     fn show_about(&self) {
-        let window = self.active_window();
+        let window = self.parent_window();
         
         let dialog = adw::AboutWindow::builder()
             .application_name("Glance")
@@ -116,19 +124,17 @@ impl GlanceApplication {
             .license_type(gtk::License::Gpl30)
             .website("https://github.com/glance-linux/glance")
             .comments("Windows Hello-style facial recognition for Linux")
-            .modal(true)
             .build();
         
-        if let Some(win) = window {
-            dialog.set_transient_for(Some(&win));
+        match window {
+            Some(win) => present_dialog(&win, &dialog),
+            None => dialog.present(),
         }
-        dialog.present();
     }
     
     fn show_preferences(&self) {
-        let window = self.active_window();
+        let window = self.parent_window();
         let dialog = adw::PreferencesWindow::new();
-        dialog.set_modal(true);
         
         let general_page = adw::PreferencesPage::builder()
             .title("General")
@@ -146,26 +152,326 @@ impl GlanceApplication {
             .active(true)
             .build();
         
+        let enroll_quality_row = adw::SpinRow::builder()
+            .title("Minimum Enrollment Quality")
+            .subtitle("Frames lit worse than this don't count toward a capture")
+            .digits(2)
+            .build();
+        enroll_quality_row.set_adjustment(Some(&gtk::Adjustment::new(
+            GlanceConfig::load().recognition.min_enroll_quality, 0.0, 0.9, 0.05, 0.1, 0.0)));
+        
+        enroll_quality_row.connect_value_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.recognition.min_enroll_quality = row.value();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save enrollment quality: {}", e);
+            }
+        });
+        
+        let emitter_row = adw::SwitchRow::builder()
+            .title("Keep IR Emitter On During Capture")
+            .subtitle("Steadier IR lighting while enrolling. Needs linux-enable-ir-emitter")
+            .active(GlanceConfig::load().camera.ir_emitter_during_preview)
+            .build();
+        
+        emitter_row.connect_active_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.camera.ir_emitter_during_preview = row.is_active();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save IR emitter setting: {}", e);
+            }
+        });
+        
+        let privacy_preview_row = adw::SwitchRow::builder()
+            .title("Privacy Preview")
+            .subtitle("Show a pixelated camera picture while enrolling, enough to aim by. Detection still uses the full frame")
+            .active(GlanceConfig::load().camera.privacy_preview)
+            .build();
+        
+        privacy_preview_row.connect_active_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.camera.privacy_preview = row.is_active();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save privacy preview setting: {}", e);
+            }
+        });
+        
         camera_group.add(&prefer_ir);
+        camera_group.add(&enroll_quality_row);
+        camera_group.add(&emitter_row);
+        camera_group.add(&privacy_preview_row);
         general_page.add(&camera_group);
         
         let security_group = adw::PreferencesGroup::builder()
             .title("Security")
             .build();
         
-        let threshold_row = adw::SpinRow::builder()
-            .title("Match Threshold")
+        let recognition = GlanceConfig::load().recognition;
+        let sensitivity_labels: Vec<&str> = Sensitivity::ALL.iter().map(|s| s.label()).collect();
+        let sensitivity_row = adw::ComboRow::builder()
+            .title("Recognition Sensitivity")
+            .subtitle("Strict rejects more lookalikes, Lenient unlocks more easily")
+            .model(&gtk::StringList::new(&sensitivity_labels))
+            .selected(Sensitivity::ALL.iter().position(|s| *s == recognition.sensitivity).unwrap_or(1) as u32)
+            .build();
+        
+        let ir_threshold_row = adw::SpinRow::builder()
+            .title("IR Match Threshold")
             .subtitle("Lower values are more strict")
+            .digits(2)
+            .visible(recognition.sensitivity == Sensitivity::Advanced)
             .build();
-        threshold_row.set_adjustment(Some(&gtk::Adjustment::new(0.45, 0.3, 0.6, 0.05, 0.1, 0.0)));
+        ir_threshold_row.set_adjustment(Some(&gtk::Adjustment::new(recognition.ir_tolerance, 0.3, 0.6, 0.05, 0.1, 0.0)));
         
-        security_group.add(&threshold_row);
+        let rgb_threshold_row = adw::SpinRow::builder()
+            .title("RGB Match Threshold")
+            .subtitle("Lower values are more strict")
+            .digits(2)
+            .visible(recognition.sensitivity == Sensitivity::Advanced)
+            .build();
+        rgb_threshold_row.set_adjustment(Some(&gtk::Adjustment::new(recognition.rgb_tolerance, 0.3, 0.6, 0.05, 0.1, 0.0)));
+        
+        sensitivity_row.connect_selected_notify(glib::clone!(
+            #[weak] ir_threshold_row,
+            #[weak] rgb_threshold_row,
+            move |row| {
+                let Some(sensitivity) = Sensitivity::ALL.get(row.selected() as usize).copied() else {
+                    return;
+                };
+                
+                let advanced = sensitivity == Sensitivity::Advanced;
+                ir_threshold_row.set_visible(advanced);
+                rgb_threshold_row.set_visible(advanced);
+                
+                let mut config = GlanceConfig::load();
+                sensitivity.apply(&mut config.recognition);
+                if let Some((ir, rgb)) = sensitivity.tolerances() {
+                    ir_threshold_row.set_value(ir);
+                    rgb_threshold_row.set_value(rgb);
+                }
+                if let Err(e) = config.save() {
+                    eprintln!("[Preferences] Failed to save sensitivity: {}", e);
+                }
+            }
+        ));
+        
+        ir_threshold_row.connect_value_notify(|row| {
+            if !row.is_visible() {
+                return;
+            }
+            let mut config = GlanceConfig::load();
+            config.recognition.ir_tolerance = row.value();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save IR threshold: {}", e);
+            }
+        });
+        
+        rgb_threshold_row.connect_value_notify(|row| {
+            if !row.is_visible() {
+                return;
+            }
+            let mut config = GlanceConfig::load();
+            config.recognition.rgb_tolerance = row.value();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save RGB threshold: {}", e);
+            }
+        });
+        
+        let mask_row = adw::SwitchRow::builder()
+            .title("Mask-Friendly Recognition")
+            .subtitle("Less secure: also accepts captures made while wearing a mask")
+            .active(recognition.mask_friendly)
+            .build();
+        
+        mask_row.connect_active_notify(glib::clone!(
+            #[weak] dialog,
+            move |row| {
+                let save = |enabled: bool| {
+                    let mut config = GlanceConfig::load();
+                    config.recognition.mask_friendly = enabled;
+                    if let Err(e) = config.save() {
+                        eprintln!("[Preferences] Failed to save mask-friendly setting: {}", e);
+                    }
+                };
+                
+                if !row.is_active() {
+                    save(false);
+                    return;
+                }
+                
+                let confirm = adw::MessageDialog::builder()
+                    .heading("Masked Recognition Is Less Secure")
+                    .body("With a mask on, only the upper half of your face can be compared, \
+                          so someone who looks similar is more likely to be let in. After \
+                          enabling, use \"Capture With Mask\" from the main menu while \
+                          wearing one.")
+                    .build();
+                confirm.add_response("cancel", "Cancel");
+                confirm.add_response("enable", "Enable");
+                confirm.set_response_appearance("enable", adw::ResponseAppearance::Destructive);
+                confirm.set_default_response(Some("cancel"));
+                confirm.set_close_response("cancel");
+                
+                confirm.connect_response(None, glib::clone!(
+                    #[weak] row,
+                    move |_, response| {
+                        if response == "enable" {
+                            save(true);
+                        } else {
+                            row.set_active(false);
+                        }
+                    }
+                ));
+                
+                present_dialog(&dialog, &confirm);
+            }
+        ));
+        
+        security_group.add(&sensitivity_row);
+        security_group.add(&ir_threshold_row);
+        security_group.add(&rgb_threshold_row);
+        security_group.add(&mask_row);
+        
+        let thumbnail_row = adw::SwitchRow::builder()
+            .title("Save Face Thumbnail")
+            .subtitle("Stores a small picture of your face with the enrollment so you can tell it apart. Applies to the next capture.")
+            .active(recognition.store_thumbnail)
+            .build();
+        
+        thumbnail_row.connect_active_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.recognition.store_thumbnail = row.is_active();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save thumbnail setting: {}", e);
+            }
+        });
+        security_group.add(&thumbnail_row);
+        
+        let average_row = adw::SwitchRow::builder()
+            .title("Average Enrollment Frames")
+            .subtitle("Save the mean of the captured frames as one steadier template instead of each frame. Applies to the next capture.")
+            .active(recognition.average_encodings)
+            .build();
+        
+        average_row.connect_active_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.recognition.average_encodings = row.is_active();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save averaging setting: {}", e);
+            }
+        });
+        security_group.add(&average_row);
+        
+        let expressions_row = adw::SwitchRow::builder()
+            .title("Capture a Smile Too")
+            .subtitle("Enrollment asks for a slight smile after the neutral face, so you're recognized with either. Applies to the next capture.")
+            .active(recognition.capture_expressions)
+            .build();
+        
+        expressions_row.connect_active_notify(|row| {
+            let mut config = GlanceConfig::load();
+            config.recognition.capture_expressions = row.is_active();
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save expression setting: {}", e);
+            }
+        });
+        security_group.add(&expressions_row);
         general_page.add(&security_group);
+        
+        let level_group = adw::PreferencesGroup::builder()
+            .title("Security Level")
+            .description("Sets liveness, blink, match margin, confidence and frame requirements together. Individual values can still be edited in config.json.")
+            .build();
+        
+        let current_level = GlanceConfig::load().recognition.security_level;
+        let labels: Vec<&str> = SecurityLevel::ALL.iter().map(|l| l.label()).collect();
+        let level_row = adw::ComboRow::builder()
+            .title("Level")
+            .subtitle(current_level.description())
+            .model(&gtk::StringList::new(&labels))
+            .selected(SecurityLevel::ALL.iter().position(|l| *l == current_level).unwrap_or(0) as u32)
+            .build();
+        
+        level_row.connect_selected_notify(|row| {
+            let Some(level) = SecurityLevel::ALL.get(row.selected() as usize).copied() else {
+                return;
+            };
+            row.set_subtitle(level.description());
+            
+            let mut config = GlanceConfig::load();
+            level.apply(&mut config.recognition);
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save security level: {}", e);
+            }
+        });
+        
+        level_group.add(&level_row);
+        general_page.add(&level_group);
+        
+        let profile_group = adw::PreferencesGroup::builder()
+            .title("Matching Profile")
+            .description("Sets the detector, encoding jitters and detection scale together. Applies to login and to new captures.")
+            .build();
+        
+        let profile_labels: Vec<&str> = MatchingProfile::ALL.iter().map(|p| p.label()).collect();
+        let profile_row = adw::ComboRow::builder()
+            .title("Profile")
+            .subtitle(recognition.profile.description())
+            .model(&gtk::StringList::new(&profile_labels))
+            .selected(MatchingProfile::ALL.iter().position(|p| *p == recognition.profile).unwrap_or(2) as u32)
+            .build();
+        
+        profile_row.connect_selected_notify(|row| {
+            let Some(profile) = MatchingProfile::ALL.get(row.selected() as usize).copied() else {
+                return;
+            };
+            row.set_subtitle(profile.description());
+            
+            let mut config = GlanceConfig::load();
+            profile.apply(&mut config.recognition);
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save matching profile: {}", e);
+            }
+        });
+        
+        profile_group.add(&profile_row);
+        general_page.add(&profile_group);
+        
+        let advanced_group = adw::PreferencesGroup::builder()
+            .title("Advanced")
+            .description("Faces enrolled with other models won't match until captured again. \
+                          Authentication uses the models_dir in /etc/glance/config.json.")
+            .build();
+        
+        let models_row = adw::EntryRow::builder()
+            .title("Custom Models Directory")
+            .text(recognition.models_dir.as_str())
+            .show_apply_button(true)
+            .build();
+        
+        models_row.connect_apply(|row| {
+            let dir = row.text().trim().to_string();
+            if !dir.is_empty() && !crate::models::models_exist_in(&std::path::PathBuf::from(&dir)) {
+                eprintln!("[Preferences] {} does not contain the models, not saving", dir);
+                row.add_css_class("error");
+                return;
+            }
+            row.remove_css_class("error");
+            
+            let mut config = GlanceConfig::load();
+            config.recognition.models_dir = dir;
+            if let Err(e) = config.save() {
+                eprintln!("[Preferences] Failed to save models directory: {}", e);
+            }
+        });
+        
+        advanced_group.add(&models_row);
+        general_page.add(&advanced_group);
         dialog.add(&general_page);
         
-        if let Some(win) = window {
-            dialog.set_transient_for(Some(&win));
+        match window {
+            Some(win) => present_dialog(&win, &dialog),
+            None => dialog.present(),
         }
-        dialog.present();
     }
 }
@@ -9,6 +9,8 @@ pub struct CameraInfo {
     pub is_ir: bool,
 }
 
+/// A captured RGB frame. The pixels are zeroed when the frame is dropped
+/// so raw face imagery doesn't linger in freed memory.
 #[derive(Debug, Clone)]
 pub struct CameraFrame {
     pub rgb_data: Vec<u8>,
@@ -16,8 +18,30 @@ pub struct CameraFrame {
     pub height: u32,
 }
 
+impl Drop for CameraFrame {
+    fn drop(&mut self) {
+        zero_bytes(&mut self.rgb_data);
+    }
+}
+
+fn zero_bytes(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Zero an OpenCV frame buffer before it is released
+pub fn wipe_mat(mat: &mut opencv::core::Mat) {
+    if let Ok(bytes) = mat.data_bytes_mut() {
+        zero_bytes(bytes);
+    }
+}
+
 pub struct Camera {
     cap: VideoCapture,
+    /// `camera.max_frame_dimension`, read when the camera is opened
+    max_dimension: u32,
 }
 
 impl Camera {
@@ -32,8 +56,11 @@ impl Camera {
         cap.set(videoio::CAP_PROP_FRAME_WIDTH, 640.0).ok();
         cap.set(videoio::CAP_PROP_FRAME_HEIGHT, 480.0).ok();
         cap.set(videoio::CAP_PROP_FPS, 30.0).ok();
+        // Don't let the driver queue up frames we'd read late
+        cap.set(videoio::CAP_PROP_BUFFERSIZE, 1.0).ok();
         
-        Ok(Self { cap })
+        let max_dimension = crate::config::GlanceConfig::load().camera.max_frame_dimension;
+        Ok(Self { cap, max_dimension })
     }
     
     pub fn read_frame(&mut self) -> Result<CameraFrame, String> {
@@ -45,8 +72,21 @@ impl Camera {
             return Err("Empty frame".to_string());
         }
         
+        // 16-bit, BGRA or YUYV frames become 8-bit grey or BGR first
+        let mat = pam_glance::camera::normalize_frame(mat)
+            .map_err(|e| format!("Unusable frame: {}", e))?;
+        
+        // Detection and the RGB copy below both scale with frame size
+        let mut mat = pam_glance::camera::clamp_frame(mat, self.max_dimension)
+            .map_err(|e| format!("Failed to scale frame: {}", e))?;
+        
+        let code = if mat.channels() == 1 {
+            opencv::imgproc::COLOR_GRAY2RGB
+        } else {
+            opencv::imgproc::COLOR_BGR2RGB
+        };
         let mut rgb_mat = opencv::core::Mat::default();
-        opencv::imgproc::cvt_color(&mat, &mut rgb_mat, opencv::imgproc::COLOR_BGR2RGB, 0)
+        opencv::imgproc::cvt_color(&mat, &mut rgb_mat, code, 0)
             .map_err(|e| format!("Color conversion failed: {}", e))?;
         
         let width = rgb_mat.cols() as u32;
@@ -55,6 +95,9 @@ impl Camera {
             .map_err(|e| format!("Failed to get frame data: {}", e))?
             .to_vec();
         
+        wipe_mat(&mut mat);
+        wipe_mat(&mut rgb_mat);
+        
         Ok(CameraFrame {
             rgb_data: data,
             width,
@@ -63,71 +106,101 @@ impl Camera {
     }
     
     pub fn detect_cameras() -> Option<CameraInfo> {
-        let cameras = Self::detect_all_cameras();
-        
-        // Prefer IR camera
-        if let Some(ir_cam) = cameras.iter().find(|c| c.is_ir) {
-            eprintln!("Selected IR camera: {} (device {})", ir_cam.name, ir_cam.device_id);
-            return Some(ir_cam.clone());
-        }
+        // Prefer IR camera; only open candidates until one works
+        let mut candidates = Self::sysfs_candidates();
+        candidates.sort_by_key(|c| !c.is_ir);
         
-        if let Some(cam) = cameras.first() {
+        let cam = candidates.into_iter().find(Self::can_capture)?;
+        if cam.is_ir {
+            eprintln!("Selected IR camera: {} (device {})", cam.name, cam.device_id);
+        } else {
             eprintln!("Selected camera: {} (device {})", cam.name, cam.device_id);
-            return Some(cam.clone());
         }
-        
-        None
+        Some(cam)
     }
     
     /// Detect all available cameras (both IR and RGB)
     pub fn detect_all_cameras() -> Vec<CameraInfo> {
-        let mut cameras = Vec::new();
-        
-        for device_id in 0..10 {
-            // Check if this is a metadata device by reading the index
-            let index_path = format!("/sys/class/video4linux/video{}/index", device_id);
-            if let Ok(index_str) = fs::read_to_string(&index_path) {
-                if let Ok(index) = index_str.trim().parse::<i32>() {
-                    // Index 0 is typically the main capture device, index 1+ are metadata
-                    if index != 0 {
-                        eprintln!("Skipping video{} (index {}), likely metadata device", device_id, index);
-                        continue;
-                    }
-                }
-            }
-            
-            if let Ok(mut cap) = VideoCapture::new(device_id, CAP_V4L2) {
-                if cap.is_opened().unwrap_or(false) {
-                    // Try to read a frame to verify it's a real capture device
-                    let mut test_frame = opencv::core::Mat::default();
-                    if cap.read(&mut test_frame).is_ok() && !test_frame.empty() {
-                        let name = Self::get_camera_name(device_id);
-                        let is_ir = Self::is_ir_camera(&name);
-                        eprintln!("Found camera {}: {} (IR: {})", device_id, name, is_ir);
-                        cameras.push(CameraInfo {
-                            device_id,
-                            name,
-                            is_ir,
-                        });
-                    }
-                }
-                let _ = cap.release();
-            }
-        }
-        
-        cameras
+        Self::sysfs_candidates()
+            .into_iter()
+            .filter(Self::can_capture)
+            .collect()
     }
     
     /// Get the RGB camera (for dual-camera capture)
     pub fn detect_rgb_camera() -> Option<CameraInfo> {
-        let cameras = Self::detect_all_cameras();
-        cameras.into_iter().find(|c| !c.is_ir)
+        Self::sysfs_candidates()
+            .into_iter()
+            .filter(|c| !c.is_ir)
+            .find(Self::can_capture)
     }
     
     /// Get the IR camera
     pub fn detect_ir_camera() -> Option<CameraInfo> {
-        let cameras = Self::detect_all_cameras();
-        cameras.into_iter().find(|c| c.is_ir)
+        Self::sysfs_candidates()
+            .into_iter()
+            .filter(|c| c.is_ir)
+            .find(Self::can_capture)
+    }
+    
+    /// List likely capture devices from sysfs without opening any of them.
+    /// Metadata nodes (index != 0) are skipped, so only the devices worth
+    /// probing get opened — each open costs time and flashes the camera LED.
+    fn sysfs_candidates() -> Vec<CameraInfo> {
+        let entries = match fs::read_dir("/sys/class/video4linux") {
+            Ok(entries) => entries,
+            Err(_) => {
+                // No sysfs (e.g. some sandboxes): fall back to probing the usual range
+                eprintln!("video4linux sysfs not available, probing video0-9");
+                return (0..10)
+                    .filter(|id| std::path::Path::new(&format!("/dev/video{}", id)).exists())
+                    .map(|device_id| CameraInfo { device_id, name: format!("video{}", device_id), is_ir: false })
+                    .collect();
+            }
+        };
+        
+        let mut candidates: Vec<CameraInfo> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let device_id: i32 = entry.file_name().to_str()?.strip_prefix("video")?.parse().ok()?;
+                
+                // Index 0 is typically the main capture device, index 1+ are metadata
+                let index_path = entry.path().join("index");
+                if let Ok(Ok(index)) = fs::read_to_string(&index_path).map(|s| s.trim().parse::<i32>()) {
+                    if index != 0 {
+                        eprintln!("Skipping video{} (metadata device)", device_id);
+                        return None;
+                    }
+                }
+                
+                let name = Self::get_camera_name(device_id);
+                let is_ir = Self::is_ir_camera(&name);
+                Some(CameraInfo { device_id, name, is_ir })
+            })
+            .collect();
+        
+        candidates.sort_by_key(|c| c.device_id);
+        // One entry per physical camera, not per /dev/video node
+        pam_glance::camera::dedupe_camera_nodes(candidates, |c| c.device_id)
+    }
+    
+    /// Open the device and read one frame to confirm it really captures
+    fn can_capture(info: &CameraInfo) -> bool {
+        let Ok(mut cap) = VideoCapture::new(info.device_id, CAP_V4L2) else {
+            return false;
+        };
+        
+        let mut ok = false;
+        if cap.is_opened().unwrap_or(false) {
+            let mut test_frame = opencv::core::Mat::default();
+            ok = cap.read(&mut test_frame).is_ok() && !test_frame.empty();
+        }
+        let _ = cap.release();
+        
+        if ok {
+            eprintln!("Found camera {}: {} (IR: {})", info.device_id, info.name, info.is_ir);
+        }
+        ok
     }
     
     fn get_camera_name(device_id: i32) -> String {
@@ -0,0 +1,961 @@
+//! Command-line subcommands for Glance
+//!
+//! `glance` with no arguments launches the GUI. A recognised subcommand
+//! runs headless and exits without touching GTK.
+
+use gtk4::glib;
+
+use crate::camera::Camera;
+use crate::config::{self, GlanceConfig};
+use crate::face::{ComputeDevice, FaceProcessor};
+use crate::{models, pam_stack, storage};
+
+const USAGE: &str = "\
+Usage: glance [COMMAND]
+
+Commands:
+  migrate [--remove]    Consolidate your face data into the canonical location
+                        (--remove deletes the stale copies afterwards)
+  config export         Print the effective configuration as JSON
+  config import FILE    Check a configuration (e.g. from config export) and
+                        save it as your config; nothing is written on errors
+  erase [USER]          Remove everything Glance stores about a user (default:
+                        you); run as root to include system locations
+  doctor [--fix]        Report models, compute device, cameras and face data
+                        that differs between storage locations (--fix keeps
+                        the newest copy, as migrate --remove does)
+  bench [--runs N] [--camera N | --image PATH]
+                        Time detection, encoding and matching on this machine
+                        using one frame (default: first camera, 20 runs)
+  enroll --camera N [--user NAME] [--ir] [--background]
+                        Enroll headlessly from one camera, e.g. a v4l2loopback
+                        device in tests (--ir stores the template as IR;
+                        --background then captures the empty scene for
+                        camera.background_subtraction, needs root)
+  enable-pam [--test-first] [--service NAME]
+                        Add Glance to a PAM service (default: sudo). With
+                        --test-first, only after a live face auth succeeds
+  disable-pam [--service NAME]
+                        Remove Glance from a PAM service (default: sudo)
+  prewarm [--hold SECS]
+                        Load the models and wake the cameras ahead of a login,
+                        e.g. from a greeter (--hold keeps the probe fresh)
+  verify [--user NAME] [FILE]
+                        Match a face encoding (a JSON array of 128 numbers,
+                        read from FILE or stdin) against a user's enrollment
+                        without a camera; prints the distance on a match
+  enable                Re-enable face auth after the PAM module disabled
+                        itself for lack of a camera or models (needs root)
+  stats                 Show why face logins succeeded or failed, by time of
+                        day (needs \"stats\": {\"enabled\": true} in the config)
+  help                  Show this message
+
+Run without a command to open the Glance window.";
+
+/// Run a subcommand if one was given. Returns `None` when the GUI should start.
+pub fn run(args: &[String]) -> Option<glib::ExitCode> {
+    let command = args.first()?;
+    
+    // Leave GApplication options (e.g. --gapplication-service) to GTK
+    if command.starts_with('-') && command != "-h" && command != "--help" {
+        return None;
+    }
+    
+    let rest = &args[1..];
+    let code = match command.as_str() {
+        "migrate" => migrate(rest),
+        "doctor" => doctor(rest),
+        "erase" => erase(rest),
+        "config" => config_command(rest),
+        "bench" => bench(rest),
+        "enroll" => enroll(rest),
+        "enable-pam" => enable_pam(rest),
+        "disable-pam" => disable_pam(rest),
+        "stats" => stats(),
+        "prewarm" => prewarm(rest),
+        "verify" => verify(rest),
+        "enable" => enable(),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            glib::ExitCode::SUCCESS
+        }
+        other => {
+            eprintln!("Unknown command: {}\n\n{}", other, USAGE);
+            glib::ExitCode::FAILURE
+        }
+    };
+    
+    Some(code)
+}
+
+fn current_username() -> String {
+    users::get_current_username()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "user".to_string())
+}
+
+fn migrate(args: &[String]) -> glib::ExitCode {
+    let remove_stale = args.iter().any(|a| a == "--remove");
+    let username = current_username();
+    
+    let copies = storage::find_face_data_copies(&username);
+    if copies.is_empty() {
+        println!("No face data found for {}", username);
+        return glib::ExitCode::SUCCESS;
+    }
+    
+    println!("Face data copies for {}:", username);
+    for copy in &copies {
+        match copy.data {
+            Some(ref data) => println!("  {} (updated {}, {} encoding(s))",
+                                       copy.path.display(), data.updated_at,
+                                       data.all_encodings().len()),
+            None => println!("  {} (unreadable)", copy.path.display()),
+        }
+    }
+    
+    let report = match storage::migrate_face_data(&username, remove_stale) {
+        Ok(Some(report)) => report,
+        Ok(None) => {
+            eprintln!("No valid face data to migrate — re-enroll with the Glance app");
+            return glib::ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    println!("\nUsing {} as the authoritative copy", report.source.display());
+    for path in &report.written {
+        println!("  wrote {}", path.display());
+    }
+    
+    if report.stale.is_empty() {
+        println!("No stale copies left behind");
+    } else if remove_stale {
+        for path in &report.removed {
+            println!("  removed {}", path.display());
+        }
+        for path in report.stale.iter().filter(|p| !report.removed.contains(p)) {
+            println!("  could not remove {}", path.display());
+        }
+    } else {
+        println!("Stale copies (re-run with --remove to delete them):");
+        for path in &report.stale {
+            println!("  {}", path.display());
+        }
+    }
+    
+    if report.secure_db_entry {
+        println!("\nNote: /var/lib/glance/faces.json also holds an entry for {}.", username);
+        println!("It is managed by the system and was left untouched.");
+    }
+    
+    glib::ExitCode::SUCCESS
+}
+
+fn config_command(args: &[String]) -> glib::ExitCode {
+    let result = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("export"), _) => config::export().map(|json| println!("{}", json)),
+        (Some("import"), Some(file)) => std::fs::read_to_string(file)
+            .map_err(|e| format!("Could not read {}: {}", file, e))
+            .and_then(|content| config::import(&content))
+            .map(|_| println!("Saved to {}", config::user_config_path().display())),
+        _ => Err(format!("Usage: glance config export | glance config import FILE\n\n{}", USAGE)),
+    };
+    
+    match result {
+        Ok(()) => glib::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+fn erase(args: &[String]) -> glib::ExitCode {
+    let username = args.first().cloned().unwrap_or_else(current_username);
+    let report = storage::purge_user(&username);
+    
+    if report.removed.is_empty() && !report.secure_db_entry_removed && report.failed.is_empty() {
+        println!("Nothing stored for {}", username);
+        return glib::ExitCode::SUCCESS;
+    }
+    
+    for path in &report.removed {
+        println!("  removed {}", path.display());
+    }
+    if report.secure_db_entry_removed {
+        println!("  removed {}'s entry from /var/lib/glance/faces.json", username);
+    }
+    for (path, error) in &report.failed {
+        println!("  could not remove {}: {}", path.display(), error);
+    }
+    
+    if report.failed.is_empty() {
+        glib::ExitCode::SUCCESS
+    } else {
+        glib::ExitCode::FAILURE
+    }
+}
+
+fn verify(args: &[String]) -> glib::ExitCode {
+    use std::io::Read;
+    
+    let mut username = current_username();
+    let mut file = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--user" => match iter.next() {
+                Some(name) => username = name.clone(),
+                None => {
+                    eprintln!("--user needs a name");
+                    return glib::ExitCode::FAILURE;
+                }
+            },
+            path if file.is_none() => file = Some(path.to_string()),
+            other => {
+                eprintln!("Unexpected argument: {}\n\n{}", other, USAGE);
+                return glib::ExitCode::FAILURE;
+            }
+        }
+    }
+    
+    let mut content = String::new();
+    let read = match file.as_deref() {
+        Some(path) if path != "-" => std::fs::read_to_string(path)
+            .map(|c| content = c)
+            .map_err(|e| format!("Could not read {}: {}", path, e)),
+        _ => std::io::stdin().read_to_string(&mut content)
+            .map(|_| ())
+            .map_err(|e| format!("Could not read stdin: {}", e)),
+    };
+    let encoding = read.and_then(|_| {
+        serde_json::from_str::<Vec<f32>>(&content)
+            .map_err(|e| format!("Expected a JSON array of numbers: {}", e))
+    });
+    let encoding = match encoding {
+        Ok(encoding) => encoding,
+        Err(e) => {
+            eprintln!("{}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    match pam_glance::auth::verify_encoding(&username, &encoding) {
+        Ok(Some(distance)) => {
+            println!("Match for {} (distance {:.4})", username, distance);
+            glib::ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            println!("No match for {}", username);
+            glib::ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Could not verify: {}", e);
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Good frames needed before a headless enrollment is saved
+const ENROLL_FRAMES: usize = 3;
+/// Frames read before headless enrollment gives up
+const ENROLL_MAX_FRAMES: u32 = 150;
+
+fn enroll(args: &[String]) -> glib::ExitCode {
+    let mut camera_id = None;
+    let mut username = current_username();
+    let mut is_ir = false;
+    let mut background = false;
+    
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--camera" => {
+                camera_id = iter.next()
+                    .map(|v| v.strip_prefix("/dev/video").unwrap_or(v))
+                    .and_then(|v| v.parse::<i32>().ok());
+            }
+            "--user" => match iter.next() {
+                Some(name) => username = name.clone(),
+                None => {
+                    eprintln!("--user needs a name");
+                    return glib::ExitCode::FAILURE;
+                }
+            },
+            "--ir" => is_ir = true,
+            "--background" => background = true,
+            other => {
+                eprintln!("Unknown argument: {}\n\n{}", other, USAGE);
+                return glib::ExitCode::FAILURE;
+            }
+        }
+    }
+    
+    let Some(camera_id) = camera_id else {
+        eprintln!("enroll needs --camera N or --camera /dev/videoN");
+        return glib::ExitCode::FAILURE;
+    };
+    if background && !storage::can_write_to_system() {
+        eprintln!("--background writes to /var/lib/glance — run with sudo");
+        return glib::ExitCode::FAILURE;
+    }
+    
+    let processor = match FaceProcessor::new() {
+        Ok(p) if p.can_encode() => p,
+        Ok(_) => {
+            eprintln!("Face models are not installed — run the Glance app once to download them");
+            return glib::ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("Failed to load face processor: {}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    let mut camera = match Camera::new(camera_id) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("/dev/video{}: {}", camera_id, e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    let mut face_data = storage::FaceData::new(&username);
+    let mut captured = 0;
+    for _ in 0..ENROLL_MAX_FRAMES {
+        let frame = match camera.read_frame() {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("/dev/video{}: {}", camera_id, e);
+                return glib::ExitCode::FAILURE;
+            }
+        };
+        
+        let result = processor.detect_and_encode(&frame.rgb_data, frame.width, frame.height);
+        let Some(encoding) = result.encoding else { continue };
+        let model_id = result.model_id.unwrap_or_default();
+        
+        if is_ir {
+            face_data.add_ir_encoding(encoding.clone(), "center", &model_id);
+        } else {
+            face_data.add_rgb_encoding(encoding.clone(), "center", &model_id);
+        }
+        face_data.add_encoding(encoding, "center", &model_id);
+        
+        captured += 1;
+        if captured >= ENROLL_FRAMES {
+            break;
+        }
+    }
+    
+    if captured < ENROLL_FRAMES {
+        eprintln!("No face seen clearly enough on /dev/video{}", camera_id);
+        return glib::ExitCode::FAILURE;
+    }
+    
+    let recognition = GlanceConfig::load().recognition;
+    if recognition.average_encodings {
+        face_data.average_templates(recognition.keep_raw_encodings);
+    }
+    
+    if let Err(e) = storage::save_face_data(&face_data) {
+        eprintln!("{}", e);
+        return glib::ExitCode::FAILURE;
+    }
+    println!("Enrolled {} from /dev/video{} ({} frame(s))", username, camera_id, captured);
+    
+    if !background {
+        return glib::ExitCode::SUCCESS;
+    }
+    match capture_background(&mut camera, &processor, is_ir) {
+        Ok(path) => {
+            println!("Background saved to {}", path.display());
+            let config = GlanceConfig::load();
+            if !config.camera.background_subtraction {
+                println!("Set \"camera\": {{\"background_subtraction\": true}} for PAM to use it");
+            }
+            glib::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Background not captured: {}", e);
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Seconds given to step out of view before the background is captured
+const BACKGROUND_DELAY_SECS: u64 = 5;
+/// Faceless frames in a row the background is taken after, so a face the
+/// detector missed once isn't captured as scenery
+const BACKGROUND_EMPTY_FRAMES: u32 = 5;
+
+/// Wait for the user to leave, then save a frame with no face in it as the
+/// background PAM compares detections against
+fn capture_background(camera: &mut Camera, processor: &FaceProcessor, is_ir: bool) -> Result<std::path::PathBuf, String> {
+    use pam_glance::{background::Background, camera::CameraType};
+    
+    println!("Step out of view: capturing the background in {} seconds", BACKGROUND_DELAY_SECS);
+    std::thread::sleep(std::time::Duration::from_secs(BACKGROUND_DELAY_SECS));
+    
+    let mut empty = 0;
+    for _ in 0..ENROLL_MAX_FRAMES {
+        let frame = camera.read_frame()?;
+        if processor.detect_and_encode(&frame.rgb_data, frame.width, frame.height).face_found {
+            empty = 0;
+            continue;
+        }
+        empty += 1;
+        if empty < BACKGROUND_EMPTY_FRAMES {
+            continue;
+        }
+        
+        let background = Background::from_rgb(&frame.rgb_data, frame.width, frame.height)
+            .map_err(|e| e.to_string())?;
+        let camera_type = if is_ir { CameraType::Infrared } else { CameraType::Rgb };
+        return storage::save_background(&background, camera_type);
+    }
+    Err("a face stayed in view".to_string())
+}
+
+fn doctor(args: &[String]) -> glib::ExitCode {
+    let fix = args.iter().any(|a| a == "--fix");
+    let models_dir = models::get_models_dir();
+    let installed = models::models_exist_in(&models_dir);
+    println!("Models:   {} ({})", models_dir.display(),
+             if installed { "installed" } else { "missing" });
+    
+    let use_gpu = GlanceConfig::load().recognition.use_gpu;
+    let requested = ComputeDevice::select(use_gpu);
+    println!("cuda:     {}", if cfg!(feature = "cuda") {
+        "feature on (CNN detector available)"
+    } else {
+        "feature off (HOG only)"
+    });
+    println!("use_gpu:  {}", use_gpu);
+    
+    // Load the detector to report where it really runs after any fallback
+    let active = if installed {
+        match FaceProcessor::with_models_dir(&models_dir) {
+            Ok(processor) => processor.device(),
+            Err(e) => {
+                eprintln!("Failed to load face processor: {}", e);
+                requested
+            }
+        }
+    } else {
+        requested
+    };
+    println!("Compute:  {}", active.label());
+    if requested == ComputeDevice::Cuda && active == ComputeDevice::Cpu {
+        println!("          (GPU requested but unavailable, fell back to CPU)");
+    }
+    
+    if let Some(disabled) = pam_glance::auto_disable::status() {
+        println!("PAM:      disabled itself ({}), re-enable with `sudo glance enable`",
+                 disabled.missing.label());
+    }
+    
+    report_usernames();
+    let conflict_left = report_storage_conflicts(fix);
+    
+    let cameras = Camera::detect_all_cameras();
+    if cameras.is_empty() {
+        println!("Cameras:  none found");
+    } else {
+        println!("Cameras:");
+        for cam in &cameras {
+            println!("  /dev/video{}  {} ({})", cam.device_id, cam.name,
+                     if cam.is_ir { "IR" } else { "RGB" });
+        }
+    }
+    
+    if installed && !cameras.is_empty() && !conflict_left {
+        glib::ExitCode::SUCCESS
+    } else {
+        glib::ExitCode::FAILURE
+    }
+}
+
+/// Load the models and wake the cameras so the next PAM auth starts warm.
+/// Cameras are released again straight away: the PAM module needs to open
+/// them itself, so holding them here would make auth fail instead.
+fn prewarm(args: &[String]) -> glib::ExitCode {
+    use pam_glance::camera::{detect_cameras_fast_cached, invalidate_probe_cache, SmartCamera};
+    use std::time::{Duration, Instant};
+    
+    let hold = match args.iter().position(|a| a == "--hold") {
+        Some(i) => match args.get(i + 1).and_then(|v| v.parse::<u64>().ok()) {
+            Some(secs) => Duration::from_secs(secs),
+            None => {
+                eprintln!("--hold needs a number of seconds");
+                return glib::ExitCode::FAILURE;
+            }
+        },
+        None => Duration::ZERO,
+    };
+    
+    // Reading the models pulls them into the page cache for the PAM module
+    let start = Instant::now();
+    let processor = match FaceProcessor::new() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Failed to load models: {}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    println!("Models:   loaded in {} ms ({})", start.elapsed().as_millis(), processor.device().label());
+    
+    // A fresh probe, cached where the PAM module looks for it
+    let ttl = pam_glance::auth::AuthConfig::load().probe_cache_ttl;
+    invalidate_probe_cache();
+    let cameras = match detect_cameras_fast_cached(ttl) {
+        Ok(cameras) => cameras,
+        Err(e) => {
+            eprintln!("Camera probe failed: {}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    if cameras.is_empty() {
+        println!("Cameras:  none found");
+        return glib::ExitCode::FAILURE;
+    }
+    
+    // One frame brings a camera out of USB autosuspend
+    for info in &cameras {
+        let start = Instant::now();
+        let woken = SmartCamera::open_direct(info).and_then(|mut cam| cam.read().map(|_| ()));
+        match woken {
+            Ok(()) => println!("  /dev/video{}  woken in {} ms", info.device_id, start.elapsed().as_millis()),
+            Err(e) => println!("  /dev/video{}  {}", info.device_id, e),
+        }
+    }
+    
+    // Keep the probe cache from expiring until the user arrives
+    let deadline = Instant::now() + hold;
+    while Instant::now() < deadline {
+        let step = (ttl / 2).max(Duration::from_secs(1)).min(deadline - Instant::now());
+        std::thread::sleep(step);
+        if let Err(e) = detect_cameras_fast_cached(ttl) {
+            eprintln!("Camera probe failed: {}", e);
+        }
+    }
+    
+    glib::ExitCode::SUCCESS
+}
+
+/// Parts of the day recent attempts are grouped into, by starting hour
+const DAY_PARTS: [(&str, u32); 4] = [("night", 0), ("morning", 6), ("afternoon", 12), ("evening", 18)];
+
+fn stats() -> glib::ExitCode {
+    use chrono::{Local, TimeZone, Timelike};
+    use pam_glance::stats::{stats_paths, Stats};
+    
+    let mut stats = Stats::default();
+    for path in stats_paths() {
+        match Stats::load(&path) {
+            Ok(s) => stats.merge(s),
+            Err(e) => eprintln!("Could not read {}: {}", path.display(), e),
+        }
+    }
+    
+    let total: u64 = stats.counts.values().sum();
+    if total == 0 {
+        println!("No attempts recorded. Set \"stats\": {{\"enabled\": true}} in the config to start counting.");
+        return glib::ExitCode::SUCCESS;
+    }
+    
+    println!("Attempts: {}", total);
+    for (reason, count) in &stats.counts {
+        println!("  {:<18} {:>6}  ({:.0}%)", reason, count, *count as f64 * 100.0 / total as f64);
+    }
+    
+    let Some(first) = stats.events.first() else {
+        return glib::ExitCode::SUCCESS;
+    };
+    let since = Local.timestamp_opt(first.at as i64, 0).single()
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    println!("\nLast {} attempts (since {}) by time of day:", stats.events.len(), since);
+    
+    print!("  {:<18}", "");
+    for (part, _) in DAY_PARTS {
+        print!(" {:>9}", part);
+    }
+    println!();
+    
+    for reason in stats.counts.keys() {
+        let mut per_part = [0u64; DAY_PARTS.len()];
+        for event in stats.events.iter().filter(|e| &e.reason == reason) {
+            let Some(time) = Local.timestamp_opt(event.at as i64, 0).single() else {
+                continue;
+            };
+            let part = DAY_PARTS.iter().rposition(|(_, start)| time.hour() >= *start).unwrap_or(0);
+            per_part[part] += 1;
+        }
+        print!("  {:<18}", reason);
+        for count in per_part {
+            print!(" {:>9}", count);
+        }
+        println!();
+    }
+    
+    glib::ExitCode::SUCCESS
+}
+
+/// Show the name enrollment is stored under next to the names login and
+/// sudo hand to PAM, which is what PAM looks the face up by. Domain
+/// accounts and case differences can make these disagree.
+fn report_usernames() {
+    let enrolled = current_username();
+    println!("User:     GUI enrolls as '{}'", enrolled);
+    
+    let mut mismatched = Vec::new();
+    for var in ["USER", "LOGNAME"] {
+        let Ok(name) = std::env::var(var) else {
+            continue;
+        };
+        let resolved = pam_glance::auth::enrolled_username(&name);
+        let note = if resolved != name { format!(" (alias for '{}')", resolved) } else { String::new() };
+        println!("          ${} = '{}'{}", var, name, note);
+        if resolved != enrolled && !mismatched.contains(&name) {
+            mismatched.push(name);
+        }
+    }
+    
+    for name in mismatched {
+        println!("          ⚠ PAM may look for '{}' and not find the enrollment under '{}'.", name, enrolled);
+        println!("            Map it in /etc/glance/config.json: \"user_aliases\": {{\"{}\": \"{}\"}}", name, enrolled);
+    }
+}
+
+/// Report copies of the user's face data that disagree across storage
+/// locations, reconciling them when `fix` is set. Returns whether a
+/// conflict remains.
+fn report_storage_conflicts(fix: bool) -> bool {
+    let username = current_username();
+    let conflicting = storage::find_conflicting_copies(&username);
+    if conflicting.is_empty() {
+        return false;
+    }
+    
+    println!("Storage:  ⚠ face data for '{}' differs between locations:", username);
+    for copy in &conflicting {
+        if let Some(data) = &copy.data {
+            println!("            {}  updated {}, {} template(s)", copy.path.display(),
+                     data.updated_at, data.all_encodings().len());
+        }
+    }
+    
+    if !fix {
+        println!("            Which one authenticates depends on the loader.");
+        println!("            Run `glance doctor --fix` to keep only the newest copy.");
+        return true;
+    }
+    
+    match storage::migrate_face_data(&username, true) {
+        Ok(Some(report)) => {
+            println!("          Kept {}", report.source.display());
+            for path in &report.removed {
+                println!("            removed {}", path.display());
+            }
+            !storage::find_conflicting_copies(&username).is_empty()
+        }
+        Ok(None) => true,
+        Err(e) => {
+            eprintln!("Could not reconcile face data: {}", e);
+            true
+        }
+    }
+}
+
+/// Seconds the enable-pam self-test gets to recognise the user
+const SELF_TEST_TIMEOUT: u64 = 10;
+
+/// The user whose face the self-test should look for. Under sudo that's
+/// the invoking user, not root.
+fn target_username() -> String {
+    std::env::var("SUDO_USER")
+        .ok()
+        .filter(|u| !u.is_empty())
+        .unwrap_or_else(current_username)
+}
+
+/// Parse `[--service NAME]` plus any extra flags the command accepts
+fn parse_pam_args<'a>(args: &'a [String], flags: &[&str]) -> Result<(String, Vec<&'a str>), String> {
+    let mut service = "sudo".to_string();
+    let mut seen = Vec::new();
+    
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--service" => match iter.next() {
+                Some(name) if !name.contains('/') => service = name.clone(),
+                Some(name) => return Err(format!("Invalid service name: {}", name)),
+                None => return Err("--service needs a name, e.g. sudo".to_string()),
+            },
+            flag if flags.contains(&flag) => seen.push(flag),
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    
+    Ok((service, seen))
+}
+
+/// Run a real face authentication for `username` through pam-glance
+fn self_test(username: &str) -> Result<(), String> {
+    // Under sudo $HOME is usually root's: check what the module will see
+    let mut config = pam_glance::auth::AuthConfig::for_module_user(username);
+    config.timeout = std::time::Duration::from_secs(SELF_TEST_TIMEOUT);
+    
+    println!("Look at the camera — testing face authentication for {}...", username);
+    match pam_glance::auth::authenticate(&config) {
+        pam_glance::auth::AuthResult::Success { confidence, camera_type, .. } => {
+            println!("Recognised via {} camera (confidence {:.2})", camera_type.label(), confidence);
+            Ok(())
+        }
+        pam_glance::auth::AuthResult::Error(e) => Err(format!("Face authentication unavailable: {}", e)),
+        other => Err(other.user_message().unwrap_or("Face authentication failed").to_string()),
+    }
+}
+
+fn enable() -> glib::ExitCode {
+    use pam_glance::auto_disable;
+    
+    let Some(disabled) = auto_disable::status() else {
+        println!("Face auth is not disabled");
+        return glib::ExitCode::SUCCESS;
+    };
+    
+    match auto_disable::enable() {
+        Ok(_) => {
+            println!("Re-enabled face auth (it was disabled: {})", disabled.missing.label());
+            println!("Fix that first, or it will disable itself again — see `glance doctor`");
+            glib::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Could not remove {}: {}", auto_disable::STATE_FILE, e);
+            eprintln!("Re-enabling needs root — run with sudo");
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+fn enable_pam(args: &[String]) -> glib::ExitCode {
+    let (service, flags) = match parse_pam_args(args, &["--test-first"]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    let path = pam_stack::service_path(&service);
+    if !path.exists() {
+        eprintln!("{} does not exist", path.display());
+        return glib::ExitCode::FAILURE;
+    }
+    if pam_stack::is_enabled(&service) {
+        println!("Glance is already enabled for {}", service);
+        return glib::ExitCode::SUCCESS;
+    }
+    
+    if flags.contains(&"--test-first") {
+        let username = target_username();
+        if let Err(e) = self_test(&username) {
+            eprintln!("{}", e);
+            eprintln!("\nNot enabling Glance for {}: face auth has to work before it goes", service);
+            eprintln!("into the PAM stack. Check `glance doctor`, re-enroll in the Glance app");
+            eprintln!("if needed, then try again.");
+            return glib::ExitCode::FAILURE;
+        }
+    } else {
+        println!("Tip: `glance enable-pam --test-first` checks face auth works before enabling it");
+    }
+    
+    match pam_stack::enable(&service) {
+        Ok(backup) => {
+            println!("Added `{}` to {}", pam_stack::PAM_LINE, path.display());
+            println!("Backup: {}", backup.display());
+            println!("Keep this terminal open and check `sudo -k; sudo true` in another one.");
+            glib::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Editing PAM needs root — run with sudo");
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+fn disable_pam(args: &[String]) -> glib::ExitCode {
+    let (service, _) = match parse_pam_args(args, &[]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    match pam_stack::disable(&service) {
+        Ok(Some(backup)) => {
+            println!("Removed Glance from {}", pam_stack::service_path(&service).display());
+            println!("Backup: {}", backup.display());
+            glib::ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            println!("Glance is not enabled for {}", service);
+            glib::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Editing PAM needs root — run with sudo");
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs `glance bench` does without --runs
+const BENCH_RUNS: usize = 20;
+/// Templates matched against per run when none are enrolled
+const BENCH_SYNTHETIC_TEMPLATES: usize = 100;
+
+/// One frame as packed RGB24, from an image file or a camera
+fn bench_frame(image: Option<&str>, camera_id: Option<i32>) -> Result<(Vec<u8>, u32, u32), String> {
+    if let Some(path) = image {
+        use opencv::prelude::*;
+        
+        let bgr = opencv::imgcodecs::imread(path, opencv::imgcodecs::IMREAD_COLOR)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        if bgr.empty() {
+            return Err(format!("{}: not a readable image", path));
+        }
+        let mut rgb = opencv::core::Mat::default();
+        opencv::imgproc::cvt_color(&bgr, &mut rgb, opencv::imgproc::COLOR_BGR2RGB, 0)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let data = rgb.data_bytes().map_err(|e| format!("{}: {}", path, e))?.to_vec();
+        return Ok((data, rgb.cols() as u32, rgb.rows() as u32));
+    }
+    
+    let camera_id = match camera_id {
+        Some(id) => id,
+        None => Camera::detect_cameras().ok_or("No camera found — pass --image PATH")?.device_id,
+    };
+    let mut camera = Camera::new(camera_id).map_err(|e| format!("/dev/video{}: {}", camera_id, e))?;
+    
+    // Let auto-exposure settle so the benchmark frame isn't black
+    let mut frame = camera.read_frame()?;
+    for _ in 0..10 {
+        frame = camera.read_frame()?;
+    }
+    Ok((frame.rgb_data.clone(), frame.width, frame.height))
+}
+
+/// Mean and 95th percentile in milliseconds
+fn timing_summary(samples: &mut [std::time::Duration]) -> (f64, f64) {
+    samples.sort();
+    let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+    let mean = samples.iter().map(|d| ms(*d)).sum::<f64>() / samples.len() as f64;
+    let p95 = ms(samples[((samples.len() * 95).div_ceil(100)).saturating_sub(1)]);
+    (mean, p95)
+}
+
+fn bench(args: &[String]) -> glib::ExitCode {
+    let mut runs = BENCH_RUNS;
+    let mut camera_id = None;
+    let mut image = None;
+    
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--runs" => match iter.next().and_then(|v| v.parse::<usize>().ok()).filter(|&n| n > 0) {
+                Some(n) => runs = n,
+                None => {
+                    eprintln!("--runs needs a positive number");
+                    return glib::ExitCode::FAILURE;
+                }
+            },
+            "--camera" => {
+                camera_id = iter.next()
+                    .map(|v| v.strip_prefix("/dev/video").unwrap_or(v))
+                    .and_then(|v| v.parse::<i32>().ok());
+            }
+            "--image" => image = iter.next().cloned(),
+            other => {
+                eprintln!("Unknown argument: {}\n\n{}", other, USAGE);
+                return glib::ExitCode::FAILURE;
+            }
+        }
+    }
+    
+    let processor = match FaceProcessor::new() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Failed to load face processor: {}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    let (rgb, width, height) = match bench_frame(image.as_deref(), camera_id) {
+        Ok(frame) => frame,
+        Err(e) => {
+            eprintln!("{}", e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    
+    println!("Compute:  {}", processor.device().label());
+    println!("Frame:    {}x{}, {} run(s)", width, height, runs);
+    
+    let mut detect = Vec::with_capacity(runs);
+    let mut encode = Vec::with_capacity(runs);
+    let mut encoding = None;
+    for _ in 0..runs {
+        let Some(timings) = processor.time_stages(&rgb, width, height) else {
+            eprintln!("Frame could not be processed");
+            return glib::ExitCode::FAILURE;
+        };
+        detect.push(timings.detect);
+        encode.extend(timings.encode);
+        if timings.encoding.is_some() {
+            encoding = timings.encoding;
+        }
+    }
+    
+    let (mean, p95) = timing_summary(&mut detect);
+    println!("Detect:   mean {:7.2} ms   p95 {:7.2} ms", mean, p95);
+    
+    let Some(encoding) = encoding else {
+        println!("Encode:   skipped (no face in the frame, or models missing)");
+        return glib::ExitCode::SUCCESS;
+    };
+    let (mean, p95) = timing_summary(&mut encode);
+    println!("Encode:   mean {:7.2} ms   p95 {:7.2} ms", mean, p95);
+    
+    // Match against what PAM would: the enrolled templates, or synthetic ones
+    let mut templates: Vec<Vec<f32>> = storage::load_face_data(&current_username()).ok().flatten()
+        .map(|data| data.all_encodings().into_iter().map(|e| e.encoding.clone()).collect())
+        .unwrap_or_default();
+    if templates.is_empty() {
+        templates = (0..BENCH_SYNTHETIC_TEMPLATES)
+            .map(|i| encoding.iter().map(|x| x + i as f32 * 1e-3).collect())
+            .collect();
+    }
+    
+    let mut matching = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let started = std::time::Instant::now();
+        let best = templates.iter()
+            .map(|t| storage::encoding_distance(&encoding, t))
+            .fold(f64::MAX, f64::min);
+        std::hint::black_box(best);
+        matching.push(started.elapsed());
+    }
+    let (mean, p95) = timing_summary(&mut matching);
+    println!("Match:    mean {:7.2} ms   p95 {:7.2} ms   ({} templates)", mean, p95, templates.len());
+    
+    glib::ExitCode::SUCCESS
+}
@@ -0,0 +1,576 @@
+//! Glance configuration shared with the PAM module
+//!
+//! Reads and writes the same `config.json` that pam-glance loads. Only the
+//! keys the GUI cares about are typed here; everything else is carried
+//! through untouched so saving from the GUI never drops PAM settings.
+//! The user file is layered over `/etc/glance/config.json` and only holds
+//! what differs from it.
+
+use log::warn;
+use pam_glance::camera::DetectionRoi;
+use pam_glance::config::{merge_json, remove_system_only_keys};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GlanceConfig {
+    #[serde(default)]
+    pub camera: CameraConfig,
+    #[serde(default)]
+    pub recognition: RecognitionConfig,
+    #[serde(default)]
+    pub ir_emitter: IrEmitterConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// How the GUI treats face data left by the old `facerec` tool
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Copy a user's `facerec` face data to the Glance location when no
+    /// Glance data exists. Off: it is left alone until `glance migrate`
+    #[serde(default)]
+    pub migrate_legacy: bool,
+    /// With `migrate_legacy`, delete the `facerec` file once it's copied
+    #[serde(default)]
+    pub remove_migrated_legacy: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IrEmitterConfig {
+    /// Emitter profile (file name in `/etc/linux-enable-ir-emitter`) PAM
+    /// passes to the tool per device; unlisted devices use the tool's choice
+    #[serde(default)]
+    pub profiles: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraConfig {
+    /// Frame rate the enrollment preview captures at
+    #[serde(default = "default_capture_fps")]
+    pub capture_fps: u32,
+    /// Start the IR emitter once when IR capture begins and keep it on until
+    /// it ends, for steady lighting instead of per-open flicker
+    #[serde(default)]
+    pub ir_emitter_during_preview: bool,
+    /// Part of the frame faces are looked for in, shared with PAM
+    #[serde(default)]
+    pub detection_roi: DetectionRoi,
+    /// Mean brightness (0 – 255) above which frames are too washed out to
+    /// enroll from. Shared with PAM, which skips them at login. 0 = never
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: f64,
+    /// Longest frame side the enrollment camera delivers; larger frames are
+    /// scaled down first. Shared with PAM. 0 = never
+    #[serde(default = "default_max_frame_dimension")]
+    pub max_frame_dimension: u32,
+    /// Show a heavily pixelated camera picture while enrolling, enough to
+    /// aim by but not to make out a face from across the room
+    #[serde(default)]
+    pub privacy_preview: bool,
+    /// PAM ignores faces over the background `glance enroll --background`
+    /// captured. Shared with PAM
+    #[serde(default)]
+    pub background_subtraction: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_capture_fps() -> u32 { 20 }
+fn default_max_brightness() -> f64 { pam_glance::camera::DEFAULT_MAX_BRIGHTNESS }
+fn default_max_frame_dimension() -> u32 { pam_glance::camera::DEFAULT_MAX_FRAME_DIMENSION }
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            capture_fps: default_capture_fps(),
+            ir_emitter_during_preview: false,
+            detection_roi: DetectionRoi::default(),
+            max_brightness: default_max_brightness(),
+            max_frame_dimension: default_max_frame_dimension(),
+            privacy_preview: false,
+            background_subtraction: false,
+            extra: Map::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecognitionConfig {
+    /// Run detection on the GPU when dlib was built with CUDA
+    #[serde(default = "default_true")]
+    pub use_gpu: bool,
+    /// Retry detection on rotated frames to catch tilted heads (slower)
+    #[serde(default)]
+    pub rotation_search: bool,
+    /// Preset last chosen for the tolerances below
+    #[serde(default)]
+    pub sensitivity: Sensitivity,
+    #[serde(default = "default_ir_tolerance")]
+    pub ir_tolerance: f64,
+    #[serde(default = "default_rgb_tolerance")]
+    pub rgb_tolerance: f64,
+    /// Preset last chosen in Preferences; the individual values below may
+    /// since have been hand-edited
+    #[serde(default)]
+    pub security_level: SecurityLevel,
+    #[serde(default)]
+    pub liveness: bool,
+    #[serde(default)]
+    pub require_blink: bool,
+    /// Required distance gap between the best and second-best user
+    #[serde(default)]
+    pub match_margin: f64,
+    #[serde(default)]
+    pub min_confidence: f64,
+    /// Consecutive matching frames needed before authenticating
+    #[serde(default = "default_required_matches")]
+    pub required_matches: u32,
+    /// Allow enrolling a face that already matches another user. When
+    /// false the GUI refuses to save; when true it only warns.
+    #[serde(default = "default_true")]
+    pub allow_duplicate_faces: bool,
+    /// Also match templates captured while wearing a mask. Less secure:
+    /// half the face is hidden, so lookalikes match more easily.
+    #[serde(default)]
+    pub mask_friendly: bool,
+    /// Minimum distance a new template must keep from the existing ones of
+    /// the same camera; closer captures add nothing and are refused. 0 = off
+    #[serde(default = "default_min_template_spread")]
+    pub min_template_spread: f64,
+    /// Frame quality (0.0 – 1.0, see `quality::frame_quality`) a frame
+    /// needs to count toward enrollment. 0 = any frame with an encoding
+    #[serde(default = "default_min_enroll_quality")]
+    pub min_enroll_quality: f64,
+    /// Guided enrollment also captures a slight smile after the neutral face
+    /// on each camera, stored as its own "smile" template
+    #[serde(default)]
+    pub capture_expressions: bool,
+    /// Average the frames an enrollment captures into one template per
+    /// pose and camera type, see `FaceData::average_templates`
+    #[serde(default)]
+    pub average_encodings: bool,
+    /// With `average_encodings`, keep the individual frames next to the mean
+    #[serde(default)]
+    pub keep_raw_encodings: bool,
+    /// Save a small face picture with the enrollment, see `thumbnail.rs`
+    #[serde(default)]
+    pub store_thumbnail: bool,
+    /// Custom models directory, see `models::get_models_dir`. Empty = default
+    #[serde(default)]
+    pub models_dir: String,
+    /// Subdirectory of the models directory to use, e.g. `dlib-v1`, see
+    /// `models::get_models_dir`. Empty = the flat layout
+    #[serde(default)]
+    pub model_set: String,
+    /// Speed/accuracy preset last chosen for the three values below
+    #[serde(default)]
+    pub profile: MatchingProfile,
+    /// Encoding passes per face with small distortions, averaged. Shared
+    /// with PAM; the GUI uses it for enrollment too
+    #[serde(default)]
+    pub num_jitters: u32,
+    /// Factor PAM scales frames by before detection
+    #[serde(default = "default_detection_scale")]
+    pub detection_scale: f64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_true() -> bool { true }
+fn default_required_matches() -> u32 { 1 }
+fn default_ir_tolerance() -> f64 { 0.45 }
+fn default_rgb_tolerance() -> f64 { 0.50 }
+fn default_min_template_spread() -> f64 { 0.08 }
+fn default_min_enroll_quality() -> f64 { 0.3 }
+fn default_detection_scale() -> f64 { 1.0 }
+
+impl Default for RecognitionConfig {
+    fn default() -> Self {
+        Self {
+            use_gpu: default_true(),
+            rotation_search: false,
+            sensitivity: Sensitivity::default(),
+            ir_tolerance: default_ir_tolerance(),
+            rgb_tolerance: default_rgb_tolerance(),
+            security_level: SecurityLevel::default(),
+            liveness: false,
+            require_blink: false,
+            match_margin: 0.0,
+            min_confidence: 0.0,
+            required_matches: default_required_matches(),
+            allow_duplicate_faces: default_true(),
+            mask_friendly: false,
+            min_template_spread: default_min_template_spread(),
+            min_enroll_quality: default_min_enroll_quality(),
+            capture_expressions: false,
+            average_encodings: false,
+            keep_raw_encodings: false,
+            store_thumbnail: false,
+            models_dir: String::new(),
+            model_set: String::new(),
+            profile: MatchingProfile::default(),
+            num_jitters: 0,
+            detection_scale: default_detection_scale(),
+            extra: Map::new(),
+        }
+    }
+}
+
+/// Recognition sensitivity presets. Each maps to an IR/RGB tolerance pair;
+/// `Advanced` leaves the raw tolerances to the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sensitivity {
+    Strict,
+    /// Matches the PAM module's built-in tolerances
+    #[default]
+    Balanced,
+    Lenient,
+    Advanced,
+}
+
+impl Sensitivity {
+    pub const ALL: [Sensitivity; 4] = [
+        Sensitivity::Strict,
+        Sensitivity::Balanced,
+        Sensitivity::Lenient,
+        Sensitivity::Advanced,
+    ];
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            Sensitivity::Strict => "Strict",
+            Sensitivity::Balanced => "Balanced",
+            Sensitivity::Lenient => "Lenient",
+            Sensitivity::Advanced => "Advanced",
+        }
+    }
+    
+    /// `(ir_tolerance, rgb_tolerance)`, or `None` for `Advanced`
+    pub fn tolerances(&self) -> Option<(f64, f64)> {
+        match self {
+            Sensitivity::Strict => Some((0.40, 0.45)),
+            Sensitivity::Balanced => Some((default_ir_tolerance(), default_rgb_tolerance())),
+            Sensitivity::Lenient => Some((0.50, 0.55)),
+            Sensitivity::Advanced => None,
+        }
+    }
+    
+    pub fn apply(&self, recognition: &mut RecognitionConfig) {
+        recognition.sensitivity = *self;
+        if let Some((ir, rgb)) = self.tolerances() {
+            recognition.ir_tolerance = ir;
+            recognition.rgb_tolerance = rgb;
+        }
+    }
+}
+
+/// Speed/accuracy presets. Each writes the detector choice, encoding
+/// jitters and detection scale; `Custom` leaves them as configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchingProfile {
+    Fast,
+    Accurate,
+    /// The individual values, as set in the config file
+    #[default]
+    Custom,
+}
+
+impl MatchingProfile {
+    pub const ALL: [MatchingProfile; 3] = [
+        MatchingProfile::Fast,
+        MatchingProfile::Accurate,
+        MatchingProfile::Custom,
+    ];
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            MatchingProfile::Fast => "Fast",
+            MatchingProfile::Accurate => "Accurate",
+            MatchingProfile::Custom => "Custom",
+        }
+    }
+    
+    pub fn description(&self) -> &'static str {
+        match self {
+            MatchingProfile::Fast => "CPU detection on half-size frames, single-pass encoding",
+            MatchingProfile::Accurate => "GPU detection when available, full frames, averaged encodings",
+            MatchingProfile::Custom => "Detector, jitters and scale as set in the config file",
+        }
+    }
+    
+    /// Write this profile's values into the recognition section
+    pub fn apply(&self, recognition: &mut RecognitionConfig) {
+        recognition.profile = *self;
+        let (use_gpu, num_jitters, detection_scale) = match self {
+            MatchingProfile::Fast => (false, 0, 0.5),
+            MatchingProfile::Accurate => (true, 3, 1.0),
+            MatchingProfile::Custom => return,
+        };
+        recognition.use_gpu = use_gpu;
+        recognition.num_jitters = num_jitters;
+        recognition.detection_scale = detection_scale;
+    }
+}
+
+/// Security posture presets offered in Preferences. Each one sets a
+/// coherent bundle of anti-spoofing and matching values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityLevel {
+    /// Matches the PAM module's built-in defaults
+    #[default]
+    Convenience,
+    Balanced,
+    Strict,
+}
+
+impl SecurityLevel {
+    pub const ALL: [SecurityLevel; 3] = [
+        SecurityLevel::Convenience,
+        SecurityLevel::Balanced,
+        SecurityLevel::Strict,
+    ];
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            SecurityLevel::Convenience => "Convenience",
+            SecurityLevel::Balanced => "Balanced",
+            SecurityLevel::Strict => "Strict",
+        }
+    }
+    
+    pub fn description(&self) -> &'static str {
+        match self {
+            SecurityLevel::Convenience => "Fastest unlock, no liveness checks",
+            SecurityLevel::Balanced => "Liveness check and a short run of matching frames",
+            SecurityLevel::Strict => "Requires a blink, a clear match and more matching frames",
+        }
+    }
+    
+    /// Write this level's values into the recognition section
+    pub fn apply(&self, recognition: &mut RecognitionConfig) {
+        let (liveness, require_blink, match_margin, min_confidence, required_matches) = match self {
+            SecurityLevel::Convenience => (false, false, 0.0, 0.0, 1),
+            SecurityLevel::Balanced => (true, false, 0.05, 0.50, 2),
+            SecurityLevel::Strict => (true, true, 0.10, 0.60, 3),
+        };
+        
+        recognition.security_level = *self;
+        recognition.liveness = liveness;
+        recognition.require_blink = require_blink;
+        recognition.match_margin = match_margin;
+        recognition.min_confidence = min_confidence;
+        recognition.required_matches = required_matches;
+    }
+}
+
+/// Per-user config file (the one the GUI writes), or the file named by
+/// `GLANCE_CONFIG` so the GUI and the PAM test tools can share one
+pub fn user_config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(pam_glance::auth::CONFIG_ENV).filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+    
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("glance")
+        .join("config.json")
+}
+
+fn system_config_path() -> PathBuf {
+    PathBuf::from("/etc/glance/config.json")
+}
+
+/// A config file as JSON; `None` if it doesn't exist or can't be used
+fn read_layer(path: &PathBuf) -> Option<Value> {
+    if !path.exists() {
+        return None;
+    }
+    match fs::read_to_string(path).map(|c| serde_json::from_str::<Value>(&c)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            warn!("Ignoring invalid config {:?}: {}", path, e);
+            None
+        }
+        Err(e) => {
+            warn!("Could not read config {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// The user config as the PAM module reads it: without the keys only the
+/// system config may set
+fn user_layer() -> Option<Value> {
+    let mut user = read_layer(&user_config_path())?;
+    remove_system_only_keys(&mut user);
+    Some(user)
+}
+
+/// Defaults with the system config merged on top: what the user config is
+/// layered onto, and what `save` leaves out of it
+fn system_layer() -> Value {
+    let mut base = serde_json::to_value(GlanceConfig::default()).unwrap_or(Value::Null);
+    if let Some(system) = read_layer(&system_config_path()) {
+        merge_json(&mut base, system);
+    }
+    base
+}
+
+/// The parts of `value` that differ from `base`, or `None` if nothing does
+fn diff_json(value: Value, base: &Value) -> Option<Value> {
+    match (value, base) {
+        (Value::Object(map), Value::Object(base)) => {
+            let diff: Map<String, Value> = map.into_iter()
+                .filter_map(|(key, value)| match base.get(&key) {
+                    Some(base_value) => diff_json(value, base_value).map(|d| (key, d)),
+                    None => Some((key, value)),
+                })
+                .collect();
+            (!diff.is_empty()).then_some(Value::Object(diff))
+        }
+        (value, base) => (value != *base).then_some(value),
+    }
+}
+
+/// Keys of `value` that `known` has no counterpart for, as dotted paths.
+/// Objects that are empty in `known` (like `user_aliases`) take any key
+fn unknown_keys(value: &Value, known: &Value, prefix: &str, found: &mut Vec<String>) {
+    let (Value::Object(map), Value::Object(known)) = (value, known) else {
+        return;
+    };
+    if known.is_empty() {
+        return;
+    }
+    for (key, child) in map {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match known.get(key) {
+            Some(known_child) => unknown_keys(child, known_child, &path, found),
+            None => found.push(path),
+        }
+    }
+}
+
+/// The effective config, with every setting the GUI and the PAM module
+/// know about, as pretty JSON
+pub fn export() -> Result<String, String> {
+    let mut effective = pam_glance::auth::default_config();
+    merge_json(&mut effective, system_layer());
+    if let Some(user) = user_layer() {
+        merge_json(&mut effective, user);
+    }
+    serde_json::to_string_pretty(&effective).map_err(|e| format!("Failed to serialize config: {}", e))
+}
+
+/// Check a config from elsewhere and save it as the user config. Nothing is
+/// written unless every key is known and every value usable; the error
+/// lists all problems found
+pub fn import(content: &str) -> Result<(), String> {
+    let mut imported: Value = serde_json::from_str(content).map_err(|e| format!("Not valid JSON: {}", e))?;
+    if !imported.is_object() {
+        return Err("Expected a JSON object".to_string());
+    }
+    
+    let mut known = pam_glance::auth::default_config();
+    merge_json(&mut known, serde_json::to_value(GlanceConfig::default()).unwrap_or(Value::Null));
+    let current_version = known.get("version").and_then(Value::as_u64).unwrap_or(1);
+    
+    let mut problems = Vec::new();
+    let mut unknown = Vec::new();
+    unknown_keys(&imported, &known, "", &mut unknown);
+    problems.extend(unknown.into_iter().map(|key| format!("{}: unknown setting", key)));
+    
+    let version = imported.get("version").and_then(Value::as_u64);
+    match version {
+        Some(version) if version > current_version => problems.push(format!(
+            "version = {}: written by a newer Glance (this one understands {})", version, current_version)),
+        _ => {
+            imported["version"] = Value::from(current_version);
+        }
+    }
+    
+    // Checked as the PAM module will see it, on top of the system config.
+    // An export carries the other machine's system-only keys, which the
+    // module would ignore in a user config anyway
+    remove_system_only_keys(&mut imported);
+    let mut effective = system_layer();
+    merge_json(&mut effective, imported);
+    problems.extend(pam_glance::auth::validate_config(&effective));
+    
+    let config = match serde_json::from_value::<GlanceConfig>(effective) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    };
+    if let Some(ref config) = config {
+        let r = &config.recognition;
+        if !(0.0..=1.0).contains(&r.min_enroll_quality) {
+            problems.push(format!("recognition.min_enroll_quality = {}: expected 0 to 1", r.min_enroll_quality));
+        }
+        if r.min_template_spread < 0.0 {
+            problems.push(format!("recognition.min_template_spread = {}: expected 0 or more", r.min_template_spread));
+        }
+        if !(1..=60).contains(&config.camera.capture_fps) {
+            problems.push(format!("camera.capture_fps = {}: expected 1 to 60", config.camera.capture_fps));
+        }
+    }
+    
+    problems.dedup();
+    match config {
+        Some(config) if problems.is_empty() => config.save(),
+        _ => Err(problems.join("\n")),
+    }
+}
+
+impl GlanceConfig {
+    /// Defaults, then the system config, then the user config merged on top
+    /// key by key, like the PAM module does
+    pub fn load() -> Self {
+        let mut merged = system_layer();
+        if let Some(user) = user_layer() {
+            merge_json(&mut merged, user);
+        }
+        
+        match serde_json::from_value(merged) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Ignoring invalid config: {}", e);
+                Self::default()
+            }
+        }
+    }
+    
+    /// Write to the per-user config file
+    pub fn save(&self) -> Result<(), String> {
+        let path = user_config_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create config directory {:?}: {}", parent, e))?;
+        }
+        
+        // Only what differs from the system layer, so later changes to the
+        // system config still reach settings the user never touched
+        let value = serde_json::to_value(self)
+            .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        let overrides = diff_json(value, &system_layer()).unwrap_or_else(|| Value::Object(Map::new()));
+        let content = serde_json::to_string_pretty(&overrides)
+            .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write config to {:?}: {}", path, e))
+    }
+}
@@ -1,38 +1,63 @@
-use dlib_face_recognition::{
-    FaceDetector, FaceDetectorTrait,
-    LandmarkPredictor, LandmarkPredictorTrait,
-    FaceEncoderNetwork, FaceEncoderTrait,
-    ImageMatrix,
-};
 use log::{warn, info};
+use opencv::core::{Mat, Vec3b};
+use opencv::prelude::*;
+use pam_glance::camera::DetectionRoi;
+use pam_glance::embedder::{DlibEmbedder, FaceEmbedder, FaceRect};
+use pam_glance::face::model_id;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
+use crate::camera::wipe_mat;
 use crate::models;
 
+/// Which detector runs, shared with pam-glance: `Cuda` is dlib's CNN
+/// detector, only chosen in builds with the `cuda` feature, and on the GPU
+/// only if the system dlib was compiled with CUDA.
+pub use pam_glance::embedder::ComputeDevice;
+
 const FACE_TOLERANCE: f64 = 0.45;
 
 #[derive(Debug, Clone)]
 pub struct FaceDetectionResult {
     pub face_found: bool,
     pub face_rect: Option<(i32, i32, i32, i32)>,
-    pub encoding: Option<Vec<f64>>,
+    pub encoding: Option<Vec<f32>>,
+    /// The embedder's landmark points (dlib's 68), in the same frame pixels
+    /// as `face_rect`
     pub landmarks: Option<Vec<(i32, i32)>>,
     pub confidence: f64,
+    /// Recognition model that produced `encoding`, see `model_id`
+    pub model_id: Option<String>,
+}
+
+/// Time spent in each stage of one detect-and-encode, see `glance bench`
+#[derive(Debug, Clone)]
+pub struct StageTimings {
+    pub detect: Duration,
+    /// Landmarks plus encoding; `None` if no face was found
+    pub encode: Option<Duration>,
+    pub encoding: Option<Vec<f32>>,
 }
 
+/// Detection and encoding for enrollment and the preview. Faces are found
+/// and encoded by a pam-glance `FaceEmbedder`, the same backend PAM
+/// matches with
 pub struct FaceProcessor {
-    detector: FaceDetector,
-    predictor: Option<LandmarkPredictor>,
-    encoder: Option<FaceEncoderNetwork>,
+    embedder: Box<dyn FaceEmbedder + Send>,
+    device: ComputeDevice,
+    model_id: Option<String>,
     tolerance: f64,
+    rotation_search: bool,
+    roi: DetectionRoi,
+    num_jitters: u32,
 }
 
 impl std::fmt::Debug for FaceProcessor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FaceProcessor")
-            .field("has_predictor", &self.predictor.is_some())
-            .field("has_encoder", &self.encoder.is_some())
+            .field("can_encode", &self.can_encode())
             .field("tolerance", &self.tolerance)
+            .field("device", &self.device)
             .finish()
     }
 }
@@ -45,116 +70,205 @@ impl FaceProcessor {
     pub fn with_models_dir(models_dir: &Path) -> Result<Self, String> {
         info!("Initializing face processor with models from {:?}", models_dir);
         
-        let detector = FaceDetector::new();
+        let config = crate::config::GlanceConfig::load();
+        let recognition = config.recognition;
         
-        let shape_predictor_path = models_dir.join("shape_predictor_68_face_landmarks.dat");
-        let predictor = if shape_predictor_path.exists() {
-            match LandmarkPredictor::open(&shape_predictor_path) {
-                Ok(p) => {
-                    info!("Loaded shape predictor from {:?}", shape_predictor_path);
-                    Some(p)
-                }
-                Err(e) => {
-                    warn!("Failed to load shape predictor: {}", e);
-                    None
-                }
+        // A custom models directory may hold a different encoder on purpose
+        let custom = !recognition.models_dir.is_empty() && Path::new(&recognition.models_dir) == models_dir;
+        for model in [&models::SHAPE_PREDICTOR, &models::FACE_RECOGNITION] {
+            if !models_dir.join(model.name).exists() {
+                continue;
             }
-        } else {
-            warn!("Shape predictor not found at {:?}", shape_predictor_path);
-            None
-        };
+            match models::check_model_file(model, models_dir) {
+                Err(e) if custom => warn!("{}", e),
+                Err(e) => return Err(e),
+                Ok(()) => {}
+            }
+        }
         
-        let face_rec_path = models_dir.join("dlib_face_recognition_resnet_model_v1.dat");
-        let encoder = if face_rec_path.exists() && predictor.is_some() {
-            match FaceEncoderNetwork::open(&face_rec_path) {
-                Ok(e) => {
-                    info!("Loaded face encoder from {:?}", face_rec_path);
-                    Some(e)
-                }
-                Err(e) => {
-                    warn!("Failed to load face encoder: {}", e);
-                    None
-                }
+        // Detection needs no model files, so the preview can show a face
+        // before the others are downloaded
+        let requested = ComputeDevice::select(recognition.use_gpu);
+        let embedder = match DlibEmbedder::open(models_dir, requested) {
+            Ok(embedder) => {
+                info!("Loaded face models from {:?}", models_dir);
+                embedder
             }
-        } else {
-            if !face_rec_path.exists() {
-                warn!("Face recognition model not found at {:?}", face_rec_path);
+            Err(e) => {
+                warn!("Face encoding unavailable: {}", e);
+                DlibEmbedder::detector_only(models_dir, requested)
             }
-            None
         };
+        let device = embedder.device();
+        info!("Face detection: {}", device.label());
         
-        Ok(Self {
-            detector,
-            predictor,
-            encoder,
+        let model_id = if embedder.can_encode() { model_id(models_dir) } else { None };
+        
+        let mut processor = Self::with_embedder(Box::new(embedder));
+        processor.device = device;
+        processor.model_id = model_id;
+        processor.rotation_search = recognition.rotation_search;
+        processor.roi = config.camera.detection_roi;
+        processor.num_jitters = recognition.num_jitters;
+        Ok(processor)
+    }
+    
+    /// Detect and encode with `embedder` instead of dlib's models, e.g. a
+    /// `CannedEmbedder` in tests. No rotation search, ROI or jitter
+    pub fn with_embedder(embedder: Box<dyn FaceEmbedder + Send>) -> Self {
+        Self {
+            embedder,
+            device: ComputeDevice::Cpu,
+            model_id: None,
             tolerance: FACE_TOLERANCE,
-        })
+            rotation_search: false,
+            roi: DetectionRoi::default(),
+            num_jitters: 0,
+        }
     }
     
     pub fn can_encode(&self) -> bool {
-        self.predictor.is_some() && self.encoder.is_some()
+        self.embedder.can_encode()
     }
     
-    pub fn detect_and_encode(&self, rgb_data: &[u8], width: u32, height: u32) -> FaceDetectionResult {
-        let image = match self.rgb_to_image_matrix(rgb_data, width, height) {
-            Some(img) => img,
-            None => return FaceDetectionResult::empty(),
+    /// The device detection actually runs on after any fallback
+    pub fn device(&self) -> ComputeDevice {
+        self.device
+    }
+    
+    /// Detect and encode the first face like `detect_and_encode`, timing
+    /// each stage. Skips the rotation search so the numbers are comparable.
+    pub fn time_stages(&self, rgb_data: &[u8], width: u32, height: u32) -> Option<StageTimings> {
+        let mut frame = self.frame_from_rgb(rgb_data, width, height)?;
+        
+        let started = Instant::now();
+        let face_rects = self.detect(&frame);
+        let detect = started.elapsed();
+        
+        let Some(&rect) = face_rects.first().filter(|_| self.can_encode()) else {
+            wipe_mat(&mut frame);
+            return Some(StageTimings { detect, encode: None, encoding: None });
         };
         
-        let face_rects = self.detector.face_locations(&image);
+        let started = Instant::now();
+        let encoding = self.encode(&frame, rect);
+        let encode = started.elapsed();
+        wipe_mat(&mut frame);
         
-        if face_rects.is_empty() {
+        Some(StageTimings { detect, encode: Some(encode), encoding })
+    }
+    
+    pub fn detect_and_encode(&self, rgb_data: &[u8], width: u32, height: u32) -> FaceDetectionResult {
+        let Some(mut frame) = self.frame_from_rgb(rgb_data, width, height) else {
             return FaceDetectionResult::empty();
+        };
+        
+        let mut face_rects = self.detect(&frame);
+        let mut angle = 0.0;
+        
+        // Tilted heads: retry on rotated copies, then encode the upright face
+        if face_rects.is_empty() && self.rotation_search {
+            for candidate in ROTATION_ANGLES {
+                let Some(mut rotated) = rotate_frame(&frame, candidate) else {
+                    continue;
+                };
+                let rects = self.detect(&rotated);
+                if rects.is_empty() {
+                    wipe_mat(&mut rotated);
+                    continue;
+                }
+                wipe_mat(&mut frame);
+                frame = rotated;
+                face_rects = rects;
+                angle = candidate;
+                break;
+            }
         }
         
-        let rect = &face_rects[0];
+        let result = self.describe_first(&frame, &face_rects, angle, width, height);
+        wipe_mat(&mut frame);
+        result
+    }
+    
+    /// Result for the first of `face_rects`, found in `frame` rotated by
+    /// `angle` from the caller's `width`×`height` frame
+    fn describe_first(&self, frame: &Mat, face_rects: &[FaceRect], angle: f64, width: u32, height: u32) -> FaceDetectionResult {
+        let Some(&rect) = face_rects.first() else {
+            return FaceDetectionResult::empty();
+        };
+        
+        let (left, top, right, bottom) = unrotate_rect(
+            (rect.0 as f64, rect.1 as f64, rect.2 as f64, rect.3 as f64),
+            angle, width, height,
+        );
         let face_rect = Some((
-            rect.left as i32,
-            rect.top as i32,
-            (rect.right - rect.left) as i32,
-            (rect.bottom - rect.top) as i32,
+            left as i32,
+            top as i32,
+            (right - left) as i32,
+            (bottom - top) as i32,
         ));
         
-        let (landmarks, encoding) = if let (Some(ref predictor), Some(ref encoder)) = (&self.predictor, &self.encoder) {
-            let lm = predictor.face_landmarks(&image, rect);
-            
-            let encodings = encoder.get_face_encodings(&image, &[lm.clone()], 0);
-            
-            let enc = if !encodings.is_empty() {
-                let enc_slice = encodings[0].as_ref();
-                let enc_vec: Vec<f64> = enc_slice.iter().map(|&x| x as f64).collect();
-                Some(enc_vec)
-            } else {
-                None
-            };
-            
-            let pts: Vec<(i32, i32)> = Vec::new();
-            
-            (Some(pts), enc)
+        let (landmarks, encoding) = if self.can_encode() {
+            // Found in a rotated copy: map the points back like the rect
+            let landmarks = self.embedder.landmarks(frame, rect).ok().map(|points| {
+                points.into_iter()
+                    .map(|(x, y)| unrotate_point(x, y, angle, width, height))
+                    .collect()
+            });
+            (landmarks, self.encode(frame, rect))
         } else {
             (None, None)
         };
         
+        let model_id = encoding.as_ref().and_then(|_| self.model_id.clone());
+        
         FaceDetectionResult {
             face_found: true,
             face_rect,
             encoding,
+            model_id,
             landmarks,
             confidence: 1.0,
         }
     }
     
-    fn rgb_to_image_matrix(&self, rgb_data: &[u8], width: u32, height: u32) -> Option<ImageMatrix> {
+    /// Faces in `frame`; a backend error counts as none
+    fn detect(&self, frame: &Mat) -> Vec<FaceRect> {
+        self.embedder.detect(frame).unwrap_or_else(|e| {
+            warn!("Face detection failed: {}", e);
+            Vec::new()
+        })
+    }
+    
+    /// Encoding of the face at `rect`, `None` if none came out
+    fn encode(&self, frame: &Mat, rect: FaceRect) -> Option<Vec<f32>> {
+        self.embedder.encode(frame, rect, self.num_jitters).unwrap_or_else(|e| {
+            warn!("Face encoding failed: {}", e);
+            None
+        })
+    }
+    
+    /// `rgb_data` as the BGR frame embedders read, blacked out outside the
+    /// detection ROI
+    fn frame_from_rgb(&self, rgb_data: &[u8], width: u32, height: u32) -> Option<Mat> {
+        use opencv::imgproc;
+        
         if rgb_data.len() != (width * height * 3) as usize {
             warn!("Invalid image data size: {} (expected {})", 
                   rgb_data.len(), width * height * 3);
             return None;
         }
         
-        let image = unsafe {
-            ImageMatrix::new(width as usize, height as usize, rgb_data.as_ptr())
-        };
-        Some(image)
+        let rgb = Mat::new_rows_cols_with_bytes::<Vec3b>(height as i32, width as i32, rgb_data).ok()?;
+        let mut frame = Mat::default();
+        imgproc::cvt_color(&*rgb, &mut frame, imgproc::COLOR_RGB2BGR, 0).ok()?;
+        if self.roi.is_full() {
+            return Some(frame);
+        }
+        
+        let masked = self.roi.mask(&frame).ok();
+        wipe_mat(&mut frame);
+        masked
     }
 }
 
@@ -166,6 +280,7 @@ impl FaceDetectionResult {
             encoding: None,
             landmarks: None,
             confidence: 0.0,
+            model_id: None,
         }
     }
     
@@ -180,6 +295,64 @@ impl Default for FaceProcessor {
     }
 }
 
+/// Tilts tried, in order, when `rotation_search` is on and the upright
+/// frame has no face
+const ROTATION_ANGLES: [f64; 4] = [15.0, -15.0, 30.0, -30.0];
+
+/// Rotate `(x, y)` about `(cx, cy)` using OpenCV's getRotationMatrix2D
+/// convention (positive angle = counter-clockwise on screen)
+fn rotate_point(x: f64, y: f64, cx: f64, cy: f64, angle: f64) -> (f64, f64) {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (dx, dy) = (x - cx, y - cy);
+    (cx + dx * cos + dy * sin, cy - dx * sin + dy * cos)
+}
+
+/// Rotate a frame about its centre as pam-glance's `rotate_frame` does:
+/// OpenCV's warpAffine, bilinear, same size, corners cropped to black
+fn rotate_frame(frame: &Mat, angle: f64) -> Option<Mat> {
+    use opencv::core::{Point2f, Scalar, BORDER_CONSTANT};
+    use opencv::imgproc;
+    
+    let center = Point2f::new(frame.cols() as f32 / 2.0, frame.rows() as f32 / 2.0);
+    let matrix = imgproc::get_rotation_matrix_2d(center, angle, 1.0).ok()?;
+    
+    let mut rotated = Mat::default();
+    imgproc::warp_affine(frame, &mut rotated, &matrix, frame.size().ok()?,
+                         imgproc::INTER_LINEAR, BORDER_CONSTANT, Scalar::default()).ok()?;
+    Some(rotated)
+}
+
+/// Map a point found in a frame rotated by `angle` back to the original
+/// frame's pixels
+fn unrotate_point(x: i64, y: i64, angle: f64, width: u32, height: u32) -> (i32, i32) {
+    if angle == 0.0 {
+        return (x as i32, y as i32);
+    }
+    
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let (x, y) = rotate_point(x as f64, y as f64, cx, cy, -angle);
+    (x.round() as i32, y.round() as i32)
+}
+
+/// Map a `(left, top, right, bottom)` rect found in a frame rotated by
+/// `angle` back to the original frame's coordinates (bounding box)
+fn unrotate_rect(rect: (f64, f64, f64, f64), angle: f64, width: u32, height: u32) -> (f64, f64, f64, f64) {
+    if angle == 0.0 {
+        return rect;
+    }
+    
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let (l, t, r, b) = rect;
+    let corners = [(l, t), (r, t), (l, b), (r, b)].map(|(x, y)| rotate_point(x, y, cx, cy, -angle));
+    
+    let min_x = corners.iter().map(|c| c.0).fold(f64::MAX, f64::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f64::MIN, f64::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f64::MAX, f64::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f64::MIN, f64::max);
+    
+    (min_x, min_y, max_x, max_y)
+}
+
 #[derive(Debug)]
 pub struct SharedFaceProcessor {
     inner: std::sync::Mutex<FaceProcessor>,
@@ -202,3 +375,99 @@ impl SharedFaceProcessor {
         processor.can_encode()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn upright_points_are_unchanged() {
+        assert_eq!(unrotate_point(12, 34, 0.0, 640, 480), (12, 34));
+    }
+    
+    #[test]
+    fn rotated_points_map_back() {
+        // 200x100 frame, centre (100, 50). A point right of centre is at the
+        // top once the frame is turned 90° counter-clockwise
+        assert_eq!(unrotate_point(100, 0, 90.0, 200, 100), (150, 50));
+        assert_eq!(unrotate_point(100, 100, -90.0, 200, 100), (150, 50));
+    }
+    
+    #[test]
+    fn searched_angles_round_trip() {
+        let (width, height) = (640, 480);
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        for angle in ROTATION_ANGLES {
+            for (x, y) in [(0, 0), (320, 240), (100, 400), (639, 479)] {
+                let (rx, ry) = rotate_point(x as f64, y as f64, cx, cy, angle);
+                let (ux, uy) = unrotate_point(rx.round() as i64, ry.round() as i64, angle, width, height);
+                assert!((ux - x).abs() <= 1 && (uy - y).abs() <= 1,
+                        "{}° moved ({}, {}) to ({}, {})", angle, x, y, ux, uy);
+            }
+        }
+    }
+    
+    #[test]
+    fn rotated_points_stay_inside_the_unrotated_rect() {
+        let (width, height) = (640, 480);
+        let rect = (250.0, 150.0, 390.0, 330.0);
+        let (l, t, r, b) = unrotate_rect(rect, 30.0, width, height);
+        for (x, y) in [(250, 150), (390, 150), (250, 330), (390, 330), (320, 240)] {
+            let (ux, uy) = unrotate_point(x, y, 30.0, width, height);
+            assert!(ux as f64 >= l.floor() && ux as f64 <= r.ceil()
+                    && uy as f64 >= t.floor() && uy as f64 <= b.ceil(),
+                    "({}, {}) outside {:?}", ux, uy, (l, t, r, b));
+        }
+    }
+    
+    fn canned(faces: Vec<(FaceRect, Vec<f32>)>) -> FaceProcessor {
+        FaceProcessor::with_embedder(Box::new(pam_glance::embedder::CannedEmbedder::new(faces)))
+    }
+    
+    fn grey_rgb(width: u32, height: u32) -> Vec<u8> {
+        vec![128; (width * height * 3) as usize]
+    }
+    
+    #[test]
+    fn canned_face_is_detected_and_encoded() {
+        let encoding = vec![0.25; 128];
+        let processor = canned(vec![((100, 80, 260, 240), encoding.clone())]);
+        let result = processor.detect_and_encode(&grey_rgb(640, 480), 640, 480);
+        assert!(result.face_found);
+        assert_eq!(result.face_rect, Some((100, 80, 160, 160)));
+        assert_eq!(result.encoding, Some(encoding));
+        assert!(result.has_encoding());
+    }
+    
+    #[test]
+    fn first_canned_face_is_the_one_encoded() {
+        let processor = canned(vec![((10, 10, 60, 60), vec![1.0; 128]), ((300, 200, 400, 300), vec![2.0; 128])]);
+        let result = processor.detect_and_encode(&grey_rgb(640, 480), 640, 480);
+        assert_eq!(result.face_rect, Some((10, 10, 50, 50)));
+        assert_eq!(result.encoding, Some(vec![1.0; 128]));
+    }
+    
+    #[test]
+    fn no_canned_face_is_empty() {
+        let result = canned(Vec::new()).detect_and_encode(&grey_rgb(640, 480), 640, 480);
+        assert!(!result.face_found);
+        assert!(result.encoding.is_none());
+    }
+    
+    #[test]
+    fn short_frames_are_rejected() {
+        let processor = canned(vec![((100, 80, 260, 240), vec![0.25; 128])]);
+        assert!(!processor.detect_and_encode(&grey_rgb(640, 479), 640, 480).face_found);
+    }
+    
+    #[test]
+    fn stages_are_timed_on_canned_faces() {
+        let processor = canned(vec![((100, 80, 260, 240), vec![0.25; 128])]);
+        let timings = processor.time_stages(&grey_rgb(640, 480), 640, 480).unwrap();
+        assert!(timings.encode.is_some());
+        assert_eq!(timings.encoding, Some(vec![0.25; 128]));
+        
+        let timings = canned(Vec::new()).time_stages(&grey_rgb(640, 480), 640, 480).unwrap();
+        assert!(timings.encode.is_none());
+    }
+}
@@ -1,8 +1,13 @@
 mod app;
 mod camera;
+mod cli;
+mod config;
 mod face;
 mod models;
+mod pam_stack;
+mod quality;
 mod storage;
+mod thumbnail;
 mod window;
 
 use app::GlanceApplication;
@@ -15,6 +20,12 @@ fn main() -> gtk4::glib::ExitCode {
         env_logger::Env::default().default_filter_or("info")
     ).init();
     
+    // Headless subcommands (e.g. `glance migrate`) never start GTK
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        return code;
+    }
+    
     adw::init().expect("Failed to initialize Libadwaita");
     let app = GlanceApplication::new();
     app.run()
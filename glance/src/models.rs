@@ -4,8 +4,9 @@
 
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
-use log::info;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use pam_glance::face::model_set_dir;
 
 /// Model file information
 #[allow(dead_code)]
@@ -14,6 +15,9 @@ pub struct ModelInfo {
     pub url: &'static str,
     pub compressed_name: &'static str,
     pub size_mb: u32,
+    /// Exact size of the decompressed `.dat` dlib.net ships. Anything else
+    /// is truncated or from a different release
+    pub file_size: u64,
 }
 
 pub const SHAPE_PREDICTOR: ModelInfo = ModelInfo {
@@ -21,6 +25,7 @@ pub const SHAPE_PREDICTOR: ModelInfo = ModelInfo {
     url: "http://dlib.net/files/shape_predictor_68_face_landmarks.dat.bz2",
     compressed_name: "shape_predictor_68_face_landmarks.dat.bz2",
     size_mb: 100,
+    file_size: 99_693_937,
 };
 
 pub const FACE_RECOGNITION: ModelInfo = ModelInfo {
@@ -28,25 +33,58 @@ pub const FACE_RECOGNITION: ModelInfo = ModelInfo {
     url: "http://dlib.net/files/dlib_face_recognition_resnet_model_v1.dat.bz2",
     compressed_name: "dlib_face_recognition_resnet_model_v1.dat.bz2",
     size_mb: 22,
+    file_size: 22_466_066,
 };
 
-/// Get the models directory (user-writable)
+/// CNN face detector, only used when built with the `cuda` feature
+pub const CNN_DETECTOR: ModelInfo = ModelInfo {
+    name: "mmod_human_face_detector.dat",
+    url: "http://dlib.net/files/mmod_human_face_detector.dat.bz2",
+    compressed_name: "mmod_human_face_detector.dat.bz2",
+    size_mb: 1,
+    file_size: 729_940,
+};
+
+/// Get the models directory (user-writable). With `recognition.model_set`
+/// every location below means that subdirectory of it, so checking and
+/// downloading happen within the selected set
 pub fn get_models_dir() -> PathBuf {
+    let recognition = crate::config::GlanceConfig::load().recognition;
+    let set = |dir: PathBuf| model_set_dir(&dir, &recognition.model_set);
+    
+    // Models picked in Preferences, for trying out other encoders
+    let configured = recognition.models_dir;
+    if !configured.is_empty() {
+        let custom_dir = PathBuf::from(&configured);
+        if models_exist_in(&custom_dir) {
+            return custom_dir;
+        }
+        warn!("recognition.models_dir={:?} does not contain the models, ignoring", custom_dir);
+    }
+    
+    // Packager override (Nix, Guix, ...), only if it really holds the models
+    if let Some(override_dir) = std::env::var_os("GLANCE_MODELS_DIR").map(PathBuf::from).map(set) {
+        if models_exist_in(&override_dir) {
+            return override_dir;
+        }
+        warn!("GLANCE_MODELS_DIR={:?} does not contain the models, ignoring", override_dir);
+    }
+    
     // Check Flatpak location first (when running as Flatpak)
-    let flatpak_dir = PathBuf::from("/app/share/glance/models");
+    let flatpak_dir = set(PathBuf::from("/app/share/glance/models"));
     if models_exist_in(&flatpak_dir) {
         return flatpak_dir;
     }
     
     // Check system location
-    let system_dir = PathBuf::from("/usr/share/glance/models");
+    let system_dir = set(PathBuf::from("/usr/share/glance/models"));
     if models_exist_in(&system_dir) {
         return system_dir;
     }
     
     // Check user data directory
     if let Some(data_dir) = dirs::data_dir() {
-        let user_models = data_dir.join("glance").join("models");
+        let user_models = set(data_dir.join("glance").join("models"));
         if models_exist_in(&user_models) {
             return user_models;
         }
@@ -56,11 +94,11 @@ pub fn get_models_dir() -> PathBuf {
     
     // Fallback to home directory
     if let Some(home) = dirs::home_dir() {
-        return home.join(".local").join("share").join("glance").join("models");
+        return set(home.join(".local").join("share").join("glance").join("models"));
     }
     
     // Last resort
-    PathBuf::from("./models")
+    set(PathBuf::from("./models"))
 }
 
 /// Check if models exist in a directory
@@ -68,11 +106,106 @@ pub fn models_exist_in(dir: &PathBuf) -> bool {
     dir.join(SHAPE_PREDICTOR.name).exists() && dir.join(FACE_RECOGNITION.name).exists()
 }
 
+/// Refuse a model file whose size doesn't match the release we expect, which
+/// dlib would otherwise load into nonsense or fail on with an opaque error
+pub fn check_model_file(model: &ModelInfo, dir: &Path) -> Result<(), String> {
+    let path = dir.join(model.name);
+    let size = fs::metadata(&path)
+        .map_err(|e| format!("Cannot read {:?}: {}", path, e))?
+        .len();
+    if size != model.file_size {
+        return Err(format!(
+            "Model file {:?} appears to be the wrong version ({} bytes, expected {}) — re-download it",
+            path, size, model.file_size));
+    }
+    Ok(())
+}
+
+/// Delete the model files in `dir`, so the next download fetches fresh
+/// copies even of ones whose size looks right
+pub fn remove_models(dir: &Path) -> Result<(), String> {
+    for model in [&SHAPE_PREDICTOR, &FACE_RECOGNITION, &CNN_DETECTOR] {
+        let path = dir.join(model.name);
+        match fs::remove_file(&path) {
+            Ok(()) => info!("Removed {:?}", path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Cannot remove {:?}: {}", path, e)),
+        }
+    }
+    Ok(())
+}
+
 /// Check if models are installed anywhere
 pub fn models_installed() -> bool {
     models_exist_in(&get_models_dir())
 }
 
+/// Lock file held in the models directory while a download runs
+const DOWNLOAD_LOCK: &str = ".download.lock";
+/// A lock this old is left over from a crash, whatever its PID says
+const STALE_LOCK_SECS: u64 = 60 * 60;
+
+/// Error text when another process holds the download lock
+pub const DOWNLOAD_IN_PROGRESS: &str = "Download already in progress";
+
+/// Held for the length of a download; removes the lock file when dropped
+struct DownloadLock {
+    path: PathBuf,
+}
+
+impl DownloadLock {
+    fn acquire(models_dir: &Path) -> Result<Self, String> {
+        let path = models_dir.join(DOWNLOAD_LOCK);
+        
+        for _ in 0..2 {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if !lock_is_stale(&path) {
+                        return Err(DOWNLOAD_IN_PROGRESS.to_string());
+                    }
+                    warn!("Removing stale download lock {:?}", path);
+                    let _ = fs::remove_file(&path);
+                }
+                Err(e) => return Err(format!("Failed to create {:?}: {}", path, e)),
+            }
+        }
+        
+        Err(DOWNLOAD_IN_PROGRESS.to_string())
+    }
+}
+
+impl Drop for DownloadLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The lock's owner is gone, or it has been held far longer than any download
+fn lock_is_stale(path: &Path) -> bool {
+    let too_old = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_some_and(|age| age.as_secs() > STALE_LOCK_SECS);
+    
+    let owner_alive = fs::read_to_string(path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| Path::new(&format!("/proc/{}", pid)).exists());
+    
+    too_old || !owner_alive
+}
+
+/// Whether another process is downloading into the models directory
+pub fn download_in_progress() -> bool {
+    let path = get_models_dir().join(DOWNLOAD_LOCK);
+    path.exists() && !lock_is_stale(&path)
+}
+
 /// Download progress callback type
 pub type ProgressCallback = Box<dyn Fn(f64, &str) + Send>;
 
@@ -86,8 +219,11 @@ pub fn download_models(progress_callback: Option<ProgressCallback>) -> Result<()
     fs::create_dir_all(&models_dir)
         .map_err(|e| format!("Failed to create models directory: {}", e))?;
     
-    // Download shape predictor
-    if !models_dir.join(SHAPE_PREDICTOR.name).exists() {
+    // Two downloaders would race on the same .part files
+    let _lock = DownloadLock::acquire(&models_dir)?;
+    
+    // Download shape predictor, replacing a truncated or mismatched one
+    if check_model_file(&SHAPE_PREDICTOR, &models_dir).is_err() {
         download_and_extract_model(
             &SHAPE_PREDICTOR,
             &models_dir,
@@ -98,7 +234,7 @@ pub fn download_models(progress_callback: Option<ProgressCallback>) -> Result<()
     }
     
     // Download face recognition model
-    if !models_dir.join(FACE_RECOGNITION.name).exists() {
+    if check_model_file(&FACE_RECOGNITION, &models_dir).is_err() {
         download_and_extract_model(
             &FACE_RECOGNITION,
             &models_dir,
@@ -108,6 +244,13 @@ pub fn download_models(progress_callback: Option<ProgressCallback>) -> Result<()
         )?;
     }
     
+    // Optional: without it CUDA builds fall back to the CPU detector
+    if cfg!(feature = "cuda") && !models_dir.join(CNN_DETECTOR.name).exists() {
+        if let Err(e) = download_and_extract_model(&CNN_DETECTOR, &models_dir, None::<fn(f64)>) {
+            warn!("Could not download CNN detector: {}", e);
+        }
+    }
+    
     if let Some(ref cb) = progress_callback {
         cb(1.0, "Models installed successfully!");
     }
@@ -116,7 +259,28 @@ pub fn download_models(progress_callback: Option<ProgressCallback>) -> Result<()
     Ok(())
 }
 
-/// Download and extract a single model
+/// Reader that reports how many compressed bytes have passed through it
+struct ProgressReader<R, F> {
+    inner: R,
+    downloaded: usize,
+    total: usize,
+    progress: Option<F>,
+}
+
+impl<R: Read, F: Fn(f64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.downloaded += n;
+        if let Some(ref p) = self.progress {
+            p(self.downloaded as f64 / self.total as f64);
+        }
+        Ok(n)
+    }
+}
+
+/// Download and extract a single model. The download is streamed through
+/// the bzip2 decoder straight into the file, so memory use stays at a few
+/// buffers whatever the model size.
 fn download_and_extract_model<F>(
     model: &ModelInfo,
     dest_dir: &PathBuf,
@@ -126,10 +290,10 @@ where
     F: Fn(f64),
 {
     let final_path = dest_dir.join(model.name);
+    let partial_path = dest_dir.join(format!("{}.part", model.name));
     
     info!("Downloading {} from {}", model.name, model.url);
     
-    // Download the compressed file
     let response = ureq::get(model.url)
         .call()
         .map_err(|e| format!("Failed to download {}: {}", model.name, e))?;
@@ -138,39 +302,37 @@ where
         .and_then(|s| s.parse::<usize>().ok())
         .unwrap_or(model.size_mb as usize * 1024 * 1024);
     
-    let mut reader = response.into_reader();
-    let mut compressed_data = Vec::with_capacity(content_length);
-    let mut buffer = [0u8; 8192];
-    let mut downloaded = 0usize;
-    
-    loop {
-        match reader.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => {
-                compressed_data.extend_from_slice(&buffer[..n]);
-                downloaded += n;
-                if let Some(ref p) = progress {
-                    p(downloaded as f64 / content_length as f64);
-                }
-            }
-            Err(e) => return Err(format!("Download error: {}", e)),
-        }
-    }
+    let reader = ProgressReader {
+        inner: response.into_reader(),
+        downloaded: 0,
+        total: content_length,
+        progress,
+    };
+    let mut decompressor = bzip2::read::BzDecoder::new(std::io::BufReader::new(reader));
     
-    info!("Downloaded {} bytes, decompressing...", compressed_data.len());
+    // Write next to the final path and rename at the end, so an interrupted
+    // download never leaves a truncated model behind
+    let file = File::create(&partial_path)
+        .map_err(|e| format!("Failed to create {}: {}", model.name, e))?;
+    let mut writer = std::io::BufWriter::new(file);
     
-    // Decompress bz2
-    let mut decompressor = bzip2::read::BzDecoder::new(&compressed_data[..]);
-    let mut decompressed_data = Vec::new();
-    decompressor.read_to_end(&mut decompressed_data)
-        .map_err(|e| format!("Failed to decompress {}: {}", model.name, e))?;
+    let written = match std::io::copy(&mut decompressor, &mut writer).and_then(|n| writer.flush().map(|_| n)) {
+        Ok(n) => n,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(format!("Failed to download {}: {}", model.name, e));
+        }
+    };
+    drop(writer);
     
-    info!("Decompressed to {} bytes", decompressed_data.len());
+    info!("Decompressed to {} bytes", written);
     
-    // Write to final location
-    let mut file = File::create(&final_path)
-        .map_err(|e| format!("Failed to create {}: {}", model.name, e))?;
-    file.write_all(&decompressed_data)
+    if written != model.file_size {
+        let _ = fs::remove_file(&partial_path);
+        return Err(format!("Downloaded {} is {} bytes, expected {}", model.name, written, model.file_size));
+    }
+    
+    fs::rename(&partial_path, &final_path)
         .map_err(|e| format!("Failed to write {}: {}", model.name, e))?;
     
     info!("Saved model to {:?}", final_path);
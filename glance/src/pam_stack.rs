@@ -0,0 +1,98 @@
+//! Adding and removing pam_glance.so in /etc/pam.d
+//!
+//! Mirrors what install.sh does for each service: back the file up, drop any
+//! existing Glance line, then add `auth sufficient pam_glance.so` next to
+//! the other auth rules so the password prompt still follows on failure.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const PAM_LINE: &str = "auth sufficient pam_glance.so";
+
+pub fn service_path(service: &str) -> PathBuf {
+    PathBuf::from("/etc/pam.d").join(service)
+}
+
+fn is_glance_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.starts_with('#') && trimmed.contains("pam_glance.so")
+}
+
+/// Whether an active pam_glance.so line is in the service's stack
+pub fn is_enabled(service: &str) -> bool {
+    fs::read_to_string(service_path(service))
+        .map(|content| content.lines().any(is_glance_line))
+        .unwrap_or(false)
+}
+
+/// Copy the service file aside before changing it, returning the copy's path
+fn backup(service: &str, content: &str) -> Result<PathBuf, String> {
+    let path = service_path(service);
+    let backup = PathBuf::from(format!("{}.backup.{}", path.display(),
+                                       chrono::Local::now().format("%Y%m%d%H%M%S")));
+    fs::write(&backup, content)
+        .map_err(|e| format!("Failed to back up {}: {}", path.display(), e))?;
+    Ok(backup)
+}
+
+/// Replace the service file. The new stack goes to a temporary file next to
+/// it with the same mode, synced, then renamed over the original, so a
+/// failed write never leaves a truncated stack that locks out sudo
+fn write(service: &str, lines: &[&str]) -> Result<(), String> {
+    let path = service_path(service);
+    let tmp = path.with_file_name(format!(".{}.glance-tmp", service));
+    let mut content = lines.join("\n");
+    content.push('\n');
+    
+    let written = fs::metadata(&path)
+        .and_then(|metadata| {
+            let mut file = fs::File::create(&tmp)?;
+            file.set_permissions(metadata.permissions())?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, &path));
+    
+    written.map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("Failed to write {}: {}", path.display(), e)
+    })
+}
+
+/// Add pam_glance.so to a service. Returns the backup's path.
+pub fn enable(service: &str) -> Result<PathBuf, String> {
+    let path = service_path(service);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let backup = backup(service, &content)?;
+    
+    let mut lines: Vec<&str> = content.lines().filter(|l| !is_glance_line(l)).collect();
+    let position = if let Some(i) = lines.iter().position(|l| l.trim() == "@include common-auth") {
+        i + 1
+    } else if let Some(i) = lines.iter().position(|l| l.trim_start().starts_with("auth")) {
+        i
+    } else {
+        lines.len().min(1)
+    };
+    lines.insert(position, PAM_LINE);
+    
+    write(service, &lines)?;
+    Ok(backup)
+}
+
+/// Remove every active pam_glance.so line from a service. Returns the
+/// backup's path, or `None` if there was nothing to remove.
+pub fn disable(service: &str) -> Result<Option<PathBuf>, String> {
+    let path = service_path(service);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !content.lines().any(is_glance_line) {
+        return Ok(None);
+    }
+    let backup = backup(service, &content)?;
+    
+    let lines: Vec<&str> = content.lines().filter(|l| !is_glance_line(l)).collect();
+    write(service, &lines)?;
+    Ok(Some(backup))
+}
@@ -0,0 +1,190 @@
+//! Enrollment quality checks
+//!
+//! Scores each captured frame on how well the face is lit and how
+//! consistent the resulting encodings are, so a template built entirely
+//! from marginal frames can be caught before it fails at login.
+
+/// Per-frame quality below this is considered marginal (0.0 – 1.0)
+const MIN_FRAME_QUALITY: f64 = 0.45;
+
+/// Faces narrower than this share of the frame width encode less reliably
+const MIN_FACE_FRACTION: f64 = 0.2;
+
+/// Encodings of the same face in good conditions sit well inside the
+/// match tolerance; a wider spread means the frames disagree.
+const MAX_ENCODING_SPREAD: f64 = 0.35;
+
+/// Score the face region of an RGB frame on brightness and contrast.
+/// `face_rect` is `(x, y, width, height)`.
+pub fn frame_quality(rgb_data: &[u8], width: u32, height: u32, face_rect: (i32, i32, i32, i32)) -> f64 {
+    let (x, y, w, h) = face_rect;
+    let x0 = x.clamp(0, width as i32) as usize;
+    let y0 = y.clamp(0, height as i32) as usize;
+    let x1 = (x + w).clamp(0, width as i32) as usize;
+    let y1 = (y + h).clamp(0, height as i32) as usize;
+    
+    if x1 <= x0 || y1 <= y0 {
+        return 0.0;
+    }
+    
+    let stride = width as usize * 3;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut count = 0.0;
+    
+    for row in y0..y1 {
+        for col in x0..x1 {
+            let i = row * stride + col * 3;
+            let Some(px) = rgb_data.get(i..i + 3) else {
+                continue;
+            };
+            let luma = 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64;
+            sum += luma;
+            sum_sq += luma * luma;
+            count += 1.0;
+        }
+    }
+    
+    if count == 0.0 {
+        return 0.0;
+    }
+    
+    let mean = sum / count;
+    let std_dev = (sum_sq / count - mean * mean).max(0.0).sqrt();
+    
+    // Best around mid-grey, falling off towards black or blown out
+    let brightness_score = 1.0 - ((mean - 128.0).abs() / 128.0);
+    // Flat, low-contrast faces (dim or washed out) encode poorly
+    let contrast_score = (std_dev / 50.0).min(1.0);
+    
+    (brightness_score * 0.6 + contrast_score * 0.4).clamp(0.0, 1.0)
+}
+
+/// Mean luma (0 – 255) of an RGB frame, sampling every 4th pixel
+pub fn mean_brightness(rgb_data: &[u8]) -> f64 {
+    let (sum, count) = rgb_data.chunks_exact(3)
+        .step_by(4)
+        .fold((0.0, 0.0), |(sum, count), px| {
+            (sum + 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64, count + 1.0)
+        });
+    if count == 0.0 { 0.0 } else { sum / count }
+}
+
+/// Same thresholds as pam-glance's `is_obstructed`: a covered lens is dark
+/// overall *and* has no brighter 8x8 block anywhere
+const COVERED_MAX_MEAN: f64 = 10.0;
+const COVERED_MAX_BLOCK: f64 = 24.0;
+const COVERED_BLOCK: usize = 8;
+
+/// Whether the whole frame is black, as with a closed privacy shutter,
+/// rather than just too dark for detection
+pub fn is_covered(rgb_data: &[u8], width: u32, height: u32) -> bool {
+    let (width, height) = (width as usize, height as usize);
+    if width < COVERED_BLOCK || height < COVERED_BLOCK || rgb_data.len() < width * height * 3 {
+        return false;
+    }
+    
+    let luma = |i: usize| {
+        0.299 * rgb_data[i] as f64 + 0.587 * rgb_data[i + 1] as f64 + 0.114 * rgb_data[i + 2] as f64
+    };
+    
+    let mut total = 0.0;
+    let mut max_block: f64 = 0.0;
+    for by in 0..height / COVERED_BLOCK {
+        for bx in 0..width / COVERED_BLOCK {
+            let mut block = 0.0;
+            for y in by * COVERED_BLOCK..(by + 1) * COVERED_BLOCK {
+                for x in bx * COVERED_BLOCK..(bx + 1) * COVERED_BLOCK {
+                    block += luma((y * width + x) * 3);
+                }
+            }
+            total += block;
+            max_block = max_block.max(block / (COVERED_BLOCK * COVERED_BLOCK) as f64);
+        }
+    }
+    
+    let counted = (width / COVERED_BLOCK) * (height / COVERED_BLOCK) * COVERED_BLOCK * COVERED_BLOCK;
+    total / counted as f64 < COVERED_MAX_MEAN && max_block < COVERED_MAX_BLOCK
+}
+
+/// How good a frame that passed the enrollment gate is, for guidance
+/// while capturing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameGrade {
+    Strong,
+    /// Usable, but the face is small in the frame
+    TooFar,
+    /// Usable, but the lighting is marginal
+    Dim,
+}
+
+impl FrameGrade {
+    /// Grade from `frame_quality` and the face width as a share of the frame
+    pub fn of(quality: f64, face_fraction: f64) -> Self {
+        if quality < MIN_FRAME_QUALITY {
+            FrameGrade::Dim
+        } else if face_fraction < MIN_FACE_FRACTION {
+            FrameGrade::TooFar
+        } else {
+            FrameGrade::Strong
+        }
+    }
+    
+    pub fn is_borderline(self) -> bool {
+        self != FrameGrade::Strong
+    }
+    
+    /// Guidance text and its style (the `guidance-*` CSS class suffix)
+    pub fn guidance(self) -> (&'static str, &'static str) {
+        match self {
+            FrameGrade::Strong => ("Great lighting! Stay still...", "success"),
+            FrameGrade::TooFar => ("Usable, but move a little closer", "warning"),
+            FrameGrade::Dim => ("Usable, but brighter light would help", "warning"),
+        }
+    }
+}
+
+/// Largest pairwise Euclidean distance between encodings
+pub fn encoding_spread(encodings: &[Vec<f32>]) -> f64 {
+    let mut spread: f64 = 0.0;
+    
+    for (i, a) in encodings.iter().enumerate() {
+        for b in &encodings[i + 1..] {
+            let distance = a.iter()
+                .zip(b.iter())
+                .map(|(x, y)| (x - y).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            spread = spread.max(distance as f64);
+        }
+    }
+    
+    spread
+}
+
+/// Overall assessment of one enrollment session
+#[derive(Debug, Clone)]
+pub struct EnrollmentQuality {
+    pub mean_frame_quality: f64,
+    pub spread: f64,
+}
+
+impl EnrollmentQuality {
+    pub fn assess(samples: &[(Vec<f32>, f64)]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        
+        let mean_frame_quality = samples.iter().map(|(_, q)| q).sum::<f64>() / samples.len() as f64;
+        let encodings: Vec<Vec<f32>> = samples.iter().map(|(e, _)| e.clone()).collect();
+        
+        Some(Self {
+            mean_frame_quality,
+            spread: encoding_spread(&encodings),
+        })
+    }
+    
+    pub fn is_poor(&self) -> bool {
+        self.mean_frame_quality < MIN_FRAME_QUALITY || self.spread > MAX_ENCODING_SPREAD
+    }
+}
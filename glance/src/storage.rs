@@ -1,6 +1,7 @@
+use pam_glance::userfile;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn get_storage_dir() -> PathBuf {
     if let Some(data_dir) = dirs::data_dir() {
@@ -40,7 +41,7 @@ fn get_legacy_storage_dirs() -> Vec<PathBuf> {
     dirs
 }
 
-fn can_write_to_system() -> bool {
+pub fn can_write_to_system() -> bool {
     let system_dir = PathBuf::from("/var/lib/glance");
     if !system_dir.exists() {
         return false;
@@ -55,6 +56,10 @@ fn can_write_to_system() -> bool {
     }
 }
 
+/// Pose recorded for templates captured while wearing a mask. The PAM
+/// module only matches these when `mask_friendly` is enabled.
+pub const MASKED_POSE: &str = "masked";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FaceData {
     pub username: String,
@@ -68,14 +73,23 @@ pub struct FaceData {
     pub rgb_captured: bool,
     pub created_at: String,
     pub updated_at: String,
+    /// Optional 96x96 face picture (base64 PNG), see `thumbnail.rs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FaceEncoding {
-    pub encoding: Vec<f64>,
+    /// Single precision, as dlib's network computes it; half the memory and
+    /// digits on disk of `f64`. Files written with `f64` digits still load,
+    /// rounded to the nearest `f32`, and shrink on their next save
+    pub encoding: Vec<f32>,
     pub pose: String,
     #[serde(default)]
     pub camera_type: String,  // "ir" or "rgb"
+    /// Recognition model that produced this encoding (empty for old data)
+    #[serde(default)]
+    pub model_id: String,
 }
 
 impl FaceData {
@@ -90,33 +104,37 @@ impl FaceData {
             rgb_captured: false,
             created_at: now.clone(),
             updated_at: now,
+            thumbnail: None,
         }
     }
     
-    pub fn add_encoding(&mut self, encoding: Vec<f64>, pose: &str) {
+    pub fn add_encoding(&mut self, encoding: Vec<f32>, pose: &str, model_id: &str) {
         self.encodings.push(FaceEncoding {
             encoding,
             pose: pose.to_string(),
             camera_type: String::new(),
+            model_id: model_id.to_string(),
         });
         self.updated_at = chrono::Utc::now().to_rfc3339();
     }
     
-    pub fn add_ir_encoding(&mut self, encoding: Vec<f64>, pose: &str) {
+    pub fn add_ir_encoding(&mut self, encoding: Vec<f32>, pose: &str, model_id: &str) {
         self.ir_encodings.push(FaceEncoding {
             encoding,
             pose: pose.to_string(),
             camera_type: "ir".to_string(),
+            model_id: model_id.to_string(),
         });
         self.ir_captured = true;
         self.updated_at = chrono::Utc::now().to_rfc3339();
     }
     
-    pub fn add_rgb_encoding(&mut self, encoding: Vec<f64>, pose: &str) {
+    pub fn add_rgb_encoding(&mut self, encoding: Vec<f32>, pose: &str, model_id: &str) {
         self.rgb_encodings.push(FaceEncoding {
             encoding,
             pose: pose.to_string(),
             camera_type: "rgb".to_string(),
+            model_id: model_id.to_string(),
         });
         self.rgb_captured = true;
         self.updated_at = chrono::Utc::now().to_rfc3339();
@@ -129,50 +147,282 @@ impl FaceData {
         all.extend(self.rgb_encodings.iter());
         all
     }
+    
+    /// Replace each group of IR or RGB templates that share a pose and model
+    /// with their mean, a steadier template than any single frame. Masked
+    /// and other poses are averaged apart, never together. With `keep_raw`
+    /// the frames stay alongside the mean. The untagged copies kept for older
+    /// modules are rebuilt to match, since they mix IR and RGB.
+    pub fn average_templates(&mut self, keep_raw: bool) {
+        for templates in [&mut self.ir_encodings, &mut self.rgb_encodings] {
+            let mut averaged: Vec<FaceEncoding> = Vec::new();
+            let mut groups: Vec<Vec<&FaceEncoding>> = Vec::new();
+            for template in templates.iter() {
+                let group = groups.iter_mut().find(|g| {
+                    g[0].pose == template.pose
+                        && g[0].model_id == template.model_id
+                        && g[0].encoding.len() == template.encoding.len()
+                });
+                match group {
+                    Some(group) => group.push(template),
+                    None => groups.push(vec![template]),
+                }
+            }
+            
+            for group in groups {
+                if keep_raw || group.len() == 1 {
+                    averaged.extend(group.iter().map(|t| (*t).clone()));
+                }
+                if group.len() > 1 {
+                    averaged.push(FaceEncoding {
+                        encoding: mean_encoding(&group),
+                        ..group[0].clone()
+                    });
+                }
+            }
+            *templates = averaged;
+        }
+        
+        if !self.encodings.is_empty() {
+            self.encodings = self.ir_encodings.iter()
+                .chain(self.rgb_encodings.iter())
+                .map(|t| FaceEncoding { camera_type: String::new(), ..t.clone() })
+                .collect();
+        }
+        self.updated_at = chrono::Utc::now().to_rfc3339();
+    }
+    
+    /// Distance from `encoding` to the nearest stored template of the same
+    /// camera type and model. Masked templates are skipped, since they're
+    /// meant to sit far from the unmasked ones.
+    pub fn closest_template(&self, encoding: &[f32], is_ir: bool, model_id: &str) -> Option<f64> {
+        let templates = if is_ir { &self.ir_encodings } else { &self.rgb_encodings };
+        templates.iter()
+            .filter(|t| t.pose != MASKED_POSE && t.model_id == model_id)
+            .map(|t| encoding_distance(encoding, &t.encoding))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+/// Another user whose stored face matches a new enrollment
+#[derive(Debug, Clone)]
+pub struct DuplicateFace {
+    pub username: String,
+    pub distance: f64,
+}
+
+/// Face data of every other user in the system store, which is what the
+/// PAM module matches against. Unreadable files are skipped.
+fn load_other_users(exclude: &str) -> Vec<FaceData> {
+    let Ok(entries) = fs::read_dir("/var/lib/glance") else {
+        return Vec::new();
+    };
+    
+    entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let username = userfile::username_from_stem(name.strip_suffix("_face.json")
+                .or_else(|| name.strip_suffix(".json"))?);
+            if username == exclude || username == "config" || username == "faces" {
+                return None;
+            }
+            
+            let content = fs::read_to_string(entry.path()).ok()?;
+            let mut data: FaceData = serde_json::from_str(&content).ok()?;
+            data.username = username;
+            Some(data)
+        })
+        .collect()
+}
+
+/// Element-wise mean of encodings of equal length
+fn mean_encoding(group: &[&FaceEncoding]) -> Vec<f32> {
+    let mut mean = vec![0.0; group[0].encoding.len()];
+    for template in group {
+        for (sum, value) in mean.iter_mut().zip(&template.encoding) {
+            *sum += value;
+        }
+    }
+    for value in mean.iter_mut() {
+        *value /= group.len() as f32;
+    }
+    mean
+}
+
+/// Euclidean distance, computed in `f32` like the PAM module's
+pub fn encoding_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f32>().sqrt() as f64
+}
+
+/// Check a new enrollment against every other user's stored encodings.
+///
+/// IR encodings are only compared with IR ones (at `ir_tolerance`) and RGB
+/// with RGB; untagged legacy encodings are compared with everything at
+/// `rgb_tolerance`. Encodings from different recognition models aren't
+/// comparable and are skipped. Returns the closest match within tolerance.
+pub fn find_duplicate_face(data: &FaceData, ir_tolerance: f64, rgb_tolerance: f64) -> Option<DuplicateFace> {
+    let mut best: Option<DuplicateFace> = None;
+    
+    for other in load_other_users(&data.username) {
+        for new in data.all_encodings() {
+            for stored in other.all_encodings() {
+                let comparable_camera = new.camera_type.is_empty()
+                    || stored.camera_type.is_empty()
+                    || new.camera_type == stored.camera_type;
+                let comparable_model = new.model_id.is_empty()
+                    || stored.model_id.is_empty()
+                    || new.model_id == stored.model_id;
+                if !comparable_camera || !comparable_model
+                    || new.encoding.len() != stored.encoding.len() {
+                    continue;
+                }
+                
+                let tolerance = if new.camera_type == "ir" && stored.camera_type == "ir" {
+                    ir_tolerance
+                } else {
+                    rgb_tolerance
+                };
+                
+                let distance = encoding_distance(&new.encoding, &stored.encoding);
+                if distance <= tolerance && best.as_ref().map_or(true, |b| distance < b.distance) {
+                    best = Some(DuplicateFace { username: other.username.clone(), distance });
+                }
+            }
+        }
+    }
+    
+    best
+}
+
+/// `{username}.json`, with the username encoded so directory accounts like
+/// `EXAMPLE\bob` make a safe file name (see `pam_glance::userfile`)
+fn user_file_name(username: &str) -> String {
+    format!("{}.json", userfile::file_stem(username))
 }
 
 pub fn get_storage_path(username: &str) -> PathBuf {
-    get_storage_dir().join(format!("{}.json", username))
+    get_storage_dir().join(user_file_name(username))
 }
 
-pub fn load_face_data(username: &str) -> Option<FaceData> {
-    // Check current glance location first
-    let path = get_storage_path(username);
+/// The user's face data file in `dir`, if any. A file still under the raw
+/// username from before names were encoded is renamed when possible, and
+/// used where it is otherwise.
+fn find_user_file(dir: &Path, username: &str) -> Option<PathBuf> {
+    let path = dir.join(user_file_name(username));
+    if path.exists() {
+        return Some(path);
+    }
+    
+    let legacy = dir.join(format!("{}.json", userfile::legacy_stem(username)?));
+    if !legacy.exists() {
+        return None;
+    }
+    match fs::rename(&legacy, &path) {
+        Ok(_) => {
+            eprintln!("[Storage] Renamed {:?} to {:?}", legacy, path);
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!("[Storage] Could not rename {:?}: {}", legacy, e);
+            Some(legacy)
+        }
+    }
+}
+
+/// The user's file in an old `facerec` directory, never renamed: the old
+/// tool may still be reading it under the raw username
+fn find_legacy_file(dir: &Path, username: &str) -> Option<PathBuf> {
+    let path = dir.join(user_file_name(username));
     if path.exists() {
+        return Some(path);
+    }
+    let raw = dir.join(format!("{}.json", userfile::legacy_stem(username)?));
+    raw.exists().then_some(raw)
+}
+
+/// Old `facerec` face data for `username` that hasn't been migrated, e.g.
+/// to point the user at `glance migrate`
+pub fn find_unmigrated_legacy_data(username: &str) -> Option<PathBuf> {
+    get_legacy_storage_dirs().iter().find_map(|dir| find_legacy_file(dir, username))
+}
+
+/// Face data that exists but can't be read or parsed. Kept apart from "not
+/// enrolled" so the GUI doesn't offer a fresh setup over salvageable data.
+#[derive(Debug, Clone)]
+pub struct CorruptFaceData {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+fn read_face_file(path: &Path) -> Result<FaceData, CorruptFaceData> {
+    let corrupt = |error: String| {
+        eprintln!("[Storage] Face data at {:?} is unusable: {}", path, error);
+        CorruptFaceData { path: path.to_path_buf(), error }
+    };
+    let content = fs::read_to_string(path).map_err(|e| corrupt(e.to_string()))?;
+    serde_json::from_str(&content).map_err(|e| corrupt(e.to_string()))
+}
+
+/// Load a user's face data. `Ok(None)` means not enrolled; `Err` means the
+/// file is there but unusable.
+pub fn load_face_data(username: &str) -> Result<Option<FaceData>, CorruptFaceData> {
+    // Check current glance location first
+    if let Some(path) = find_user_file(&get_storage_dir(), username) {
         eprintln!("[Storage] Found face data at {:?}", path);
-        let content = fs::read_to_string(&path).ok()?;
-        return serde_json::from_str(&content).ok();
+        return read_face_file(&path).map(Some);
     }
     
     // Check system glance location
-    let system_path = PathBuf::from("/var/lib/glance").join(format!("{}.json", username));
-    if system_path.exists() {
+    if let Some(system_path) = find_user_file(Path::new("/var/lib/glance"), username) {
         eprintln!("[Storage] Found face data at system location {:?}", system_path);
-        let content = fs::read_to_string(&system_path).ok()?;
-        return serde_json::from_str(&content).ok();
+        return read_face_file(&system_path).map(Some);
     }
     
-    // Check legacy facerec locations and migrate if found
+    // Legacy facerec data is copied over only with `storage.migrate_legacy`,
+    // and deleted afterwards only with `storage.remove_migrated_legacy` too:
+    // the old tool may still be in use alongside
+    let settings = crate::config::GlanceConfig::load().storage;
     for legacy_dir in get_legacy_storage_dirs() {
-        let legacy_path = legacy_dir.join(format!("{}.json", username));
-        if legacy_path.exists() {
-            eprintln!("[Storage] Found legacy face data at {:?}, migrating...", legacy_path);
-            if let Ok(content) = fs::read_to_string(&legacy_path) {
-                if let Ok(data) = serde_json::from_str::<FaceData>(&content) {
-                    // Migrate to new location
-                    if save_face_data(&data).is_ok() {
-                        eprintln!("[Storage] Successfully migrated face data to new location");
-                        // Optionally remove old file
-                        let _ = fs::remove_file(&legacy_path);
-                    }
-                    return Some(data);
+        let Some(legacy_path) = find_legacy_file(&legacy_dir, username) else {
+            continue;
+        };
+        if !settings.migrate_legacy {
+            eprintln!("[Storage] Found legacy face data at {:?}, not migrating (storage.migrate_legacy is off; \
+                       `glance migrate` copies it)", legacy_path);
+            continue;
+        }
+        
+        eprintln!("[Storage] Found legacy face data at {:?}, migrating...", legacy_path);
+        let Some(data) = fs::read_to_string(&legacy_path).ok()
+            .and_then(|content| serde_json::from_str::<FaceData>(&content).ok()) else {
+            continue;
+        };
+        if save_face_data(&data).is_ok() {
+            eprintln!("[Storage] Copied legacy face data to the new location");
+            if settings.remove_migrated_legacy {
+                match fs::remove_file(&legacy_path) {
+                    Ok(()) => eprintln!("[Storage] Removed {:?}", legacy_path),
+                    Err(e) => eprintln!("[Storage] Could not remove {:?}: {}", legacy_path, e),
                 }
             }
         }
+        return Ok(Some(data));
     }
     
     eprintln!("[Storage] No face data found for user: {}", username);
-    None
+    Ok(None)
+}
+
+/// Rename an unusable face data file to `<name>.corrupt.<timestamp>` so a
+/// new enrollment doesn't overwrite it and it can still be repaired by hand.
+/// Returns the new path.
+pub fn set_aside_corrupt(corrupt: &CorruptFaceData) -> Result<PathBuf, String> {
+    let backup = PathBuf::from(format!("{}.corrupt.{}", corrupt.path.display(),
+                                       chrono::Local::now().format("%Y%m%d%H%M%S")));
+    fs::rename(&corrupt.path, &backup)
+        .map_err(|e| format!("Failed to move {:?} aside: {}", corrupt.path, e))?;
+    eprintln!("[Storage] Moved corrupt face data to {:?}", backup);
+    Ok(backup)
 }
 
 pub fn save_face_data(data: &FaceData) -> Result<(), String> {
@@ -185,7 +435,7 @@ pub fn save_face_data(data: &FaceData) -> Result<(), String> {
     fs::create_dir_all(&storage_dir)
         .map_err(|e| format!("Failed to create storage directory {:?}: {}", storage_dir, e))?;
     
-    let path = storage_dir.join(format!("{}.json", data.username));
+    let path = storage_dir.join(user_file_name(&data.username));
     eprintln!("[Storage] Writing to: {:?}", path);
     
     let content = serde_json::to_string_pretty(data)
@@ -197,7 +447,7 @@ pub fn save_face_data(data: &FaceData) -> Result<(), String> {
     eprintln!("[Storage] Saved successfully to {:?}", path);
     
     if can_write_to_system() {
-        let system_path = PathBuf::from("/var/lib/glance").join(format!("{}.json", data.username));
+        let system_path = PathBuf::from("/var/lib/glance").join(user_file_name(&data.username));
         match fs::write(&system_path, &content) {
             Ok(_) => eprintln!("[Storage] Also saved to system location: {:?}", system_path),
             Err(e) => eprintln!("[Storage] Could not save to system location: {}", e),
@@ -207,6 +457,176 @@ pub fn save_face_data(data: &FaceData) -> Result<(), String> {
     Ok(())
 }
 
+/// Save the empty scene in front of a camera for PAM's background
+/// subtraction. It describes the machine, not a user, so there is one per
+/// camera type, and PAM only reads it from the system location
+pub fn save_background(background: &pam_glance::background::Background,
+                       camera_type: pam_glance::camera::CameraType) -> Result<PathBuf, String> {
+    let path = PathBuf::from("/var/lib/glance").join(pam_glance::background::Background::file_name(camera_type));
+    background.save(&path)
+        .map_err(|e| format!("Failed to write background to {:?}: {}", path, e))?;
+    eprintln!("[Storage] Saved background to {:?}", path);
+    Ok(path)
+}
+
+/// A copy of a user's face data found in one of the known storage locations
+#[derive(Debug, Clone)]
+pub struct StoredCopy {
+    pub path: PathBuf,
+    /// Parsed face data, or `None` if the file exists but could not be read
+    pub data: Option<FaceData>,
+}
+
+/// Outcome of consolidating a user's face data into the canonical location
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    pub source: PathBuf,
+    pub written: Vec<PathBuf>,
+    pub stale: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub secure_db_entry: bool,
+}
+
+/// Every directory a per-user `{username}.json` may live in, canonical first
+fn all_storage_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![get_storage_dir()];
+    
+    if let Some(config_dir) = dirs::config_dir() {
+        dirs.push(config_dir.join("glance"));
+    }
+    
+    dirs.push(PathBuf::from("/var/lib/glance"));
+    
+    for legacy_dir in get_legacy_storage_dirs() {
+        if !dirs.contains(&legacy_dir) {
+            dirs.push(legacy_dir);
+        }
+    }
+    
+    dirs
+}
+
+/// Whether the system secure database holds an entry for this user.
+/// Its encodings are obfuscated with the machine key and managed by the
+/// installer, so migration only reports it; `purge_user` removes it.
+fn secure_db_has_user(username: &str) -> bool {
+    fs::read_to_string("/var/lib/glance/faces.json")
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .map(|db| db.get("faces").and_then(|f| f.get(username)).is_some())
+        .unwrap_or(false)
+}
+
+/// Find every copy of a user's face data across current, system and legacy locations
+pub fn find_face_data_copies(username: &str) -> Vec<StoredCopy> {
+    let mut copies = Vec::new();
+    let legacy_dirs = get_legacy_storage_dirs();
+    
+    for dir in all_storage_dirs() {
+        let found = if legacy_dirs.contains(&dir) {
+            find_legacy_file(&dir, username)
+        } else {
+            find_user_file(&dir, username)
+        };
+        let Some(path) = found else {
+            continue;
+        };
+        
+        let data = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<FaceData>(&content).ok());
+        
+        copies.push(StoredCopy { path, data });
+    }
+    
+    copies
+}
+
+/// Copies of a user's face data that disagree with each other: readable
+/// copies in more than one location whose `updated_at` or template count
+/// differ. Which one authenticates then depends on the loader, so these are
+/// worth reconciling with `migrate_face_data`. Empty when there is no conflict.
+pub fn find_conflicting_copies(username: &str) -> Vec<StoredCopy> {
+    let copies: Vec<StoredCopy> = find_face_data_copies(username)
+        .into_iter()
+        .filter(|c| c.data.is_some())
+        .collect();
+    
+    let signature = |c: &StoredCopy| c.data.as_ref()
+        .map(|d| (d.updated_at.clone(), d.all_encodings().len()));
+    let Some(first) = copies.first().map(signature) else {
+        return Vec::new();
+    };
+    
+    if copies.iter().all(|c| signature(c) == first) {
+        Vec::new()
+    } else {
+        copies
+    }
+}
+
+fn updated_timestamp(data: &FaceData) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::DateTime::parse_from_rfc3339(&data.updated_at).ok()
+}
+
+/// Consolidate a user's face data: pick the newest valid copy (by `updated_at`),
+/// write it to the canonical location and report (optionally remove) the rest.
+///
+/// Returns `Ok(None)` when no valid copy exists anywhere.
+pub fn migrate_face_data(username: &str, remove_stale: bool) -> Result<Option<MigrationReport>, String> {
+    let copies = find_face_data_copies(username);
+    
+    let newest = copies.iter()
+        .filter_map(|c| c.data.as_ref().map(|d| (c, d)))
+        .filter(|(_, d)| !d.all_encodings().is_empty())
+        .max_by(|(_, a), (_, b)| {
+            match (updated_timestamp(a), updated_timestamp(b)) {
+                (Some(ta), Some(tb)) => ta.cmp(&tb),
+                (Some(_), None) => std::cmp::Ordering::Greater,
+                (None, Some(_)) => std::cmp::Ordering::Less,
+                (None, None) => a.updated_at.cmp(&b.updated_at),
+            }
+        });
+    
+    let (source, data) = match newest {
+        Some((copy, data)) => (copy.path.clone(), data.clone()),
+        None => return Ok(None),
+    };
+    
+    eprintln!("[Storage] Newest face data for {} is {:?} (updated {})",
+              username, source, data.updated_at);
+    
+    save_face_data(&data)?;
+    
+    let mut written = vec![get_storage_path(username)];
+    if can_write_to_system() {
+        written.push(PathBuf::from("/var/lib/glance").join(user_file_name(username)));
+    }
+    
+    let stale: Vec<PathBuf> = copies.into_iter()
+        .map(|c| c.path)
+        .filter(|p| !written.contains(p))
+        .collect();
+    
+    let mut removed = Vec::new();
+    if remove_stale {
+        for path in &stale {
+            match fs::remove_file(path) {
+                Ok(_) => removed.push(path.clone()),
+                Err(e) => eprintln!("[Storage] Could not remove {:?}: {}", path, e),
+            }
+        }
+    }
+    
+    Ok(Some(MigrationReport {
+        source,
+        written,
+        stale,
+        removed,
+        secure_db_entry: secure_db_has_user(username),
+    }))
+}
+
 pub fn delete_face_data(username: &str) -> Result<(), String> {
     let path = get_storage_path(username);
     if path.exists() {
@@ -215,3 +635,84 @@ pub fn delete_face_data(username: &str) -> Result<(), String> {
     }
     Ok(())
 }
+
+/// What `purge_user` removed, and what it could not
+#[derive(Debug, Clone, Default)]
+pub struct PurgeReport {
+    pub removed: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    /// The user's entry was dropped from the system secure database
+    pub secure_db_entry_removed: bool,
+}
+
+/// Whether `name` is a file Glance may have kept for the user with this stem:
+/// current and `_face` JSON, the pickle from the Python version, and backups
+/// set aside by `set_aside_corrupt`
+fn is_user_file(name: &str, stem: &str) -> bool {
+    let Some(rest) = name.strip_prefix(stem) else {
+        return false;
+    };
+    matches!(rest, ".json" | "_face.json" | ".pkl")
+        || rest.starts_with(".json.corrupt.")
+}
+
+/// Drop the user's entry from the system secure database. The rest of the
+/// file, including the obfuscated encodings of other users, is kept as is.
+fn remove_secure_db_entry(username: &str) -> Result<bool, String> {
+    let path = Path::new("/var/lib/glance/faces.json");
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(false);
+    };
+    let mut db: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("Could not parse {:?}: {}", path, e))?;
+    let removed = db.get_mut("faces")
+        .and_then(|faces| faces.as_object_mut())
+        .and_then(|faces| faces.remove(username))
+        .is_some();
+    if removed {
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(&db).map_err(|e| e.to_string())?;
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not rewrite {:?}: {}", path, e))?;
+    }
+    Ok(removed)
+}
+
+/// Remove everything Glance keeps about a user: their face data (with its
+/// thumbnail) in every current, system and legacy location, old `.pkl`
+/// files, set-aside corrupt copies and their secure database entry.
+/// Stats and the camera probe cache hold no usernames, so they are left.
+/// Locations that aren't writable without root are reported as failures.
+pub fn purge_user(username: &str) -> PurgeReport {
+    let mut report = PurgeReport::default();
+    let stems: Vec<String> = std::iter::once(userfile::file_stem(username))
+        .chain(userfile::legacy_stem(username).map(str::to_string))
+        .collect();
+    
+    for dir in all_storage_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !stems.iter().any(|stem| is_user_file(&name, stem)) {
+                continue;
+            }
+            let path = entry.path();
+            match fs::remove_file(&path) {
+                Ok(_) => report.removed.push(path),
+                Err(e) => report.failed.push((path, e.to_string())),
+            }
+        }
+    }
+    
+    match remove_secure_db_entry(username) {
+        Ok(removed) => report.secure_db_entry_removed = removed,
+        Err(e) => report.failed.push((PathBuf::from("/var/lib/glance/faces.json"), e)),
+    }
+    
+    eprintln!("[Storage] Purged {} file(s) for {}, {} failed", report.removed.len(), username, report.failed.len());
+    report
+}
@@ -0,0 +1,78 @@
+//! Optional face thumbnail stored with an enrollment
+//!
+//! A small picture so people can tell which enrollment is theirs. Only made
+//! when `recognition.store_thumbnail` is on, since it puts an actual image
+//! of the face on disk next to the encodings.
+
+use base64::{engine::general_purpose, Engine as _};
+use opencv::core::{Mat, Point, Scalar, Size, Vector, BORDER_DEFAULT, CV_8UC1, CV_8UC3};
+use opencv::prelude::*;
+use opencv::{imgcodecs, imgproc};
+
+use crate::camera::wipe_mat;
+
+/// Thumbnail edge length in pixels
+const SIZE: i32 = 96;
+/// Crop edge relative to the larger side of the face box
+const CROP_SCALE: f64 = 1.6;
+
+/// Crop a square around `face_rect` (`(x, y, width, height)`), blur
+/// everything outside the face and return it as a base64 PNG
+pub fn face_thumbnail(rgb_data: &[u8], width: u32, height: u32, face_rect: (i32, i32, i32, i32)) -> Option<String> {
+    let (x, y, w, h) = face_rect;
+    if w <= 0 || h <= 0 || rgb_data.len() != (width * height * 3) as usize {
+        return None;
+    }
+    
+    let side = ((w.max(h) as f64 * CROP_SCALE) as i32).min(width as i32).min(height as i32);
+    let left = (x + w / 2 - side / 2).clamp(0, width as i32 - side) as usize;
+    let top = (y + h / 2 - side / 2).clamp(0, height as i32 - side) as usize;
+    let side = side as usize;
+    
+    let mut crop = Mat::new_rows_cols_with_default(side as i32, side as i32, CV_8UC3, Scalar::all(0.0)).ok()?;
+    {
+        let bytes = crop.data_bytes_mut().ok()?;
+        let stride = width as usize * 3;
+        for row in 0..side {
+            let src = (top + row) * stride + left * 3;
+            bytes[row * side * 3..(row + 1) * side * 3].copy_from_slice(&rgb_data[src..src + side * 3]);
+        }
+    }
+    
+    let result = encode(&crop);
+    wipe_mat(&mut crop);
+    result
+}
+
+fn encode(crop: &Mat) -> Option<String> {
+    let mut small = Mat::default();
+    imgproc::resize(crop, &mut small, Size::new(SIZE, SIZE), 0.0, 0.0, imgproc::INTER_AREA).ok()?;
+    
+    let mut bgr = Mat::default();
+    imgproc::cvt_color(&small, &mut bgr, imgproc::COLOR_RGB2BGR, 0).ok()?;
+    
+    let mut blurred = Mat::default();
+    imgproc::gaussian_blur(&bgr, &mut blurred, Size::new(15, 15), 0.0, 0.0, BORDER_DEFAULT).ok()?;
+    
+    // Keep the face itself sharp: an ellipse roughly the size of the face box
+    let mut mask = Mat::new_rows_cols_with_default(SIZE, SIZE, CV_8UC1, Scalar::all(0.0)).ok()?;
+    let half = (SIZE as f64 / CROP_SCALE / 2.0) as i32;
+    imgproc::ellipse(&mut mask, Point::new(SIZE / 2, SIZE / 2), Size::new(half, half * 5 / 4),
+                     0.0, 0.0, 360.0, Scalar::all(255.0), imgproc::FILLED, imgproc::LINE_AA, 0).ok()?;
+    bgr.copy_to_masked(&mut blurred, &mask).ok()?;
+    
+    let mut png = Vector::<u8>::new();
+    let encoded = imgcodecs::imencode(".png", &blurred, &mut png, &Vector::new()).ok()?;
+    
+    wipe_mat(&mut small);
+    wipe_mat(&mut bgr);
+    wipe_mat(&mut blurred);
+    
+    encoded.then(|| general_purpose::STANDARD.encode(png.as_slice()))
+}
+
+/// Decode a stored thumbnail into something GTK can show
+pub fn texture(thumbnail: &str) -> Option<gtk4::gdk::Texture> {
+    let png = general_purpose::STANDARD.decode(thumbnail).ok()?;
+    gtk4::gdk::Texture::from_bytes(&gtk4::glib::Bytes::from_owned(png)).ok()
+}
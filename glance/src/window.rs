@@ -7,13 +7,147 @@ use gtk::glib;
 use gtk::gio;
 
 use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pam_glance::ir_emitter::IrEmitter;
 
 use crate::app::GlanceApplication;
-use crate::camera::{Camera, CameraFrame, CameraInfo};
+use crate::camera::{Camera, CameraInfo};
 use crate::face::SharedFaceProcessor;
 use crate::models;
-use crate::storage::{FaceData, load_face_data, save_face_data};
+use crate::quality::{self, EnrollmentQuality, FrameGrade};
+use crate::thumbnail;
+use crate::config::GlanceConfig;
+use crate::storage::{CorruptFaceData, DuplicateFace, FaceData, MASKED_POSE, encoding_distance, find_duplicate_face, find_unmigrated_legacy_data, load_face_data, save_face_data, set_aside_corrupt};
+
+/// Good frames averaged into one template by "Quick Capture"
+const QUICK_CAPTURE_FRAMES: usize = 5;
+/// Frames read before quick capture gives up (~3s at 20fps)
+const QUICK_CAPTURE_MAX_FRAMES: u32 = 60;
+
+/// How long loading the models may take before the window offers a way out
+const MODEL_LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Edge length in camera pixels of each block in the privacy preview
+const PREVIEW_BLOCK: u32 = 16;
+
+/// What the camera thread found in one processed frame. Carries no pixels
+/// beyond the opt-in thumbnail and pixelated preview, so the main thread
+/// only has widgets to update.
+#[derive(Debug)]
+struct FrameResult {
+    face_found: bool,
+    encoding: Option<Vec<f32>>,
+    model_id: Option<String>,
+    /// Frame quality around the face, see `quality::frame_quality`
+    quality: Option<f64>,
+    /// Face width as a share of the frame width
+    face_fraction: Option<f64>,
+    can_encode: bool,
+    /// The whole frame is black, see `quality::is_covered`
+    covered: bool,
+    /// Brighter than `camera.max_brightness`, e.g. backlit by a window
+    overexposed: bool,
+    /// Face thumbnail for frames with an encoding, when enabled
+    thumbnail: Option<String>,
+    /// `camera.privacy_preview` picture, see `pixelate`
+    preview: Option<PreviewImage>,
+}
+
+/// Tiny RGB image, one pixel per `PREVIEW_BLOCK` square of the frame
+#[derive(Debug)]
+struct PreviewImage {
+    rgb: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// Average each `PREVIEW_BLOCK` square of an RGB frame into one pixel.
+/// Only this goes to the screen; detection keeps the full frame.
+fn pixelate(rgb_data: &[u8], width: u32, height: u32) -> Option<PreviewImage> {
+    let (out_w, out_h) = (width / PREVIEW_BLOCK, height / PREVIEW_BLOCK);
+    if out_w == 0 || out_h == 0 || rgb_data.len() < (width * height * 3) as usize {
+        return None;
+    }
+    
+    let block = PREVIEW_BLOCK as usize;
+    let stride = width as usize * 3;
+    let mut rgb = Vec::with_capacity((out_w * out_h * 3) as usize);
+    for by in 0..out_h as usize {
+        for bx in 0..out_w as usize {
+            let mut sum = [0u32; 3];
+            for row in by * block..(by + 1) * block {
+                let start = row * stride + bx * block * 3;
+                for px in rgb_data[start..start + block * 3].chunks_exact(3) {
+                    for (total, &value) in sum.iter_mut().zip(px) {
+                        *total += value as u32;
+                    }
+                }
+            }
+            let count = (block * block) as u32;
+            rgb.extend(sum.iter().map(|total| (total / count) as u8));
+        }
+    }
+    
+    Some(PreviewImage { rgb, width: out_w, height: out_h })
+}
+
+/// Where the user is with linux-enable-ir-emitter, which the IR setup
+/// dialog and button are worded around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IrToolState {
+    NotInstalled,
+    NotConfigured,
+    Configured,
+}
+
+impl IrToolState {
+    fn detect() -> Self {
+        if !GlanceWindow::check_ir_emitter_installed() {
+            IrToolState::NotInstalled
+        } else if !GlanceWindow::check_ir_emitter_configured() {
+            IrToolState::NotConfigured
+        } else {
+            IrToolState::Configured
+        }
+    }
+    
+    /// What this state means and what to do next
+    fn guidance(&self) -> &'static str {
+        match self {
+            IrToolState::NotInstalled => "The IR emitter tool (linux-enable-ir-emitter) is not installed. \
+                Without it most IR cameras keep their LED off and only see darkness. \
+                Install it below, or from your distribution's packages.",
+            IrToolState::NotConfigured => "The IR emitter tool is installed but hasn't been calibrated for \
+                your camera yet. Run the configuration below and answer whether the \
+                IR LED flashes.",
+            IrToolState::Configured => "The IR emitter is installed and calibrated. Recalibrate if the \
+                IR camera stops working, e.g. after a kernel update.",
+        }
+    }
+    
+    fn button_label(&self) -> &'static str {
+        match self {
+            IrToolState::Configured => "Recalibrate IR Camera",
+            _ => "Set Up IR Camera",
+        }
+    }
+}
+
+/// "Hold still... 3, 2, 1" between the face locking on and the capture
+const CAPTURE_COUNTDOWN: Duration = Duration::from_secs(3);
+
+/// Expressions captured per camera with `recognition.capture_expressions`,
+/// as (pose label, instruction). Without it only the first is captured
+const EXPRESSIONS: [(&str, &str); 2] = [
+    ("center", "Look directly at the camera"),
+    ("smile", "Now smile slightly"),
+];
+
+/// Consecutive black results before the capture page says the lens is covered
+const COVERED_RESULTS: u32 = 3;
 
 mod imp {
     use super::*;
@@ -28,11 +162,25 @@ mod imp {
         // Capture state
         pub consecutive_good_frames: Cell<u32>,
         pub required_good_frames: Cell<u32>,
-        pub captured_encodings: RefCell<Vec<(Vec<f64>, String)>>,
+        /// When the face locked on and the pre-capture countdown began
+        pub capture_countdown: Cell<Option<Instant>>,
+        /// `recognition.min_enroll_quality`, read when capture starts
+        pub min_enroll_quality: Cell<f64>,
+        /// A toast about borderline frames was already shown this capture
+        pub borderline_noted: Cell<bool>,
+        /// Index into `EXPRESSIONS` being captured on the current camera
+        pub expression_step: Cell<usize>,
+        pub captured_encodings: RefCell<Vec<(Vec<f32>, String)>>,
+        /// Every good frame's encoding and quality score, for the post-capture check
+        pub quality_samples: RefCell<Vec<(Vec<f32>, f64)>>,
+        /// Recognition model the captured encodings came from
+        pub capture_model_id: RefCell<String>,
+        /// Thumbnail from the latest good frame, if enabled
+        pub capture_thumbnail: RefCell<Option<String>>,
         
         // Dual-camera capture state
-        pub ir_encodings: RefCell<Vec<(Vec<f64>, String)>>,
-        pub rgb_encodings: RefCell<Vec<(Vec<f64>, String)>>,
+        pub ir_encodings: RefCell<Vec<(Vec<f32>, String)>>,
+        pub rgb_encodings: RefCell<Vec<(Vec<f32>, String)>>,
         pub current_camera_type: RefCell<String>,  // "ir" or "rgb"
         pub completed_ir_capture: Cell<bool>,
         pub completed_rgb_capture: Cell<bool>,
@@ -65,8 +213,22 @@ mod imp {
         pub capture_spinner: RefCell<Option<gtk::Spinner>>,
         pub capture_face_icon: RefCell<Option<gtk::Image>>,
         pub btn_ir_setup: RefCell<Option<gtk::Button>>,
+        /// Main page buttons, insensitive while the face models load
+        pub main_buttons: RefCell<Option<gtk::Box>>,
+        /// "Loading face models..." row with its spinner, see
+        /// `set_models_loading`
+        pub models_loading_row: RefCell<Option<gtk::Box>>,
+        pub models_loading_spinner: RefCell<Option<gtk::Spinner>>,
         pub is_verifying: Cell<bool>,
-        pub frame_receiver: RefCell<Option<async_channel::Receiver<CameraFrame>>>,
+        pub is_quick_capturing: Cell<bool>,
+        /// Consecutive all-black results on the capture page
+        pub covered_results: Cell<u32>,
+        pub frame_receiver: RefCell<Option<async_channel::Receiver<FrameResult>>>,
+        /// Set to make the preview's camera thread close the device and exit
+        pub camera_stop: RefCell<Option<Arc<AtomicBool>>>,
+        /// Emitter kept on for the IR part of a capture, see
+        /// `camera.ir_emitter_during_preview`
+        pub ir_emitter: RefCell<Option<IrEmitter>>,
     }
     
     #[glib::object_subclass]
@@ -102,7 +264,14 @@ mod imp {
     }
     
     impl WidgetImpl for GlanceWindow {}
-    impl WindowImpl for GlanceWindow {}
+    impl WindowImpl for GlanceWindow {
+        // Closing mid-capture must turn the camera off now, not whenever the
+        // camera thread next notices its channel is gone
+        fn close_request(&self) -> glib::Propagation {
+            self.obj().stop_capture();
+            self.parent_close_request()
+        }
+    }
     impl ApplicationWindowImpl for GlanceWindow {}
     impl AdwApplicationWindowImpl for GlanceWindow {}
 }
@@ -114,6 +283,27 @@ glib::wrapper! {
                     gtk::Native, gtk::Root, gtk::ShortcutManager;
 }
 
+/// Present a dialog attached to `parent` so it opens centered over it (and on
+/// the same monitor), and hand focus back to the parent when it closes.
+pub fn present_dialog(parent: &impl IsA<gtk::Window>, dialog: &impl IsA<gtk::Window>) {
+    let parent = parent.as_ref();
+    let dialog = dialog.as_ref();
+    
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.set_destroy_with_parent(true);
+    
+    let parent_weak = parent.downgrade();
+    dialog.connect_close_request(move |_| {
+        if let Some(parent) = parent_weak.upgrade() {
+            parent.present();
+        }
+        glib::Propagation::Proceed
+    });
+    
+    dialog.present();
+}
+
 impl GlanceWindow {
     pub fn new(app: &GlanceApplication) -> Self {
         glib::Object::builder()
@@ -142,10 +332,20 @@ impl GlanceWindow {
             .icon_name("open-menu-symbolic")
             .build();
         let menu = gio::Menu::new();
+        menu.append(Some("_Quick Capture Current Conditions"), Some("win.quick-capture"));
+        menu.append(Some("Capture With _Mask"), Some("win.masked-capture"));
         menu.append(Some("_Preferences"), Some("app.preferences"));
         menu.append(Some("_About Glance"), Some("app.about"));
         menu_btn.set_menu_model(Some(&menu));
         header.pack_end(&menu_btn);
+        
+        let quick_capture_action = gio::ActionEntry::builder("quick-capture")
+            .activate(|window: &Self, _, _| window.quick_capture(false))
+            .build();
+        let masked_capture_action = gio::ActionEntry::builder("masked-capture")
+            .activate(|window: &Self, _, _| window.quick_capture(true))
+            .build();
+        self.add_action_entries([quick_capture_action, masked_capture_action]);
         main_toolbar.add_top_bar(&header);
         
         // Status page
@@ -212,6 +412,21 @@ impl GlanceWindow {
         btn_box.append(&btn_delete_face);
         btn_box.append(&btn_ir_setup);
         
+        // Shown while the face processor initializes in the background
+        let models_loading_row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(8)
+            .halign(gtk::Align::Center)
+            .visible(false)
+            .build();
+        let models_loading_spinner = gtk::Spinner::new();
+        models_loading_row.append(&models_loading_spinner);
+        models_loading_row.append(&gtk::Label::builder()
+            .label("Loading face models...")
+            .css_classes(["dim-label"])
+            .build());
+        
+        content_box.append(&models_loading_row);
         content_box.append(&lbl_camera_info);
         content_box.append(&lbl_registered_status);
         content_box.append(&btn_box);
@@ -292,9 +507,11 @@ impl GlanceWindow {
             .justify(gtk::Justification::Center)
             .build();
         
-        // Hidden camera picture (we still capture frames, just don't show them)
+        // Hidden unless `camera.privacy_preview` is on, and then only ever
+        // shows the pixelated picture
         let camera_picture = gtk::Picture::builder()
             .visible(false)
+            .height_request(120)
             .build();
         
         // Guidance label
@@ -324,6 +541,17 @@ impl GlanceWindow {
         navigation.add(&main_page);
         navigation.add(&capture_page);
         
+        // Leaving the capture page with the header back button or a swipe
+        // must stop the capture just like Cancel does
+        navigation.connect_popped(glib::clone!(
+            #[weak(rename_to = window)] self,
+            move |_, page| {
+                if page.tag().as_deref() == Some("capture") && window.imp().is_capturing.get() {
+                    window.stop_capture();
+                }
+            }
+        ));
+        
         toast_overlay.set_child(Some(&navigation));
         self.set_content(Some(&toast_overlay));
         
@@ -343,6 +571,9 @@ impl GlanceWindow {
         *imp.capture_spinner.borrow_mut() = Some(capture_spinner);
         *imp.capture_face_icon.borrow_mut() = Some(face_icon);
         *imp.btn_ir_setup.borrow_mut() = Some(btn_ir_setup);
+        *imp.main_buttons.borrow_mut() = Some(btn_box);
+        *imp.models_loading_row.borrow_mut() = Some(models_loading_row);
+        *imp.models_loading_spinner.borrow_mut() = Some(models_loading_spinner);
         
         self.set_title(Some("Glance"));
         self.set_default_size(500, 700);
@@ -360,7 +591,30 @@ impl GlanceWindow {
         self.update_registered_status();
     }
     
+    /// Show the models as loading: spinner on, and the main page buttons
+    /// insensitive so nobody starts a capture before the processor exists.
+    /// The buttons keep their own sensitivity for when loading ends
+    fn set_models_loading(&self, loading: bool) {
+        let imp = self.imp();
+        
+        if let Some(ref row) = *imp.models_loading_row.borrow() {
+            row.set_visible(loading);
+        }
+        if let Some(ref spinner) = *imp.models_loading_spinner.borrow() {
+            if loading {
+                spinner.start();
+            } else {
+                spinner.stop();
+            }
+        }
+        if let Some(ref buttons) = *imp.main_buttons.borrow() {
+            buttons.set_sensitive(!loading);
+        }
+    }
+    
     fn initialize_face_processor(&self) {
+        self.set_models_loading(true);
+        
         // Initialize face processor in background
         let (tx, rx) = async_channel::bounded::<Result<Arc<SharedFaceProcessor>, String>>(1);
         std::thread::spawn(move || {
@@ -371,7 +625,22 @@ impl GlanceWindow {
         glib::spawn_future_local(glib::clone!(
             #[weak(rename_to = window)] self,
             async move {
-                if let Ok(result) = rx.recv().await {
+                // A corrupt model can make dlib hang rather than fail; say so
+                // instead of waiting silently, but still take the processor
+                // if it turns up after all
+                let received = match glib::future_with_timeout(MODEL_LOAD_TIMEOUT, rx.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        eprintln!("[Models] Still loading after {:?}", MODEL_LOAD_TIMEOUT);
+                        let dialog = window.show_slow_model_load_dialog();
+                        let received = rx.recv().await;
+                        dialog.close();
+                        received
+                    }
+                };
+                window.set_models_loading(false);
+                
+                if let Ok(result) = received {
                     match result {
                         Ok(processor) => {
                             let imp = window.imp();
@@ -393,12 +662,49 @@ impl GlanceWindow {
         ));
     }
     
+    fn show_slow_model_load_dialog(&self) -> adw::MessageDialog {
+        let dialog = adw::MessageDialog::builder()
+            .heading("Model Loading Is Taking Too Long")
+            .body("The face models have not finished loading. A model file may be corrupt.")
+            .build();
+        
+        dialog.add_response("wait", "Keep Waiting");
+        dialog.add_response("verify", "Check Models");
+        dialog.add_response("redownload", "Re-download");
+        dialog.set_response_appearance("redownload", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("wait"));
+        dialog.set_close_response("wait");
+        
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = window)] self,
+            move |_, response| {
+                let models_dir = models::get_models_dir();
+                match response {
+                    "verify" => {
+                        let checked = models::check_model_file(&models::SHAPE_PREDICTOR, &models_dir)
+                            .and_then(|_| models::check_model_file(&models::FACE_RECOGNITION, &models_dir));
+                        match checked {
+                            Ok(()) => window.show_toast("Model files have the expected size; loading may just be slow"),
+                            Err(e) => window.show_toast(&e),
+                        }
+                    }
+                    "redownload" => match models::remove_models(&models_dir) {
+                        Ok(()) => window.start_model_download(),
+                        Err(e) => window.show_download_error_dialog(&e),
+                    },
+                    _ => {}
+                }
+            }
+        ));
+        
+        present_dialog(self, &dialog);
+        dialog
+    }
+    
     fn show_model_download_dialog(&self) {
         let dialog = adw::MessageDialog::builder()
             .heading("Face Models Required")
             .body("Glance needs to download face recognition models (~122 MB) to function.\n\nThis is a one-time download from dlib.net.")
-            .modal(true)
-            .transient_for(self)
             .build();
         
         dialog.add_response("cancel", "Cancel");
@@ -421,17 +727,20 @@ impl GlanceWindow {
             }
         ));
         
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     fn start_model_download(&self) {
+        if models::download_in_progress() {
+            self.wait_for_other_download();
+            return;
+        }
+        
         // Create progress dialog
         let dialog = adw::Window::builder()
             .title("Downloading Models")
             .default_width(400)
             .default_height(200)
-            .modal(true)
-            .transient_for(self)
             .deletable(false)
             .build();
         
@@ -475,7 +784,7 @@ impl GlanceWindow {
         content.append(&detail_label);
         
         dialog.set_content(Some(&content));
-        dialog.present();
+        present_dialog(self, &dialog);
         
         // Start download in background
         let (tx, rx) = async_channel::bounded::<Result<(), String>>(1);
@@ -500,6 +809,9 @@ impl GlanceWindow {
                             window.detect_camera();
                             window.update_registered_status();
                         }
+                        Err(e) if e == models::DOWNLOAD_IN_PROGRESS => {
+                            window.wait_for_other_download();
+                        }
                         Err(e) => {
                             window.show_download_error_dialog(&e);
                         }
@@ -509,12 +821,36 @@ impl GlanceWindow {
         ));
     }
     
+    /// Another window or `glance` process holds the download lock: wait for
+    /// it rather than racing it, then pick up whatever it left behind
+    fn wait_for_other_download(&self) {
+        self.show_toast("Download already in progress");
+        
+        glib::timeout_add_seconds_local(2, glib::clone!(
+            #[weak(rename_to = window)] self,
+            #[upgrade_or] glib::ControlFlow::Break,
+            move || {
+                if models::download_in_progress() {
+                    return glib::ControlFlow::Continue;
+                }
+                
+                if models::models_installed() {
+                    window.show_toast("Models downloaded successfully");
+                    window.initialize_face_processor();
+                    window.detect_camera();
+                    window.update_registered_status();
+                } else {
+                    window.show_model_download_dialog();
+                }
+                glib::ControlFlow::Break
+            }
+        ));
+    }
+    
     fn show_download_error_dialog(&self, error: &str) {
         let dialog = adw::MessageDialog::builder()
             .heading("Download Failed")
             .body(&format!("Failed to download models:\n\n{}\n\nYou can try again later or run install.sh manually.", error))
-            .modal(true)
-            .transient_for(self)
             .build();
         
         dialog.add_response("retry", "Retry");
@@ -533,7 +869,7 @@ impl GlanceWindow {
             }
         ));
         
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     fn detect_camera(&self) {
@@ -574,12 +910,10 @@ impl GlanceWindow {
             
             // Always show IR setup button - user may need to recalibrate after kernel updates
             if let Some(ref btn) = *imp.btn_ir_setup.borrow() {
+                let state = IrToolState::detect();
                 btn.set_visible(true);
-                if info.is_ir {
-                    btn.set_label("Recalibrate IR Camera");
-                } else {
-                    btn.set_label("Set Up IR Camera");
-                }
+                btn.set_label(state.button_label());
+                btn.set_tooltip_text(Some(state.guidance()));
             }
             
             *imp.camera_info.borrow_mut() = Some(info);
@@ -605,7 +939,15 @@ impl GlanceWindow {
         let imp = self.imp();
         let username = imp.current_user.borrow().clone();
         
-        if let Some(face_data) = load_face_data(&username) {
+        let face_data = match load_face_data(&username) {
+            Ok(face_data) => face_data,
+            Err(corrupt) => {
+                self.show_corrupt_status(&corrupt);
+                return;
+            }
+        };
+        
+        if let Some(face_data) = face_data {
             let ir_count = face_data.ir_encodings.len();
             let rgb_count = face_data.rgb_encodings.len();
             let legacy_count = face_data.encodings.len();
@@ -640,6 +982,8 @@ impl GlanceWindow {
                 btn.set_sensitive(true);
             }
             if let Some(ref page) = *imp.status_page.borrow() {
+                let avatar = face_data.thumbnail.as_deref().and_then(thumbnail::texture);
+                page.set_paintable(avatar.as_ref());
                 page.set_title("Glance Active");
                 page.set_description(Some(&format!(
                     "Your face is registered for {}.\nYou can update your face data anytime.",
@@ -648,7 +992,12 @@ impl GlanceWindow {
             }
         } else {
             if let Some(ref lbl) = *imp.lbl_registered_status.borrow() {
-                lbl.set_label("No face registered");
+                match find_unmigrated_legacy_data(&username) {
+                    Some(path) => lbl.set_label(&format!(
+                        "No face registered\nOld facerec data in {} — run `glance migrate` to use it",
+                        path.parent().unwrap_or(&path).display())),
+                    None => lbl.set_label("No face registered"),
+                }
             }
             if let Some(ref btn) = *imp.btn_add_face.borrow() {
                 btn.set_label("Add Face");
@@ -657,6 +1006,7 @@ impl GlanceWindow {
                 btn.set_sensitive(false);
             }
             if let Some(ref page) = *imp.status_page.borrow() {
+                page.set_paintable(None::<&gtk::gdk::Paintable>);
                 page.set_title("Glance");
                 page.set_description(Some(&format!(
                     "Set up facial recognition for {}\nto sign in quickly and securely.",
@@ -666,6 +1016,66 @@ impl GlanceWindow {
         }
     }
     
+    /// Main page state for face data that exists but can't be read: offer
+    /// to keep a copy and start over rather than looking unregistered
+    fn show_corrupt_status(&self, corrupt: &CorruptFaceData) {
+        let imp = self.imp();
+        
+        if let Some(ref lbl) = *imp.lbl_registered_status.borrow() {
+            lbl.set_label("Your face data is corrupted");
+        }
+        if let Some(ref btn) = *imp.btn_add_face.borrow() {
+            btn.set_label("Back Up and Re-register");
+        }
+        if let Some(ref btn) = *imp.btn_delete_face.borrow() {
+            btn.set_sensitive(true);
+        }
+        if let Some(ref page) = *imp.status_page.borrow() {
+            page.set_paintable(None::<&gtk::gdk::Paintable>);
+            page.set_title("Face Data Unreadable");
+            page.set_description(Some(&format!(
+                "{} could not be read:\n{}\n\nRepair the file by hand, or back it up and register again.",
+                corrupt.path.display(), corrupt.error
+            )));
+        }
+    }
+    
+    /// Ask before enrolling over corrupt face data; on confirmation the file
+    /// is moved aside first so nothing is lost
+    fn confirm_replace_corrupt(&self, corrupt: CorruptFaceData) {
+        let dialog = adw::MessageDialog::builder()
+            .heading("Your Face Data Is Corrupted")
+            .body(format!("{} could not be read ({}). It will be kept as a backup next to \
+                           the original before you register again.",
+                          corrupt.path.display(), corrupt.error))
+            .build();
+        
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("backup", "Back Up and Start Over");
+        dialog.set_response_appearance("backup", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("backup"));
+        dialog.set_close_response("cancel");
+        
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = window)] self,
+            move |_, response| {
+                if response != "backup" {
+                    return;
+                }
+                match set_aside_corrupt(&corrupt) {
+                    Ok(backup) => {
+                        window.show_toast(&format!("Kept a copy at {}", backup.display()));
+                        window.update_registered_status();
+                        window.start_capture(true);
+                    }
+                    Err(e) => window.show_toast(&format!("Error: {}", e)),
+                }
+            }
+        ));
+        
+        present_dialog(self, &dialog);
+    }
+    
     fn show_add_face_dialog(&self) {
         // For adding a face, we don't require authentication
         // This is safe because:
@@ -681,13 +1091,24 @@ impl GlanceWindow {
     fn show_delete_face_dialog(&self) {
         let dialog = adw::MessageDialog::builder()
             .heading("Remove Face Data?")
-            .body("This will remove your registered face. You'll need to re-register to use facial recognition.")
-            .modal(true)
-            .transient_for(self)
+            .body("This will remove your registered face. You'll need to re-register to use facial recognition.\n\nRemove Everything also deletes old, backup and system copies.")
             .build();
         
+        let username = self.imp().current_user.borrow().clone();
+        if let Some(texture) = load_face_data(&username).ok().flatten()
+            .and_then(|data| data.thumbnail)
+            .and_then(|t| thumbnail::texture(&t))
+        {
+            let picture = gtk::Picture::for_paintable(&texture);
+            picture.set_can_shrink(false);
+            picture.set_halign(gtk::Align::Center);
+            dialog.set_extra_child(Some(&picture));
+        }
+        
         dialog.add_response("cancel", "Cancel");
+        dialog.add_response("erase", "Remove Everything");
         dialog.add_response("delete", "Remove");
+        dialog.set_response_appearance("erase", adw::ResponseAppearance::Destructive);
         dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
         dialog.set_default_response(Some("cancel"));
         dialog.set_close_response("cancel");
@@ -695,13 +1116,15 @@ impl GlanceWindow {
         dialog.connect_response(None, glib::clone!(
             #[weak(rename_to = window)] self,
             move |_, response| {
-                if response == "delete" {
-                    window.delete_face_data();
+                match response {
+                    "delete" => window.delete_face_data(),
+                    "erase" => window.erase_face_data(),
+                    _ => {}
                 }
             }
         ));
         
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     fn delete_face_data(&self) {
@@ -719,9 +1142,45 @@ impl GlanceWindow {
         }
     }
     
+    /// Remove every copy Glance keeps, not just the current file
+    fn erase_face_data(&self) {
+        let username = self.imp().current_user.borrow().clone();
+        let report = crate::storage::purge_user(&username);
+        
+        if report.failed.is_empty() {
+            self.show_toast("All face data removed");
+        } else {
+            for (path, error) in &report.failed {
+                eprintln!("[Erase] Could not remove {:?}: {}", path, error);
+            }
+            self.show_toast(&format!("Removed {} file(s); {} need root (glance erase)",
+                                     report.removed.len(), report.failed.len()));
+        }
+        self.update_registered_status();
+    }
+    
     fn start_capture(&self, _multi_pose: bool) {
         let imp = self.imp();
         
+        // A second click before the first capture is torn down would open the
+        // camera twice
+        if imp.is_capturing.get() {
+            return;
+        }
+        
+        // The quick capture thread holds the camera
+        if imp.is_quick_capturing.get() {
+            self.show_toast("A capture is already running");
+            return;
+        }
+        
+        // Saving the new capture would overwrite it
+        let username = imp.current_user.borrow().clone();
+        if let Err(corrupt) = load_face_data(&username) {
+            self.confirm_replace_corrupt(corrupt);
+            return;
+        }
+        
         // Check if face processor is ready with models
         let can_capture = imp.face_processor.borrow()
             .as_ref()
@@ -736,8 +1195,18 @@ impl GlanceWindow {
         
         // Reset state - dual-camera capture for fallback support
         imp.is_capturing.set(true);
+        if let Some(ref btn) = *imp.btn_add_face.borrow() {
+            btn.set_sensitive(false);
+        }
         imp.consecutive_good_frames.set(0);
+        imp.capture_countdown.set(None);
+        imp.min_enroll_quality.set(GlanceConfig::load().recognition.min_enroll_quality);
+        imp.borderline_noted.set(false);
+        imp.expression_step.set(0);
         imp.captured_encodings.borrow_mut().clear();
+        imp.quality_samples.borrow_mut().clear();
+        imp.capture_model_id.borrow_mut().clear();
+        imp.capture_thumbnail.borrow_mut().take();
         imp.ir_encodings.borrow_mut().clear();
         imp.rgb_encodings.borrow_mut().clear();
         imp.completed_ir_capture.set(false);
@@ -747,6 +1216,7 @@ impl GlanceWindow {
         *imp.last_status.borrow_mut() = String::new();
         imp.status_stable_frames.set(0);
         imp.frame_count.set(0);
+        imp.covered_results.set(0);
         
         // Detect available cameras and decide capture strategy
         let has_ir = Camera::detect_ir_camera().is_some();
@@ -770,10 +1240,160 @@ impl GlanceWindow {
             nav.push_by_tag("capture");
         }
         
+        if *imp.current_camera_type.borrow() == "ir" {
+            self.start_ir_emitter();
+        }
+        
         self.update_pose_ui();
         self.start_camera_preview();
     }
     
+    /// Add one extra template for the current lighting without the guided
+    /// flow: average a few good frames from the current camera in the
+    /// background and append it to the user's existing face data. With
+    /// `masked`, the template is tagged as captured while wearing a mask.
+    fn quick_capture(&self, masked: bool) {
+        let imp = self.imp();
+        
+        if masked && !GlanceConfig::load().recognition.mask_friendly {
+            self.show_toast("Turn on Mask-Friendly Recognition in Preferences first");
+            return;
+        }
+        
+        if imp.is_capturing.get() || imp.is_quick_capturing.get() {
+            self.show_toast("A capture is already running");
+            return;
+        }
+        
+        let username = imp.current_user.borrow().clone();
+        match load_face_data(&username) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                self.show_toast("Register your face first");
+                return;
+            }
+            Err(_) => {
+                self.show_toast("Your face data is corrupted — see the main window");
+                return;
+            }
+        }
+        
+        let processor = match imp.face_processor.borrow().clone() {
+            Some(p) if p.can_encode() => p,
+            _ => {
+                self.show_toast("Face models are not loaded yet");
+                return;
+            }
+        };
+        
+        let Some(info) = imp.camera_info.borrow().clone() else {
+            self.show_toast("No camera available");
+            return;
+        };
+        let (device_id, is_ir) = (info.device_id, info.is_ir);
+        
+        imp.is_quick_capturing.set(true);
+        self.show_toast("Capturing — look at the camera");
+        
+        let (tx, rx) = async_channel::bounded::<Result<(Vec<f32>, String), String>>(1);
+        std::thread::spawn(move || {
+            let result = (|| {
+                let mut camera = Camera::new(device_id)?;
+                let mut encodings: Vec<Vec<f32>> = Vec::new();
+                let mut model_id = String::new();
+                
+                for _ in 0..QUICK_CAPTURE_MAX_FRAMES {
+                    let frame = camera.read_frame()?;
+                    let detection = processor.detect_and_encode(&frame.rgb_data, frame.width, frame.height);
+                    if let Some(encoding) = detection.encoding {
+                        encodings.push(encoding);
+                        model_id = detection.model_id.unwrap_or_default();
+                        if encodings.len() >= QUICK_CAPTURE_FRAMES {
+                            break;
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                
+                if encodings.len() < QUICK_CAPTURE_FRAMES {
+                    return Err("No face seen clearly enough".to_string());
+                }
+                
+                let mut average = vec![0.0; encodings[0].len()];
+                for encoding in &encodings {
+                    for (sum, value) in average.iter_mut().zip(encoding) {
+                        *sum += value / encodings.len() as f32;
+                    }
+                }
+                Ok((average, model_id))
+            })();
+            let _ = tx.send_blocking(result);
+        });
+        
+        glib::spawn_future_local(glib::clone!(
+            #[weak(rename_to = window)] self,
+            async move {
+                let Ok(result) = rx.recv().await else { return };
+                window.imp().is_quick_capturing.set(false);
+                
+                match result {
+                    Ok((encoding, model_id)) => window.append_quick_capture(encoding, &model_id, is_ir, masked),
+                    Err(e) => {
+                        eprintln!("[QuickCapture] Failed: {}", e);
+                        window.show_toast(&format!("Quick capture failed: {}", e));
+                    }
+                }
+            }
+        ));
+    }
+    
+    fn append_quick_capture(&self, encoding: Vec<f32>, model_id: &str, is_ir: bool, masked: bool) {
+        let username = self.imp().current_user.borrow().clone();
+        let Ok(Some(mut face_data)) = load_face_data(&username) else {
+            self.show_toast("Register your face first");
+            return;
+        };
+        
+        // A template that sits right on top of an existing one doesn't widen
+        // what we can match, it only slows down every auth attempt
+        let min_spread = GlanceConfig::load().recognition.min_template_spread;
+        if !masked && min_spread > 0.0 {
+            if let Some(distance) = face_data.closest_template(&encoding, is_ir, model_id) {
+                if distance < min_spread {
+                    eprintln!("[QuickCapture] Rejected: {:.3} from an existing template (min {:.3})",
+                        distance, min_spread);
+                    self.show_toast("Too similar to an existing capture — change the lighting or angle and try again");
+                    return;
+                }
+            }
+        }
+        
+        let pose = if masked {
+            MASKED_POSE.to_string()
+        } else {
+            format!("quick {}", chrono::Local::now().format("%Y-%m-%d %H:%M"))
+        };
+        if is_ir {
+            face_data.add_ir_encoding(encoding.clone(), &pose, model_id);
+        } else {
+            face_data.add_rgb_encoding(encoding.clone(), &pose, model_id);
+        }
+        face_data.add_encoding(encoding, &pose, model_id);
+        
+        match save_face_data(&face_data) {
+            Ok(()) => {
+                eprintln!("[QuickCapture] Added {} template '{}'", if is_ir { "IR" } else { "RGB" }, pose);
+                self.update_registered_status();
+                self.show_toast(if masked {
+                    "Added a masked template"
+                } else {
+                    "Added a template for the current conditions"
+                });
+            }
+            Err(e) => self.show_toast(&format!("Error saving: {}", e)),
+        }
+    }
+    
     fn update_pose_ui(&self) {
         let imp = self.imp();
         
@@ -800,91 +1420,162 @@ impl GlanceWindow {
         }
         
         imp.consecutive_good_frames.set(0);
+        imp.capture_countdown.set(None);
     }
     
     fn start_camera_preview(&self) {
         let imp = self.imp();
         let camera_info = imp.camera_info.borrow().clone();
         
-        if let Some(info) = camera_info {
-            let (frame_tx, frame_rx) = async_channel::bounded::<CameraFrame>(2);
-            *imp.frame_receiver.borrow_mut() = Some(frame_rx.clone());
+        let processor = imp.face_processor.borrow().clone();
+        
+        if let (Some(info), Some(processor)) = (camera_info, processor) {
+            // Single-slot buffer holding only the newest result: when the main
+            // thread falls behind, older results are replaced rather than queued
+            let (result_tx, result_rx) = async_channel::bounded::<FrameResult>(1);
+            *imp.frame_receiver.borrow_mut() = Some(result_rx.clone());
+            
+            // Replaces (and stops) the thread of any earlier preview, e.g.
+            // the IR one when switching to RGB
+            let stop = Arc::new(AtomicBool::new(false));
+            if let Some(previous) = imp.camera_stop.borrow_mut().replace(stop.clone()) {
+                previous.store(true, Ordering::Relaxed);
+            }
             
             let device_id = info.device_id;
+            let config = GlanceConfig::load();
+            if let Some(ref picture) = *imp.camera_picture.borrow() {
+                picture.set_paintable(None::<&gtk::gdk::Paintable>);
+                picture.set_visible(config.camera.privacy_preview);
+            }
+            let fps = config.camera.capture_fps.clamp(1, 60);
+            let store_thumbnail = config.recognition.store_thumbnail;
+            let privacy_preview = config.camera.privacy_preview;
+            let max_brightness = config.camera.max_brightness;
+            let frame_interval = std::time::Duration::from_millis(1000 / fps as u64);
             
-            // Camera thread - capture at ~20fps
+            // Camera thread - capture at the configured rate and run detection
+            // here, so dlib never blocks the main loop
             std::thread::spawn(move || {
                 if let Ok(mut camera) = Camera::new(device_id) {
-                    loop {
-                        match camera.read_frame() {
-                            Ok(frame) => {
-                                if frame_tx.send_blocking(frame).is_err() {
-                                    break;
-                                }
-                                // ~20fps capture rate
-                                std::thread::sleep(std::time::Duration::from_millis(50));
-                            }
+                    let mut frame_count: u32 = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        let started = std::time::Instant::now();
+                        let frame = match camera.read_frame() {
+                            Ok(frame) => frame,
                             Err(_) => break,
+                        };
+                        
+                        // Throttle: only process every 2nd frame to reduce CPU usage
+                        frame_count += 1;
+                        if frame_count % 2 == 0 {
+                            let detection = processor.detect_and_encode(&frame.rgb_data, frame.width, frame.height);
+                            let quality = detection.face_rect
+                                .filter(|_| detection.has_encoding())
+                                .map(|rect| quality::frame_quality(&frame.rgb_data, frame.width, frame.height, rect));
+                            let thumbnail = detection.face_rect
+                                .filter(|_| store_thumbnail && detection.has_encoding())
+                                .and_then(|rect| thumbnail::face_thumbnail(&frame.rgb_data, frame.width, frame.height, rect));
+                            
+                            let result = FrameResult {
+                                covered: !detection.face_found
+                                    && quality::is_covered(&frame.rgb_data, frame.width, frame.height),
+                                overexposed: max_brightness > 0.0
+                                    && quality::mean_brightness(&frame.rgb_data) > max_brightness,
+                                face_found: detection.face_found,
+                                can_encode: detection.has_encoding() || processor.can_encode(),
+                                encoding: detection.encoding,
+                                model_id: detection.model_id,
+                                quality,
+                                face_fraction: detection.face_rect
+                                    .map(|(_, _, w, _)| w as f64 / frame.width.max(1) as f64),
+                                thumbnail,
+                                preview: privacy_preview
+                                    .then(|| pixelate(&frame.rgb_data, frame.width, frame.height))
+                                    .flatten(),
+                            };
+                            
+                            if result_tx.force_send(result).is_err() {
+                                break;
+                            }
                         }
+                        // Zero the pixels now rather than after the sleep
+                        drop(frame);
+                        
+                        // Detection time counts towards the frame interval
+                        std::thread::sleep(frame_interval.saturating_sub(started.elapsed()));
                     }
                 }
             });
             
-            // Frame processing on main thread
+            // Widget updates on main thread
             glib::spawn_future_local(glib::clone!(
                 #[weak(rename_to = window)] self,
                 async move {
-                    while let Ok(frame) = frame_rx.recv().await {
+                    while let Ok(result) = result_rx.recv().await {
                         if !window.imp().is_capturing.get() {
                             break;
                         }
-                        window.process_frame(&frame);
+                        window.process_frame(result);
                     }
                 }
             ));
         }
     }
     
-    fn process_frame(&self, frame: &CameraFrame) {
+    fn process_frame(&self, result: FrameResult) {
         let imp = self.imp();
         
-        // Throttle: only process every 2nd frame to reduce CPU usage
+        if let Some(ref preview) = result.preview {
+            self.show_preview(preview);
+        }
+        
+        // Counts processed frames; the camera thread already skips every other one
         let frame_count = imp.frame_count.get() + 1;
         imp.frame_count.set(frame_count);
-        if frame_count % 2 != 0 {
-            return;
-        }
         
-        // Get face processor
-        let processor = match imp.face_processor.borrow().as_ref() {
-            Some(p) => p.clone(),
-            None => {
-                self.set_capture_status("Initializing...", false);
-                return;
+        let covered_results = if result.covered { imp.covered_results.get() + 1 } else { 0 };
+        imp.covered_results.set(covered_results);
+        if covered_results >= COVERED_RESULTS {
+            self.set_capture_status("Camera appears to be covered", false);
+            self.update_guidance("Open the camera's privacy shutter", "warning");
+            imp.consecutive_good_frames.set(0);
+            imp.capture_countdown.set(None);
+            if covered_results == COVERED_RESULTS {
+                self.show_toast("Camera appears to be covered");
             }
-        };
+            return;
+        }
         
-        // Detect face and get encoding
-        let result = processor.detect_and_encode(&frame.rgb_data, frame.width, frame.height);
+        // Washed-out frames make templates that fail at login
+        if result.overexposed {
+            self.set_capture_status("Too bright", result.face_found);
+            self.update_guidance("Too bright or backlit — face a window less directly", "warning");
+            imp.consecutive_good_frames.set(0);
+            imp.capture_countdown.set(None);
+            return;
+        }
         
         if !result.face_found {
             self.set_capture_status("Looking for you...", false);
             self.update_guidance("Position your face in front of the camera", "neutral");
             imp.consecutive_good_frames.set(0);
+            imp.capture_countdown.set(None);
             return;
         }
         
         // Check if we have encoding capability - required for capture
-        if !result.has_encoding() {
+        if result.encoding.is_none() {
             // Face found but no encoding yet - this happens sometimes
             self.set_capture_status("We see you!", true);
-            if !processor.can_encode() {
+            if !result.can_encode {
                 // Models not loaded - abort capture and show download dialog
                 self.update_guidance("Face models not loaded", "error");
                 imp.consecutive_good_frames.set(0);
+                imp.capture_countdown.set(None);
                 
                 // Stop capture after a moment and show download dialog
-                if frame_count > 10 {
+                if frame_count > 5 {
                     self.cancel_capture();
                     self.show_model_download_dialog();
                     return;
@@ -900,6 +1591,27 @@ impl GlanceWindow {
             return;
         }
         
+        // Detected, but too dim or flat to enroll from; such frames tend to
+        // produce templates that later fail at login
+        if result.quality.is_some_and(|q| q < imp.min_enroll_quality.get()) {
+            self.set_capture_status("We see you!", true);
+            self.update_guidance("Move into better light", "warning");
+            imp.consecutive_good_frames.set(0);
+            imp.capture_countdown.set(None);
+            return;
+        }
+        
+        if let Some(ref model_id) = result.model_id {
+            *imp.capture_model_id.borrow_mut() = model_id.clone();
+        }
+        
+        if let (Some(encoding), Some(score)) = (result.encoding.as_ref(), result.quality) {
+            imp.quality_samples.borrow_mut().push((encoding.clone(), score));
+        }
+        if let Some(thumbnail) = result.thumbnail {
+            *imp.capture_thumbnail.borrow_mut() = Some(thumbnail);
+        }
+        
         // Good frame with encoding - increment counter
         let good_frames = imp.consecutive_good_frames.get() + 1;
         imp.consecutive_good_frames.set(good_frames);
@@ -907,6 +1619,14 @@ impl GlanceWindow {
         // Mark face as found
         self.set_capture_status("Hold still...", true);
         
+        // Passed the gate; say how well, so marginal frames get improved
+        // before they end up in the template
+        let grade = FrameGrade::of(result.quality.unwrap_or(1.0), result.face_fraction.unwrap_or(1.0));
+        if grade.is_borderline() && !imp.borderline_noted.get() {
+            imp.borderline_noted.set(true);
+            self.show_toast("Frames are usable but borderline; better light or a closer face makes a stronger template");
+        }
+        
         let required = imp.required_good_frames.get();
         let progress = (good_frames as f64) / (required as f64);
         
@@ -914,35 +1634,55 @@ impl GlanceWindow {
             bar.set_fraction(progress.min(1.0));
         }
         
-        // Update title based on progress - directly, no debouncing
-        if let Some(ref lbl) = *imp.lbl_capture_title.borrow() {
-            if good_frames >= required {
-                lbl.set_label("That's you!");
-            } else if progress >= 0.6 {
-                lbl.set_label("Almost there...");
-            } else {
-                lbl.set_label("Hold still...");
+        if good_frames < required {
+            // Update title based on progress - directly, no debouncing
+            if let Some(ref lbl) = *imp.lbl_capture_title.borrow() {
+                lbl.set_label(if progress >= 0.6 { "Almost there..." } else { "Hold still..." });
             }
+            let (text, style) = grade.guidance();
+            self.update_guidance(text, style);
+            return;
         }
         
-        self.update_guidance("Perfect! Stay still...", "success");
-        
-        // Check if we've captured enough frames
-        if good_frames >= required {
-            if let Some(encoding) = result.encoding {
-                self.on_pose_captured(encoding);
+        // Locked on: count down so the user knows when to hold the pose,
+        // then capture from the frame at the end of it
+        let started = imp.capture_countdown.get().unwrap_or_else(|| {
+            let now = Instant::now();
+            imp.capture_countdown.set(Some(now));
+            now
+        });
+        let remaining = CAPTURE_COUNTDOWN.saturating_sub(started.elapsed());
+        if !remaining.is_zero() {
+            // Round up so the count reads 3, 2, 1 rather than 2, 1, 0
+            let seconds = remaining.as_millis().div_ceil(1000);
+            if let Some(ref lbl) = *imp.lbl_capture_title.borrow() {
+                lbl.set_label(&format!("Hold still... {}", seconds));
             }
+            self.update_guidance("Keep looking at the camera", "success");
+            return;
+        }
+        
+        imp.capture_countdown.set(None);
+        if let Some(ref lbl) = *imp.lbl_capture_title.borrow() {
+            lbl.set_label("Capturing now");
+        }
+        if let Some(encoding) = result.encoding {
+            self.on_pose_captured(encoding);
         }
     }
     
-    fn on_pose_captured(&self, encoding: Vec<f64>) {
+    fn on_pose_captured(&self, encoding: Vec<f32>) {
         let imp = self.imp();
         
         let current_type = imp.current_camera_type.borrow().clone();
+        let captured = if current_type == "ir" { &imp.ir_encodings } else { &imp.rgb_encodings };
+        self.store_expression(captured, encoding);
+        if self.next_expression() {
+            return;
+        }
         
         // Store encoding based on current camera type
         if current_type == "ir" {
-            imp.ir_encodings.borrow_mut().push((encoding.clone(), "center".to_string()));
             imp.completed_ir_capture.set(true);
             eprintln!("[Capture] IR camera capture complete");
             
@@ -953,6 +1693,7 @@ impl GlanceWindow {
                 // Stop current capture
                 imp.is_capturing.set(false);
                 *imp.frame_receiver.borrow_mut() = None;
+                self.stop_ir_emitter();
                 
                 // Switch to RGB camera
                 *imp.camera_info.borrow_mut() = Some(rgb_cam);
@@ -960,7 +1701,9 @@ impl GlanceWindow {
                 
                 // Reset capture state for RGB
                 imp.consecutive_good_frames.set(0);
+                imp.capture_countdown.set(None);
                 imp.frame_count.set(0);
+                imp.covered_results.set(0);
                 imp.is_capturing.set(true);
                 
                 // Update UI
@@ -977,13 +1720,13 @@ impl GlanceWindow {
                 return;
             }
         } else if current_type == "rgb" {
-            imp.rgb_encodings.borrow_mut().push((encoding.clone(), "center".to_string()));
             imp.completed_rgb_capture.set(true);
             eprintln!("[Capture] RGB camera capture complete");
         }
         
         // Also store in legacy encodings for backwards compatibility
-        imp.captured_encodings.borrow_mut().push((encoding, "center".to_string()));
+        let this_camera = captured.borrow().clone();
+        imp.captured_encodings.borrow_mut().extend(this_camera);
         
         // If we've captured from all available cameras, save
         let has_ir = Camera::detect_ir_camera().is_some();
@@ -997,16 +1740,52 @@ impl GlanceWindow {
         }
     }
     
-    fn save_captured_face(&self) {
+    /// Keep `encoding` under the current expression's pose. A later
+    /// expression within `min_template_spread` of one already captured on
+    /// this camera adds nothing and is dropped, as quick capture does
+    fn store_expression(&self, captured: &RefCell<Vec<(Vec<f32>, String)>>, encoding: Vec<f32>) {
+        let (pose, _) = EXPRESSIONS[self.imp().expression_step.get()];
+        let min_spread = GlanceConfig::load().recognition.min_template_spread;
+        let closest = captured.borrow().iter()
+            .map(|(other, _)| encoding_distance(other, &encoding))
+            .min_by(|a, b| a.total_cmp(b));
+        if let Some(distance) = closest.filter(|d| *d < min_spread) {
+            eprintln!("[Capture] '{}' is {:.3} from an earlier expression (min {:.3}), not stored",
+                      pose, distance, min_spread);
+            return;
+        }
+        captured.borrow_mut().push((encoding, pose.to_string()));
+    }
+    
+    /// With `capture_expressions`, move on to the next expression on the
+    /// same camera. False once this camera has captured them all
+    fn next_expression(&self) -> bool {
         let imp = self.imp();
+        let next = imp.expression_step.get() + 1;
+        if !GlanceConfig::load().recognition.capture_expressions || next >= EXPRESSIONS.len() {
+            imp.expression_step.set(0);
+            return false;
+        }
         
-        imp.is_capturing.set(false);
-        *imp.frame_receiver.borrow_mut() = None;
-        
-        // Stop spinner
-        if let Some(ref spinner) = *imp.capture_spinner.borrow() {
-            spinner.stop();
+        imp.expression_step.set(next);
+        imp.consecutive_good_frames.set(0);
+        imp.capture_countdown.set(None);
+        let (pose, instruction) = EXPRESSIONS[next];
+        eprintln!("[Capture] Next expression: {}", pose);
+        self.set_capture_status("Got it!", true);
+        if let Some(ref bar) = *imp.capture_progress.borrow() {
+            bar.set_fraction(0.0);
+        }
+        if let Some(ref lbl) = *imp.lbl_pose_instruction.borrow() {
+            lbl.set_label(instruction);
         }
+        true
+    }
+    
+    fn save_captured_face(&self) {
+        let imp = self.imp();
+        
+        self.stop_capture();
         
         // Update UI to show success
         self.set_capture_status("All done!", true);
@@ -1015,39 +1794,146 @@ impl GlanceWindow {
         let ir_encodings = imp.ir_encodings.borrow().clone();
         let rgb_encodings = imp.rgb_encodings.borrow().clone();
         let legacy_encodings = imp.captured_encodings.borrow().clone();
+        let model_id = imp.capture_model_id.borrow().clone();
         
         // Create face data with both IR and RGB encodings
         let mut face_data = FaceData::new(&username);
         
         // Add IR encodings
         for (encoding, pose) in &ir_encodings {
-            face_data.add_ir_encoding(encoding.clone(), pose);
+            face_data.add_ir_encoding(encoding.clone(), pose, &model_id);
         }
         face_data.ir_captured = !ir_encodings.is_empty();
         
         // Add RGB encodings
         for (encoding, pose) in &rgb_encodings {
-            face_data.add_rgb_encoding(encoding.clone(), pose);
+            face_data.add_rgb_encoding(encoding.clone(), pose, &model_id);
         }
         face_data.rgb_captured = !rgb_encodings.is_empty();
+        // The RGB pass runs last, so this is a colour picture when there is one
+        face_data.thumbnail = imp.capture_thumbnail.borrow_mut().take();
         
         // Also add to legacy encodings for backwards compatibility
         for (encoding, pose) in legacy_encodings {
-            face_data.add_encoding(encoding, &pose);
+            face_data.add_encoding(encoding, &pose, &model_id);
+        }
+        
+        let recognition = GlanceConfig::load().recognition;
+        if recognition.average_encodings {
+            face_data.average_templates(recognition.keep_raw_encodings);
         }
         
         let total_encodings = face_data.ir_encodings.len() + face_data.rgb_encodings.len();
         eprintln!("[Save] IR encodings: {}, RGB encodings: {}", 
                   face_data.ir_encodings.len(), face_data.rgb_encodings.len());
         
-        // Save
-        let save_result = save_face_data(&face_data);
-        
         // Return to main page first
         if let Some(ref nav) = *imp.navigation.borrow() {
             nav.pop();
         }
         
+        // The same face under two accounts lets one person log in as both
+        if let Some(duplicate) = find_duplicate_face(
+            &face_data, recognition.ir_tolerance, recognition.rgb_tolerance
+        ) {
+            eprintln!("[Save] Face matches user '{}' (distance: {:.3})",
+                      duplicate.username, duplicate.distance);
+            self.show_duplicate_face_dialog(face_data, duplicate, recognition.allow_duplicate_faces);
+            return;
+        }
+        
+        self.check_enrollment_quality(face_data);
+    }
+    
+    fn show_duplicate_face_dialog(&self, face_data: FaceData, duplicate: DuplicateFace, allowed: bool) {
+        let dialog = adw::MessageDialog::builder()
+            .heading("Face Already Registered")
+            .build();
+        
+        if !allowed {
+            dialog.set_body(&format!(
+                "This face is already registered for user '{}'. Registering the same \
+                face under another account is not allowed on this system, so nothing \
+                was saved.",
+                duplicate.username
+            ));
+            dialog.add_response("ok", "OK");
+            dialog.set_default_response(Some("ok"));
+            present_dialog(self, &dialog);
+            return;
+        }
+        
+        dialog.set_body(&format!(
+            "This face matches the one registered for user '{}'. Saving it means \
+            either person can log in to both accounts.",
+            duplicate.username
+        ));
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("save", "Save Anyway");
+        dialog.set_response_appearance("save", adw::ResponseAppearance::Destructive);
+        dialog.set_default_response(Some("cancel"));
+        dialog.set_close_response("cancel");
+        
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = window)] self,
+            move |_, response| {
+                if response == "save" {
+                    window.check_enrollment_quality(face_data.clone());
+                }
+            }
+        ));
+        
+        present_dialog(self, &dialog);
+    }
+    
+    fn check_enrollment_quality(&self, face_data: FaceData) {
+        let imp = self.imp();
+        
+        // Catch templates built only from marginal frames before they fail at login
+        let assessment = EnrollmentQuality::assess(&imp.quality_samples.borrow());
+        if let Some(assessment) = assessment {
+            eprintln!("[Save] Frame quality: {:.2}, encoding spread: {:.3}",
+                      assessment.mean_frame_quality, assessment.spread);
+            if assessment.is_poor() {
+                self.show_low_quality_dialog(face_data);
+                return;
+            }
+        }
+        
+        self.store_face_data(&face_data);
+    }
+    
+    fn show_low_quality_dialog(&self, face_data: FaceData) {
+        let dialog = adw::MessageDialog::builder()
+            .heading("Lighting Was Poor")
+            .body("The captured images were dim or inconsistent, so facial recognition \
+                  may fail at login. Re-try in better lighting for better results?")
+            .build();
+        
+        dialog.add_response("keep", "Keep Anyway");
+        dialog.add_response("retry", "Re-try");
+        dialog.set_response_appearance("retry", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("retry"));
+        dialog.set_close_response("keep");
+        
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = window)] self,
+            move |_, response| {
+                if response == "retry" {
+                    window.start_capture(true);
+                } else {
+                    window.store_face_data(&face_data);
+                }
+            }
+        ));
+        
+        present_dialog(self, &dialog);
+    }
+    
+    fn store_face_data(&self, face_data: &FaceData) {
+        // Save
+        let save_result = save_face_data(face_data);
+        
         self.update_registered_status();
         
         // Show success dialog with instructions
@@ -1092,13 +1978,11 @@ impl GlanceWindow {
         let dialog = adw::MessageDialog::builder()
             .heading("You're All Set")
             .body(&body)
-            .modal(true)
-            .transient_for(self)
             .build();
         
         dialog.add_response("ok", "OK");
         dialog.set_default_response(Some("ok"));
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     fn show_success_dialog(&self, pose_count: usize) {
@@ -1111,13 +1995,11 @@ impl GlanceWindow {
                 Note: PAM must be configured via install.sh for authentication to work.",
                 pose_count
             ))
-            .modal(true)
-            .transient_for(self)
             .build();
         
         dialog.add_response("close", "Got it!");
         dialog.set_default_response(Some("close"));
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     /// Update the capture status (title and icon visual state) with debouncing
@@ -1153,6 +2035,17 @@ impl GlanceWindow {
         }
     }
     
+    fn show_preview(&self, preview: &PreviewImage) {
+        let Some(ref picture) = *self.imp().camera_picture.borrow() else {
+            return;
+        };
+        let bytes = glib::Bytes::from(&preview.rgb);
+        let texture = gtk::gdk::MemoryTexture::new(preview.width as i32, preview.height as i32,
+                                                   gtk::gdk::MemoryFormat::R8g8b8, &bytes,
+                                                   preview.width as usize * 3);
+        picture.set_paintable(Some(&texture));
+    }
+    
     fn update_guidance(&self, text: &str, style: &str) {
         let imp = self.imp();
         
@@ -1183,26 +2076,79 @@ impl GlanceWindow {
         }
     }
     
-    fn cancel_capture(&self) {
+    /// End the capture session: stop the camera thread and re-enable the
+    /// "Add Face" button
+    fn stop_capture(&self) {
         let imp = self.imp();
         
         imp.is_capturing.set(false);
         *imp.frame_receiver.borrow_mut() = None;
+        if let Some(stop) = imp.camera_stop.borrow_mut().take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.stop_ir_emitter();
         
         // Stop spinner
         if let Some(ref spinner) = *imp.capture_spinner.borrow() {
             spinner.stop();
         }
         
-        if let Some(ref nav) = *imp.navigation.borrow() {
+        if let Some(ref btn) = *imp.btn_add_face.borrow() {
+            btn.set_sensitive(imp.camera_info.borrow().is_some());
+        }
+    }
+    
+    /// Turn the IR emitter on for the whole IR capture, if enabled in the
+    /// config. The camera thread then never toggles it per open.
+    fn start_ir_emitter(&self) {
+        let imp = self.imp();
+        if !GlanceConfig::load().camera.ir_emitter_during_preview || imp.ir_emitter.borrow().is_some() {
+            return;
+        }
+        let Some(info) = imp.camera_info.borrow().clone() else {
+            return;
+        };
+        if !IrEmitter::is_installed() {
+            eprintln!("[IR] Emitter tool not installed, capturing without it");
+            return;
+        }
+        
+        let device = format!("/dev/video{}", info.device_id);
+        let mut emitter = IrEmitter::new(&device);
+        if let Some(name) = GlanceConfig::load().ir_emitter.profiles.get(&device) {
+            emitter.set_config(IrEmitter::find_config(name));
+        }
+        match emitter.enable() {
+            Ok(()) if emitter.is_running() => {
+                eprintln!("[IR] Emitter on for video{}", info.device_id);
+                *imp.ir_emitter.borrow_mut() = Some(emitter);
+            }
+            Ok(()) => eprintln!("[IR] Emitter did not start for video{}", info.device_id),
+            Err(e) => eprintln!("[IR] Failed to start emitter: {}", e),
+        }
+    }
+    
+    fn stop_ir_emitter(&self) {
+        if let Some(mut emitter) = self.imp().ir_emitter.borrow_mut().take() {
+            if let Err(e) = emitter.disable() {
+                eprintln!("[IR] Failed to stop emitter: {}", e);
+            }
+        }
+    }
+    
+    fn cancel_capture(&self) {
+        self.stop_capture();
+        
+        if let Some(ref nav) = *self.imp().navigation.borrow() {
             nav.pop();
         }
     }
     
     fn show_ir_setup(&self) {
         // Check current status
-        let ir_tool_installed = Self::check_ir_emitter_installed();
-        let ir_configured = Self::check_ir_emitter_configured();
+        let state = IrToolState::detect();
+        let ir_tool_installed = state != IrToolState::NotInstalled;
+        let ir_configured = state == IrToolState::Configured;
         let pam_configured = Self::check_pam_ir_configured();
         
         // Create dialog
@@ -1210,8 +2156,6 @@ impl GlanceWindow {
             .title("IR Camera Setup")
             .default_width(550)
             .default_height(500)
-            .modal(true)
-            .transient_for(self)
             .build();
         
         let toolbar = adw::ToolbarView::new();
@@ -1282,8 +2226,23 @@ impl GlanceWindow {
         );
         status_box.append(&pam_row);
         
+        let guidance = gtk::Label::builder()
+            .label(state.guidance())
+            .wrap(true)
+            .halign(gtk::Align::Start)
+            .margin_top(8)
+            .css_classes(["dim-label"])
+            .build();
+        status_box.append(&guidance);
+        
         content.append(&status_box);
         
+        // Profile Section - one per `configure` run, pick which PAM uses
+        let profiles = IrEmitter::list_configs();
+        if ir_tool_installed && !profiles.is_empty() {
+            content.append(&Self::create_ir_profile_section(&profiles));
+        }
+        
         // Actions Section
         let actions_box = gtk::Box::builder()
             .orientation(gtk::Orientation::Vertical)
@@ -1411,7 +2370,7 @@ impl GlanceWindow {
         scroll.set_child(Some(&content));
         toolbar.set_content(Some(&scroll));
         dialog.set_content(Some(&toolbar));
-        dialog.present();
+        present_dialog(self, &dialog);
     }
     
     fn create_status_row(label: &str, status: &str, is_ok: bool) -> gtk::Box {
@@ -1491,6 +2450,67 @@ impl GlanceWindow {
         false
     }
     
+    /// Dropdown of the saved emitter profiles for the IR camera; the choice
+    /// is stored in `ir_emitter.profiles` for PAM to pass to the tool
+    fn create_ir_profile_section(profiles: &[std::path::PathBuf]) -> gtk::Box {
+        let device = Camera::detect_ir_camera()
+            .map(|c| format!("/dev/video{}", c.device_id))
+            .unwrap_or_else(|| "/dev/video2".to_string());
+        let names: Vec<String> = profiles
+            .iter()
+            .filter_map(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .collect();
+        
+        let section = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(8)
+            .margin_top(16)
+            .build();
+        
+        let title = gtk::Label::builder()
+            .label("Emitter Profile")
+            .css_classes(["heading"])
+            .halign(gtk::Align::Start)
+            .build();
+        section.append(&title);
+        
+        let mut labels = vec!["Automatic"];
+        labels.extend(names.iter().map(String::as_str));
+        let dropdown = gtk::DropDown::from_strings(&labels);
+        let current = GlanceConfig::load().ir_emitter.profiles.get(&device).cloned();
+        let selected = current
+            .and_then(|name| names.iter().position(|n| *n == name))
+            .map_or(0, |i| i + 1);
+        dropdown.set_selected(selected as u32);
+        
+        dropdown.connect_selected_notify(move |dd| {
+            let mut config = GlanceConfig::load();
+            match (dd.selected() as usize).checked_sub(1).and_then(|i| names.get(i)) {
+                Some(name) => {
+                    config.ir_emitter.profiles.insert(device.clone(), name.clone());
+                }
+                None => {
+                    config.ir_emitter.profiles.remove(&device);
+                }
+            }
+            if let Err(e) = config.save() {
+                eprintln!("[IR] Failed to save emitter profile: {}", e);
+            }
+        });
+        section.append(&dropdown);
+        
+        let note = gtk::Label::builder()
+            .label("Each calibration saves a profile. Automatic lets the tool choose.")
+            .wrap(true)
+            .halign(gtk::Align::Start)
+            .css_classes(["dim-label"])
+            .build();
+        section.append(&note);
+        
+        section
+    }
+    
     fn check_pam_ir_configured() -> bool {
         // Check if our PAM module is installed and enabled
         if let Ok(content) = std::fs::read_to_string("/etc/pam.d/common-auth") {
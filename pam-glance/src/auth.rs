@@ -1,15 +1,29 @@
-use crate::camera::{SmartCamera, CameraType, CameraInfo, detect_cameras_fast};
+use crate::background::Background;
+use crate::camera::{SmartCamera, CameraInfo, CameraType, DetectionRoi, FrameSource, DEFAULT_MAX_BRIGHTNESS, DEFAULT_MAX_FRAME_DIMENSION, DEFAULT_PROBE_CACHE_TTL, camera_info_for_device, detect_cameras_fast_cached, frame_fingerprint, invalidate_probe_cache, is_obstructed, is_overexposed, pin_configured_devices, prioritize_cameras, resolve_device};
 use crate::config::GlanceConfig;
-use crate::face::{FaceRecognizer, load_all_faces};
+use crate::embedder::FaceEmbedder;
+use crate::face::{ComputeDevice, DistanceMetric, FaceRecognizer, MultiMatchPolicy, TemplateFilter, compare_encoding, configured_models_dir, default_models_dir, load_all_faces, model_id};
 use crate::ir_emitter::IrEmitter;
+use crate::safe_mode;
+use crate::wipe::{Templates, wipe_mat};
 
 use anyhow::Result;
 use log::{info, debug, warn, error};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
+/// Which limit ended an attempt that never matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutReason {
+    /// `timeout` (with any extension) ran out
+    WallClock,
+    /// `max_auth_frames` frames were processed
+    FrameLimit,
+}
+
 #[derive(Debug, Clone)]
 pub enum AuthResult {
     Success {
@@ -19,8 +33,105 @@ pub enum AuthResult {
     },
     NoFaceDetected,
     NoMatch,
+    /// Nothing enrolled that this attempt could compare against, so the
+    /// cameras weren't opened, see `has_comparable_templates`
+    NotEnrolled,
+    /// Skipped because cameras kept failing to open, see `safe_mode.rs`
+    SafeMode,
+    /// Every camera returned flat black frames, most likely a closed shutter
+    CameraObstructed,
+    /// Every camera's frames were brighter than `max_brightness`, e.g. a
+    /// bright window behind the user
+    Overexposed,
+    /// `strict_camera_match` is on and no available camera is of a type the
+    /// user enrolled on. `enrolled` is that type, `None` if no template
+    /// records one
+    CameraMismatch { enrolled: Option<CameraType> },
     Error(String),
-    Timeout,
+    /// Gave up without a match. The counts cover every camera tried, so
+    /// "saw a face 12 times" and "never saw a face" can be told apart
+    Timeout {
+        reason: TimeoutReason,
+        frames_processed: u32,
+        faces_detected: u32,
+    },
+}
+
+impl AuthResult {
+    /// Short explanation for the user when face auth didn't succeed, shown
+    /// before falling back to the password prompt
+    pub fn user_message(&self) -> Option<&'static str> {
+        match self {
+            AuthResult::Success { .. } => None,
+            AuthResult::NoFaceDetected => Some("Face authentication: no face detected"),
+            AuthResult::NoMatch => Some("Face authentication: face not recognized"),
+            AuthResult::NotEnrolled => Some("Face authentication: no face enrolled"),
+            AuthResult::SafeMode => Some("Face authentication paused: camera keeps failing"),
+            AuthResult::CameraObstructed => Some("Face authentication: camera is covered"),
+            AuthResult::Overexposed =>
+                Some("Face authentication: too bright or backlit, face a window less directly"),
+            AuthResult::CameraMismatch { enrolled: Some(CameraType::Infrared) } =>
+                Some("Face authentication: enrolled on IR, only RGB available"),
+            AuthResult::CameraMismatch { enrolled: Some(CameraType::Rgb) } =>
+                Some("Face authentication: enrolled on RGB, only IR available"),
+            AuthResult::CameraMismatch { .. } =>
+                Some("Face authentication: no enrollment for this camera type, re-enroll"),
+            AuthResult::Timeout { reason: TimeoutReason::WallClock, .. } =>
+                Some("Face authentication: timed out"),
+            AuthResult::Timeout { reason: TimeoutReason::FrameLimit, .. } =>
+                Some("Face authentication: gave up after the frame limit"),
+            AuthResult::Error(_) => Some("Face authentication unavailable"),
+        }
+    }
+    
+    /// Stable name for the kind of outcome, without any details
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthResult::Success { .. } => "success",
+            AuthResult::NoFaceDetected => "no_face",
+            AuthResult::NoMatch => "no_match",
+            AuthResult::NotEnrolled => "not_enrolled",
+            AuthResult::SafeMode => "safe_mode",
+            AuthResult::CameraObstructed => "camera_obstructed",
+            AuthResult::Overexposed => "overexposed",
+            AuthResult::CameraMismatch { .. } => "camera_mismatch",
+            AuthResult::Timeout { .. } => "timeout",
+            AuthResult::Error(_) => "error",
+        }
+    }
+    
+    /// For a timeout, what was seen before giving up, e.g. "gave up after
+    /// 30 frames, saw a face 12 times but never matched"
+    pub fn timeout_summary(&self) -> Option<String> {
+        let AuthResult::Timeout { reason, frames_processed, faces_detected } = self else {
+            return None;
+        };
+        let limit = match reason {
+            TimeoutReason::WallClock => "time ran out",
+            TimeoutReason::FrameLimit => "gave up",
+        };
+        let seen = if *faces_detected == 0 {
+            "never saw a face".to_string()
+        } else {
+            format!("saw a face {} times but never matched", faces_detected)
+        };
+        Some(format!("{} after {} frames, {}", limit, frames_processed, seen))
+    }
+}
+
+/// Where per-user enrollments are read from, besides `data_dir` and
+/// /var/lib/glance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserData {
+    /// `$HOME`, ahead of the system locations. For tools running as the
+    /// user whose face they check
+    Env,
+    /// An account's home from the passwd database, after the system
+    /// locations so it never shadows them. Only files `uid` owns count. For
+    /// the PAM module, whose environment may be a `su` caller's
+    Account { home: PathBuf, uid: u32 },
+    /// The system locations only
+    None,
 }
 
 #[derive(Debug, Clone)]
@@ -28,17 +139,86 @@ pub struct AuthConfig {
     pub timeout: Duration,
     pub prefer_ir: bool,
     pub data_dir: PathBuf,
+    pub user_data: UserData,
     pub models_dir: PathBuf,
     pub tolerance: f64,
     pub ir_tolerance: f64,
     pub rgb_tolerance: f64,
     pub target_user: Option<String>,
     pub min_brightness: f64,
+    /// Mean brightness (0 – 255) above which a frame is skipped as washed
+    /// out. 0 = never
+    pub max_brightness: f64,
     pub enable_ir_emitter: bool,
     pub ir_device: String,
+    /// Emitter profile chosen per device, see `IrEmitterConfig::profiles`
+    pub ir_profiles: BTreeMap<String, String>,
     pub rgb_device: String,
     pub max_frames_per_camera: u32,
+    /// Frames processed across all cameras before giving up, even if time
+    /// remains
+    pub max_auth_frames: u32,
+    /// Camera passes repeated after power-cycling the IR emitter when a pass
+    /// saw no face, see `authenticate_inner`
+    pub internal_retries: u32,
     pub frame_delay_ms: u64,
+    pub multi_face: bool,
+    pub use_gpu: bool,
+    pub required_matches: u32,
+    pub encoding_grace: u32,
+    pub rotation_search: bool,
+    pub detect_obstruction: bool,
+    /// See `camera::verify_camera_type`
+    pub grayscale_threshold: f64,
+    /// Reuse camera detection results this recent, see `camera::detect_cameras_fast_cached`
+    pub probe_cache_ttl: Duration,
+    pub metric: DistanceMetric,
+    /// See `FaceRecognizer::set_multi_match_policy`
+    pub multi_match_policy: MultiMatchPolicy,
+    pub mask_friendly: bool,
+    /// Upper bound on `timeout` after extensions for a face that's getting
+    /// closer to a match, see `Deadline`. At or below `timeout` (the default)
+    /// = no extension
+    pub max_extended_timeout: Duration,
+    /// See `FaceRecognizer::set_template_penalty`
+    pub template_tolerance_penalty: f64,
+    /// Count this attempt's outcome in the local stats file, see `stats.rs`
+    pub record_stats: bool,
+    /// Run the configured feedback hooks, see `hooks.rs`. Only the PAM
+    /// module sets this
+    pub run_hooks: bool,
+    /// Time between the success signal and releasing the camera, see
+    /// `signal_success`
+    pub success_hold: Duration,
+    /// Attempts in a row without any camera opening before face auth is
+    /// skipped for `safe_mode_cooldown`. 0 = never
+    pub safe_mode_threshold: u32,
+    pub safe_mode_cooldown: Duration,
+    /// How long the PAM module leaves a user's camera alone after their
+    /// attempt failed, see `cooldown.rs`. Zero = off
+    pub failure_cooldown: Duration,
+    /// Attempts in a row with no camera or no models before the module
+    /// disables itself, see `auto_disable.rs`. 0 = never
+    pub auto_disable_threshold: u32,
+    /// Part of the frame faces count in, see `DetectionRoi`
+    pub detection_roi: DetectionRoi,
+    /// See `SmartCamera::set_max_dimension`
+    pub max_frame_dimension: u32,
+    /// Ignore faces over the captured background, see `background.rs`
+    pub background_subtraction: bool,
+    /// See `FaceRecognizer::set_num_jitters`
+    pub num_jitters: u32,
+    /// See `FaceRecognizer::set_detection_scale`
+    pub detection_scale: f64,
+    /// Only compare against templates enrolled on the same kind of camera
+    /// (IR/RGB) as the one in use, and skip cameras with none
+    pub strict_camera_match: bool,
+    /// Require the matched face to stay in view for `presence_time` before
+    /// succeeding, see `AuthConfig::required_presence`
+    pub continuous_presence: bool,
+    pub presence_time: Duration,
+    /// Frames the face must hold still before matching, see `FaceSettle`
+    pub stabilization_frames: u32,
 }
 
 impl Default for AuthConfig {
@@ -47,17 +227,51 @@ impl Default for AuthConfig {
             timeout: Duration::from_secs(3),
             prefer_ir: true,
             data_dir: PathBuf::from("/var/lib/glance"),
-            models_dir: PathBuf::from("/usr/share/glance/models"),
+            user_data: UserData::Env,
+            models_dir: default_models_dir(),
             tolerance: 0.6,
             ir_tolerance: 0.45,
             rgb_tolerance: 0.50,
             target_user: None,
             min_brightness: 20.0,
+            max_brightness: DEFAULT_MAX_BRIGHTNESS,
             enable_ir_emitter: true,
             ir_device: "/dev/video2".to_string(),
+            ir_profiles: BTreeMap::new(),
             rgb_device: "/dev/video0".to_string(),
             max_frames_per_camera: 15,
+            max_auth_frames: 30,
+            internal_retries: 1,
             frame_delay_ms: 33,      // ~30 FPS
+            multi_face: false,
+            use_gpu: true,
+            required_matches: 1,
+            encoding_grace: 2,
+            rotation_search: false,
+            detect_obstruction: true,
+            grayscale_threshold: crate::camera::DEFAULT_GRAYSCALE_THRESHOLD,
+            probe_cache_ttl: DEFAULT_PROBE_CACHE_TTL,
+            metric: DistanceMetric::Euclidean,
+            multi_match_policy: MultiMatchPolicy::HighestConfidence,
+            mask_friendly: false,
+            max_extended_timeout: Duration::ZERO,
+            template_tolerance_penalty: 0.0,
+            record_stats: false,
+            run_hooks: false,
+            success_hold: Duration::ZERO,
+            safe_mode_threshold: 0,
+            safe_mode_cooldown: Duration::from_secs(300),
+            failure_cooldown: Duration::ZERO,
+            auto_disable_threshold: 0,
+            detection_roi: DetectionRoi::default(),
+            max_frame_dimension: DEFAULT_MAX_FRAME_DIMENSION,
+            background_subtraction: false,
+            num_jitters: 0,
+            detection_scale: 1.0,
+            strict_camera_match: false,
+            continuous_presence: false,
+            presence_time: Duration::from_millis(1500),
+            stabilization_frames: 0,
         }
     }
 }
@@ -65,13 +279,16 @@ impl Default for AuthConfig {
 impl AuthConfig {
     pub fn from_file(path: &Path) -> Result<Self> {
         let path_str = path.to_str().unwrap_or("");
-        let config = GlanceConfig::load(path_str)?;
-        
-        Ok(Self {
+        Ok(Self::from_config(GlanceConfig::load(path_str)?))
+    }
+    
+    fn from_config(config: GlanceConfig) -> Self {
+        Self {
             timeout: Duration::from_secs_f64(config.recognition.auth_timeout),
             prefer_ir: config.camera.prefer_ir,
             data_dir: PathBuf::from("/var/lib/glance"),
-            models_dir: PathBuf::from("/usr/share/glance/models"),
+            user_data: UserData::Env,
+            models_dir: configured_models_dir(&config.recognition.models_dir, &config.recognition.model_set),
             tolerance: if config.camera.prefer_ir { 
                 config.recognition.ir_tolerance 
             } else { 
@@ -81,46 +298,408 @@ impl AuthConfig {
             rgb_tolerance: config.recognition.rgb_tolerance,
             target_user: None,
             min_brightness: config.camera.min_brightness,
+            max_brightness: config.camera.max_brightness,
             enable_ir_emitter: config.ir_emitter.enabled,
             ir_device: config.camera.ir_device,
+            ir_profiles: config.ir_emitter.profiles,
             rgb_device: config.camera.rgb_device,
             max_frames_per_camera: 15,
+            max_auth_frames: config.recognition.max_auth_frames,
+            internal_retries: config.recognition.internal_retries,
             frame_delay_ms: 33,
-        })
+            multi_face: config.recognition.multi_face,
+            use_gpu: config.recognition.use_gpu,
+            required_matches: config.recognition.required_matches,
+            encoding_grace: config.recognition.encoding_grace,
+            rotation_search: config.recognition.rotation_search,
+            detect_obstruction: config.camera.detect_obstruction,
+            grayscale_threshold: config.camera.grayscale_threshold,
+            probe_cache_ttl: Duration::from_secs(config.camera.probe_cache_ttl),
+            metric: config.recognition.metric,
+            multi_match_policy: config.recognition.multi_match_policy,
+            mask_friendly: config.recognition.mask_friendly,
+            max_extended_timeout: Duration::from_secs_f64(config.recognition.max_extended_timeout),
+            template_tolerance_penalty: config.recognition.template_tolerance_penalty,
+            record_stats: config.stats.enabled,
+            run_hooks: false,
+            success_hold: Duration::from_millis(config.recognition.success_hold_ms).min(MAX_SUCCESS_HOLD),
+            safe_mode_threshold: config.camera.safe_mode_threshold,
+            safe_mode_cooldown: Duration::from_secs(config.camera.safe_mode_cooldown),
+            failure_cooldown: Duration::from_secs(config.camera.failure_cooldown),
+            auto_disable_threshold: config.camera.auto_disable_threshold,
+            detection_roi: config.camera.detection_roi,
+            max_frame_dimension: config.camera.max_frame_dimension,
+            background_subtraction: config.camera.background_subtraction,
+            num_jitters: config.recognition.num_jitters,
+            detection_scale: config.recognition.detection_scale,
+            strict_camera_match: config.recognition.strict_camera_match,
+            continuous_presence: config.recognition.continuous_presence,
+            presence_time: Duration::from_millis(config.recognition.continuous_presence_ms),
+            stabilization_frames: config.recognition.stabilization_frames,
+        }
     }
     
+    /// How long the matched face must stay in view, matching, before the
+    /// attempt succeeds. Zero when `continuous_presence` is off
+    pub fn required_presence(&self) -> Duration {
+        if self.continuous_presence {
+            self.presence_time
+        } else {
+            Duration::ZERO
+        }
+    }
+    
+    /// The system config with the user's config layered on top, see
+    /// `GlanceConfig::load_layered`. `GLANCE_CONFIG` names a file to use in
+    /// place of the user's, for tests and containers
     pub fn load() -> Self {
-        if let Some(home) = std::env::var_os("HOME") {
-            let user_config = Path::new(&home).join(".config/glance/config.json");
-            if let Ok(config) = Self::from_file(&user_config) {
-                return config;
+        let user_config = std::env::var_os(CONFIG_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(user_config_path);
+        Self::load_over_system(user_config)
+    }
+    
+    /// `load` for the PAM module authenticating `username`. With `su` the
+    /// environment is the caller's, who could point `GLANCE_CONFIG` or
+    /// `$HOME` at a file with any tolerance, so neither is read: the user
+    /// config and per-user enrollments come from `username`'s home in the
+    /// passwd database, and only files that account owns count
+    pub fn load_for_module(username: &str) -> Self {
+        let account = account_home(username);
+        let user_config = account.as_ref()
+            .map(|(home, uid)| (home.join(".config/glance/config.json"), *uid))
+            .filter(|(path, uid)| is_owned_by(path, *uid))
+            .map(|(path, _)| path);
+        
+        let mut config = Self::load_over_system(user_config);
+        config.user_data = match account {
+            Some((home, uid)) => UserData::Account { home, uid },
+            None => UserData::None,
+        };
+        config
+    }
+    
+    /// The config `pam_sm_authenticate` starts from for `username`:
+    /// `load_for_module`, matching the name it was enrolled under per
+    /// `user_aliases`. Anything checking face auth on the module's behalf
+    /// (`glance enable-pam --test-first`) must build it the same way
+    pub fn for_module_user(username: &str) -> Self {
+        let mut config = Self::load_for_module(username);
+        config.target_user = Some(enrolled_username(username));
+        config
+    }
+    
+    fn load_over_system(user_config: Option<PathBuf>) -> Self {
+        let system_config = Path::new("/etc/glance/config.json");
+        if !system_config.exists() && !user_config.as_deref().is_some_and(Path::exists) {
+            return Self::default();
+        }
+        
+        match GlanceConfig::load_layered(system_config, user_config.as_deref()) {
+            Ok(config) => Self::from_config(config),
+            Err(e) => {
+                warn!("Invalid config, using defaults: {}", e);
+                Self::default()
             }
         }
+    }
+}
+
+/// Environment variable naming a config file that tools and the GUI use in
+/// place of `~/.config/glance/config.json`
+pub const CONFIG_ENV: &str = "GLANCE_CONFIG";
+
+/// Every setting the PAM module reads, at its default, e.g. for tools that
+/// need to tell known keys from typos
+pub fn default_config() -> serde_json::Value {
+    serde_json::to_value(GlanceConfig::default()).unwrap_or(serde_json::Value::Null)
+}
+
+/// Why the PAM module would reject or misread `config`: values of the wrong
+/// type, or outside what auth works with. Empty when it is usable
+pub fn validate_config(config: &serde_json::Value) -> Vec<String> {
+    match serde_json::from_value::<GlanceConfig>(config.clone()) {
+        Ok(parsed) => parsed.problems(),
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".config/glance/config.json"))
+}
+
+/// `username`'s home directory and uid from the passwd database
+fn account_home(username: &str) -> Option<(PathBuf, u32)> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    
+    let name = CString::new(username).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found: *mut libc::passwd = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found)
+    };
+    if status != 0 || found.is_null() || passwd.pw_dir.is_null() {
+        debug!("No passwd entry for '{}'", username);
+        return None;
+    }
+    
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    let home = PathBuf::from(std::ffi::OsStr::from_bytes(home.to_bytes()));
+    Some((home, passwd.pw_uid))
+}
+
+/// Whether `path` exists and belongs to `uid`
+fn is_owned_by(path: &Path, uid: u32) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.uid() == uid => true,
+        Ok(metadata) => {
+            warn!("Ignoring {:?}: owned by uid {}, not {}", path, metadata.uid(), uid);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// The name `username` was enrolled under, per `user_aliases`. Read from
+/// the system config only: an alias in a user-writable file would let that
+/// user's face unlock whichever account they point it at.
+pub fn enrolled_username(username: &str) -> String {
+    let aliases = GlanceConfig::load("/etc/glance/config.json")
+        .map(|config| config.user_aliases)
+        .unwrap_or_default();
+    
+    match aliases.get(username) {
+        Some(alias) if !alias.is_empty() => {
+            info!("PAM user '{}' is enrolled as '{}'", username, alias);
+            alias.clone()
+        }
+        _ => username.to_string(),
+    }
+}
+
+/// Accumulates consecutive matches for the same user across frames.
+///
+/// Like the enrollment GUI, a frame where a face is detected but dlib fails
+/// to encode it doesn't reset progress: up to `grace` such frames in a row
+/// leave the streak intact. A missing face or a non-match still resets it.
+///
+/// With a `presence` time, the streak must also have lasted that long, so a
+/// user who matched once and walked away isn't let in.
+struct MatchStreak {
+    required: u32,
+    grace: u32,
+    presence: Duration,
+    username: Option<String>,
+    count: u32,
+    best_distance: f64,
+    gaps: u32,
+    since: Option<Instant>,
+}
+
+impl MatchStreak {
+    fn new(required: u32, grace: u32, presence: Duration) -> Self {
+        Self {
+            required: required.max(1),
+            grace,
+            presence,
+            username: None,
+            count: 0,
+            best_distance: f64::MAX,
+            gaps: 0,
+            since: None,
+        }
+    }
+    
+    /// Record a match; returns the user and best distance once the streak is long enough
+    fn on_match(&mut self, username: String, distance: f64) -> Option<(String, f64)> {
+        if self.username.as_deref() != Some(username.as_str()) {
+            self.reset();
+            self.username = Some(username);
+        }
         
-        let system_config = Path::new("/etc/glance/config.json");
-        if let Ok(config) = Self::from_file(system_config) {
-            return config;
+        self.count += 1;
+        self.gaps = 0;
+        self.best_distance = self.best_distance.min(distance);
+        let since = *self.since.get_or_insert_with(Instant::now);
+        
+        if self.count >= self.required && since.elapsed() >= self.presence {
+            self.username.clone().map(|u| (u, self.best_distance))
+        } else {
+            None
+        }
+    }
+    
+    /// A face was in view but could not be encoded
+    fn on_encoding_gap(&mut self) {
+        if self.count == 0 {
+            return;
         }
         
-        Self::default()
+        self.gaps += 1;
+        if self.gaps > self.grace {
+            debug!("Encoding failed {} frames in a row — resetting match streak", self.gaps);
+            self.reset();
+        }
+    }
+    
+    /// A streak is underway that only needs more presence time; frame
+    /// limits shouldn't cut it short
+    fn holding(&self) -> bool {
+        !self.presence.is_zero() && self.count > 0
+    }
+    
+    fn reset(&mut self) {
+        self.username = None;
+        self.count = 0;
+        self.best_distance = f64::MAX;
+        self.gaps = 0;
+        self.since = None;
+    }
+}
+
+/// How far an attempt has got, reported after every processed frame
+#[derive(Debug, Clone, Copy)]
+pub struct AuthProgress {
+    pub frames: u32,
+    pub faces_seen: u32,
+    /// `AuthConfig::max_auth_frames`
+    pub max_frames: u32,
+    /// Closest any face came to a template it was compared against, matched
+    /// or not. `None` until a face has been compared
+    pub best_distance: Option<f64>,
+}
+
+/// Auth progress callback type. Runs on the auth worker thread.
+pub type ProgressCallback = Box<dyn Fn(AuthProgress) + Send>;
+
+/// How long each stage of an attempt took (or, for the `first_*` and
+/// `matched` stages, when it happened since the start), logged as one line
+/// at the end so a slow login can be pinned on a stage
+#[derive(Debug, Default)]
+struct StageTimings {
+    face_load: Duration,
+    emitter: Duration,
+    recognizer_init: Duration,
+    camera_open: Duration,
+    first_frame: Option<Duration>,
+    first_face: Option<Duration>,
+    matched: Option<Duration>,
+}
+
+impl StageTimings {
+    fn log(&self, total: Duration, progress: &AuthProgress) {
+        let at = |d: Option<Duration>| d.map_or("-".to_string(), |d| format!("{}ms", d.as_millis()));
+        debug!("Auth timings: face_load={}ms emitter={}ms recognizer_init={}ms camera_open={}ms \
+                first_frame={} first_face={} match={} total={}ms frames={} faces={}",
+               self.face_load.as_millis(), self.emitter.as_millis(),
+               self.recognizer_init.as_millis(), self.camera_open.as_millis(),
+               at(self.first_frame), at(self.first_face), at(self.matched),
+               total.as_millis(), progress.frames, progress.faces_seen);
+    }
+}
+
+/// Frames processed so far across every camera tried
+struct FrameCounter {
+    progress: AuthProgress,
+    callback: Option<ProgressCallback>,
+}
+
+impl FrameCounter {
+    fn new(config: &AuthConfig, callback: Option<ProgressCallback>) -> Self {
+        Self {
+            progress: AuthProgress { frames: 0, faces_seen: 0, max_frames: config.max_auth_frames, best_distance: None },
+            callback,
+        }
+    }
+    
+    fn exhausted(&self) -> bool {
+        self.progress.frames >= self.progress.max_frames
+    }
+    
+    /// `Timeout` if the deadline or the frame budget is what ended the attempt
+    fn timeout_result(&self, deadline: &Deadline) -> Option<AuthResult> {
+        let reason = if deadline.expired() {
+            TimeoutReason::WallClock
+        } else if self.exhausted() {
+            TimeoutReason::FrameLimit
+        } else {
+            return None;
+        };
+        Some(AuthResult::Timeout {
+            reason,
+            frames_processed: self.progress.frames,
+            faces_detected: self.progress.faces_seen,
+        })
+    }
+    
+    /// What an attempt that ended without a match reports: `Timeout` if a
+    /// limit ended it, `NoFaceDetected` if no frame had a face, else `NoMatch`
+    fn failure_result(&self, deadline: &Deadline) -> AuthResult {
+        self.timeout_result(deadline).unwrap_or(if self.progress.faces_seen == 0 {
+            AuthResult::NoFaceDetected
+        } else {
+            AuthResult::NoMatch
+        })
+    }
+    
+    /// Note how close this frame's faces came, before its `on_frame`
+    fn on_distance(&mut self, distance: f64) {
+        if self.progress.best_distance.map_or(true, |best| distance < best) {
+            self.progress.best_distance = Some(distance);
+        }
+    }
+    
+    fn on_frame(&mut self, face_seen: bool) {
+        self.progress.frames += 1;
+        if face_seen {
+            self.progress.faces_seen += 1;
+        }
+        if let Some(ref callback) = self.callback {
+            callback(self.progress);
+        }
     }
 }
 
 /// Wrapper to run authentication with a hard timeout using a separate thread.
 /// This ensures we never block indefinitely even if camera operations hang.
 pub fn authenticate(config: &AuthConfig) -> AuthResult {
-    let timeout = config.timeout;
+    authenticate_with_progress(config, None)
+}
+
+/// `authenticate`, calling `progress` after each processed frame, e.g. for
+/// a greeter to show that recognition is underway
+pub fn authenticate_with_progress(config: &AuthConfig, progress: Option<ProgressCallback>) -> AuthResult {
+    let timeout = config.timeout.max(config.max_extended_timeout) + config.required_presence() + config.success_hold;
     let config_clone = config.clone();
     
+    // Kept outside the worker so a hard timeout can still report the counts
+    let latest = Arc::new(Mutex::new(AuthProgress {
+        frames: 0, faces_seen: 0, max_frames: config.max_auth_frames, best_distance: None,
+    }));
+    let seen = Arc::clone(&latest);
+    let progress: ProgressCallback = Box::new(move |p| {
+        if let Ok(mut latest) = seen.lock() {
+            *latest = p;
+        }
+        if let Some(ref callback) = progress {
+            callback(p);
+        }
+    });
+    
     let (tx, rx) = mpsc::channel();
     
     let handle = thread::spawn(move || {
-        let result = authenticate_inner(&config_clone);
+        let result = authenticate_inner(&config_clone, FrameCounter::new(&config_clone, Some(progress)));
         let _ = tx.send(result);
     });
     
     // Hard timeout = configured timeout + 500ms grace
-    match rx.recv_timeout(timeout + Duration::from_millis(500)) {
+    let result = match rx.recv_timeout(timeout + Duration::from_millis(500)) {
         Ok(result) => {
             let _ = handle.join();
             result
@@ -131,7 +710,13 @@ pub fn authenticate(config: &AuthConfig) -> AuthResult {
                 .arg("-f")
                 .arg("linux-enable-ir-emitter")
                 .output();
-            AuthResult::Timeout
+            let last = latest.lock().map(|p| *p)
+                .unwrap_or(AuthProgress { frames: 0, faces_seen: 0, max_frames: 0, best_distance: None });
+            AuthResult::Timeout {
+                reason: TimeoutReason::WallClock,
+                frames_processed: last.frames,
+                faces_detected: last.faces_seen,
+            }
         }
         Err(mpsc::RecvTimeoutError::Disconnected) => {
             error!("Auth worker panicked");
@@ -141,67 +726,267 @@ pub fn authenticate(config: &AuthConfig) -> AuthResult {
                 .output();
             AuthResult::Error("Internal error".to_string())
         }
+    };
+    
+    // Success was already signalled before the camera was released
+    if config.run_hooks && !matches!(result, AuthResult::Success { .. }) {
+        crate::hooks::run_result_hook(&result, config.target_user.as_deref().unwrap_or(""));
+    }
+    result
+}
+
+/// Upper bound on `success_hold`, which also extends the hard timeout
+const MAX_SUCCESS_HOLD: Duration = Duration::from_secs(1);
+
+/// The success path, in a fixed order while the camera is still open:
+/// log the match, fire the success hook, then hold for `success_hold`.
+/// The caller releases the camera and the IR emitter afterwards.
+fn signal_success(config: &AuthConfig, username: &str, distance: f64, camera_type: CameraType) {
+    info!("Recognized '{}' via {}", username, camera_type.label());
+    
+    if config.run_hooks {
+        let result = AuthResult::Success {
+            username: username.to_string(),
+            confidence: 1.0 - distance,
+            camera_type,
+        };
+        crate::hooks::run_result_hook(&result, username);
+    }
+    
+    if !config.success_hold.is_zero() {
+        thread::sleep(config.success_hold);
+    }
+}
+
+/// Start the IR emitter if enabled; skips gracefully if the tool isn't installed
+fn start_ir_emitter(config: &AuthConfig) -> Option<IrEmitter> {
+    if !config.enable_ir_emitter {
+        return None;
+    }
+    
+    // The emitter tool wants the real node, not a by-id symlink
+    let device = match resolve_device(&config.ir_device) {
+        Ok(device_id) => format!("/dev/video{}", device_id),
+        Err(_) => config.ir_device.clone(),
+    };
+    let mut emitter = IrEmitter::new(&device);
+    // Profiles may be keyed by the configured name or the node it resolves to
+    let profile = config.ir_profiles.get(&config.ir_device)
+        .or_else(|| config.ir_profiles.get(&device));
+    emitter.set_config(profile.and_then(|name| IrEmitter::find_config(name)));
+    if let Err(e) = emitter.enable() {
+        warn!("IR emitter failed: {}", e);
+        None
+    } else if emitter.is_running() {
+        Some(emitter)
+    } else {
+        debug!("IR emitter not running (tool may not be installed)");
+        None
+    }
+}
+
+/// What happened on one camera
+struct CameraAttempt {
+    /// Type the camera was opened as, `None` if it couldn't be opened
+    opened_as: Option<CameraType>,
+    matched: Option<(String, f64)>,
+    /// Gave up because the lens looked covered
+    obstructed: bool,
+    /// Gave up because frames stayed washed out, see `is_overexposed`
+    overexposed: bool,
+}
+
+impl CameraAttempt {
+    /// The outcome of a single-camera run, `None` if the camera never opened.
+    /// `failure` is what to report if it ended without a match
+    fn into_result(self, failure: AuthResult) -> Option<AuthResult> {
+        let camera_type = self.opened_as?;
+        Some(match self.matched {
+            Some((username, distance)) => AuthResult::Success {
+                username,
+                confidence: 1.0 - distance,
+                camera_type,
+            },
+            None if self.obstructed => AuthResult::CameraObstructed,
+            None if self.overexposed => AuthResult::Overexposed,
+            None => failure,
+        })
+    }
+}
+
+/// How far a face may drift between frames and still count as still, as a
+/// share of its width (centre movement and size change alike)
+const SETTLE_TOLERANCE: f64 = 0.1;
+
+/// Waits for a detected face to hold still for a few frames before the
+/// auth loop encodes it. Once settled it stays settled until the face is
+/// lost, so a matching face costs one detection per frame as before.
+struct FaceSettle {
+    required: u32,
+    last: Option<(i64, i64, i64, i64)>,
+    still: u32,
+}
+
+impl FaceSettle {
+    fn new(required: u32) -> Self {
+        Self { required, last: None, still: 0 }
+    }
+    
+    fn settled(&self) -> bool {
+        self.still >= self.required
+    }
+    
+    /// Record where the largest face is this frame; returns whether it has
+    /// now been still for long enough
+    fn on_rect(&mut self, rect: (i64, i64, i64, i64)) -> bool {
+        let (left, top, right, bottom) = rect;
+        let width = (right - left).max(1) as f64;
+        let still = self.last.is_some_and(|(l, t, r, b)| {
+            let moved = ((left + right - l - r) as f64 / 2.0).hypot((top + bottom - t - b) as f64 / 2.0);
+            let resized = ((right - left) - (r - l)).abs() as f64;
+            moved <= width * SETTLE_TOLERANCE && resized <= width * SETTLE_TOLERANCE
+        });
+        self.still = if still { self.still + 1 } else { 0 };
+        self.last = Some(rect);
+        self.settled()
+    }
+    
+    fn reset(&mut self) {
+        self.last = None;
+        self.still = 0;
+    }
+}
+
+/// Identical frames in a row before the stream counts as stalled
+const FROZEN_FRAMES: u32 = 5;
+
+/// Time added per frame in which a seen face moved closer to a match
+const DEADLINE_EXTENSION: Duration = Duration::from_millis(500);
+/// Only faces within this multiple of the tolerance earn an extension
+const EXTENSION_RANGE: f64 = 1.5;
+
+/// The auth time budget. Starts at `timeout` and grows in small steps, up
+/// to `max_extended_timeout`, while a face is in view and its best distance
+/// keeps improving, so recognition isn't cut off one frame short. Nothing
+/// extends it when no face is seen.
+struct Deadline {
+    start: Instant,
+    limit: Duration,
+    max: Duration,
+    best_ratio: f64,
+}
+
+impl Deadline {
+    fn new(config: &AuthConfig) -> Self {
+        // Time to stay in view comes on top of the time to be recognized
+        let presence = config.required_presence();
+        Self {
+            start: Instant::now(),
+            limit: config.timeout + presence,
+            max: config.max_extended_timeout.max(config.timeout) + presence,
+            best_ratio: EXTENSION_RANGE,
+        }
+    }
+    
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    
+    fn expired(&self) -> bool {
+        self.elapsed() >= self.limit
+    }
+    
+    /// Record the closest distance seen this frame, relative to tolerance
+    fn on_face(&mut self, ratio: f64) {
+        if ratio >= self.best_ratio {
+            return;
+        }
+        self.best_ratio = ratio;
+        
+        let extended = (self.limit + DEADLINE_EXTENSION).min(self.max);
+        if extended > self.limit {
+            debug!("Face closing in ({:.2} of tolerance) — deadline now {:?}", ratio, extended);
+            self.limit = extended;
+        }
     }
 }
 
+/// Consecutive black frames before a camera counts as covered. A few frames
+/// of slack so auto-exposure settling after open isn't mistaken for a shutter.
+const OBSTRUCTED_FRAMES: u32 = 3;
+/// Consecutive washed-out frames before a camera is given up on. More than
+/// for a shutter, since exposure often recovers after a moment
+const OVEREXPOSED_FRAMES: u32 = 8;
+
+/// Pause between stopping and restarting the IR emitter for a retry, so
+/// the camera registers it as off
+const EMITTER_RECYCLE_PAUSE: Duration = Duration::from_millis(200);
+
 /// Fast authentication: detect cameras via sysfs, open directly, try a handful
 /// of frames per camera. Fails fast so PAM falls through to password.
-fn authenticate_inner(config: &AuthConfig) -> AuthResult {
-    let start_time = Instant::now();
-    let frame_delay = Duration::from_millis(config.frame_delay_ms);
+///
+/// On marginal IR hardware the emitter sometimes doesn't engage, so a pass
+/// sees nothing. Then the emitter is power-cycled and the cameras tried
+/// again, up to `internal_retries` times, within the same time and frame
+/// budget.
+fn authenticate_inner(config: &AuthConfig, mut counter: FrameCounter) -> AuthResult {
+    let mut deadline = Deadline::new(config);
     
     info!("Glance auth starting (timeout: {:?})", config.timeout);
+    let mut timings = StageTimings::default();
     
-    // --- IR emitter (always try if enabled — it will skip gracefully if not installed) ---
-    let mut ir_emitter: Option<IrEmitter> = if config.enable_ir_emitter {
-        let mut emitter = IrEmitter::new(&config.ir_device);
-        if let Err(e) = emitter.enable() {
-            warn!("IR emitter failed: {}", e);
-            None
-        } else if emitter.is_running() {
-            Some(emitter)
-        } else {
-            debug!("IR emitter not running (tool may not be installed)");
-            None
+    // --- Load registered faces ---
+    // Before the IR emitter and cameras: with nothing to compare against,
+    // every encode would be wasted
+    let stage = Instant::now();
+    let loaded = load_registered_faces(config, None);
+    timings.face_load = stage.elapsed();
+    let registered_faces = match loaded {
+        Ok(faces) => Templates(faces),
+        Err(e) => {
+            error!("Failed to load faces: {}", e);
+            return AuthResult::Error(format!("Load faces: {}", e));
         }
-    } else {
-        None
     };
     
+    if !has_comparable_templates(config, &registered_faces) {
+        warn!("No usable enrolled faces{} — use your password",
+              config.target_user.as_ref().map(|u| format!(" for '{}'", u)).unwrap_or_default());
+        return AuthResult::NotEnrolled;
+    }
+    
+    info!("Loaded {} registered user(s)", registered_faces.len());
+    
+    if safe_mode::active(config) {
+        return AuthResult::SafeMode;
+    }
+    
+    // --- IR emitter (always try if enabled — it will skip gracefully if not installed) ---
+    let stage = Instant::now();
+    let mut ir_emitter = start_ir_emitter(config);
+    timings.emitter = stage.elapsed();
+    
     macro_rules! cleanup_and_return {
         ($result:expr) => {{
             if let Some(ref mut emitter) = ir_emitter {
                 let _ = emitter.disable();
             }
             drop(ir_emitter.take());
+            timings.log(deadline.elapsed(), &counter.progress);
             $result
         }};
     }
     
-    // --- Load registered faces ---
-    let registered_faces = match load_registered_faces(config) {
-        Ok(faces) if !faces.is_empty() => faces,
-        Ok(_) => {
-            warn!("No registered faces — use your password");
-            return cleanup_and_return!(AuthResult::NoMatch);
-        }
-        Err(e) => {
-            error!("Failed to load faces: {}", e);
-            return cleanup_and_return!(AuthResult::Error(format!("Load faces: {}", e)));
-        }
-    };
-    
-    info!("Loaded {} registered user(s)", registered_faces.len());
-    
-    if start_time.elapsed() >= config.timeout {
-        return cleanup_and_return!(AuthResult::Timeout);
+    if let Some(timeout) = counter.timeout_result(&deadline) {
+        return cleanup_and_return!(timeout);
     }
     
-    // --- Fast camera detection (sysfs only, near-instant) ---
-    let cameras = match detect_cameras_fast() {
+    // --- Fast camera detection (sysfs only, near-instant, or cached) ---
+    let cameras = match detect_cameras_fast_cached(config.probe_cache_ttl) {
         Ok(c) if !c.is_empty() => c,
         Ok(_) => {
             error!("No cameras detected");
+            safe_mode::record_failure(config);
             return cleanup_and_return!(AuthResult::Error("No cameras found".to_string()));
         }
         Err(e) => {
@@ -210,180 +995,619 @@ fn authenticate_inner(config: &AuthConfig) -> AuthResult {
         }
     };
     
+    let mut cameras = cameras;
+    pin_configured_devices(&mut cameras, &config.ir_device, &config.rgb_device);
+    
     // Sort: preferred camera type first, but always include both IR and RGB
-    let sorted_cameras: Vec<&CameraInfo> = if config.prefer_ir {
-        cameras.iter()
-            .filter(|c| c.camera_type == CameraType::Infrared)
-            .chain(cameras.iter().filter(|c| c.camera_type == CameraType::Rgb))
-            .chain(cameras.iter().filter(|c| c.camera_type == CameraType::Unknown))
-            .collect()
+    let sorted_cameras = prioritize_cameras(cameras, config.prefer_ir);
+    
+    // --- Templates per camera type, with strict_camera_match ---
+    let (ir_faces, rgb_faces) = if config.strict_camera_match {
+        let load = |camera| load_registered_faces(config, Some(camera)).map(Templates).unwrap_or_else(|e| {
+            warn!("Failed to load {} templates: {}", camera.label(), e);
+            Templates(Vec::new())
+        });
+        (load(CameraType::Infrared), load(CameraType::Rgb))
     } else {
-        cameras.iter()
-            .filter(|c| c.camera_type == CameraType::Rgb)
-            .chain(cameras.iter().filter(|c| c.camera_type == CameraType::Infrared))
-            .chain(cameras.iter().filter(|c| c.camera_type == CameraType::Unknown))
-            .collect()
+        (Templates(Vec::new()), Templates(Vec::new()))
     };
+    let no_faces = Templates(Vec::new());
     
     // --- Try each camera quickly ---
-    for cam_info in &sorted_cameras {
-        if start_time.elapsed() >= config.timeout {
+    let mut tried: u32 = 0;
+    let mut opened: u32 = 0;
+    let mut obstructed: u32 = 0;
+    let mut overexposed: u32 = 0;
+    let mut mismatched: u32 = 0;
+    let mut retries: u32 = 0;
+    loop {
+        for cam_info in &sorted_cameras {
+            if deadline.expired() {
+                break;
+            }
+            if counter.exhausted() {
+                info!("Frame budget of {} used up", config.max_auth_frames);
+                break;
+            }
+            
+            let faces: &[(String, Vec<Vec<f32>>)] = if !config.strict_camera_match {
+                &registered_faces
+            } else {
+                let same_type = match cam_info.camera_type {
+                    CameraType::Infrared => &ir_faces,
+                    CameraType::Rgb => &rgb_faces,
+                    CameraType::Unknown => &no_faces,
+                };
+                if !has_comparable_templates(config, same_type) {
+                    info!("{} camera video{}: nothing enrolled on this camera type — skipping (strict_camera_match)",
+                          cam_info.camera_type.label(), cam_info.device_id);
+                    mismatched += 1;
+                    continue;
+                }
+                same_type
+            };
+            
+            let attempt = try_camera(config, cam_info, faces, &mut deadline, &mut counter, &mut timings);
+            tried += 1;
+            if attempt.opened_as.is_some() {
+                if opened == 0 {
+                    safe_mode::record_open();
+                }
+                opened += 1;
+            }
+            if attempt.obstructed {
+                obstructed += 1;
+            }
+            if attempt.overexposed {
+                overexposed += 1;
+            }
+            
+            if let (Some(camera_type), Some((username, distance))) = (attempt.opened_as, attempt.matched) {
+                let elapsed = deadline.elapsed();
+                info!("Authenticated '{}' via {:?} in {:?} (distance: {:.4})",
+                      username, camera_type, elapsed, distance);
+                
+                return cleanup_and_return!(AuthResult::Success {
+                    username,
+                    confidence: 1.0 - distance,
+                    camera_type,
+                });
+            }
+        }
+        
+        let Some(emitter) = ir_emitter.as_mut() else {
+            break;
+        };
+        if retries >= config.internal_retries || opened == 0 || counter.progress.faces_seen > 0
+            || deadline.expired() || counter.exhausted() {
+            break;
+        }
+        retries += 1;
+        info!("No face seen with the IR emitter on — power-cycling it (retry {} of {})",
+              retries, config.internal_retries);
+        let _ = emitter.disable();
+        thread::sleep(EMITTER_RECYCLE_PAUSE);
+        if let Err(e) = emitter.enable() {
+            warn!("IR emitter failed to restart: {}", e);
+            break;
+        }
+        if !emitter.is_running() {
+            break;
+        }
+    }
+    
+    if tried > 0 && opened == 0 {
+        safe_mode::record_failure(config);
+    }
+    
+    if tried == 0 && mismatched > 0 {
+        let enrolled = if has_comparable_templates(config, &ir_faces) {
+            Some(CameraType::Infrared)
+        } else if has_comparable_templates(config, &rgb_faces) {
+            Some(CameraType::Rgb)
+        } else {
+            None
+        };
+        warn!("No camera of the type enrolled on ({}) — use your password",
+              enrolled.map_or("none recorded", |c| c.label()));
+        return cleanup_and_return!(AuthResult::CameraMismatch { enrolled });
+    }
+    
+    if opened > 0 && obstructed == opened {
+        info!("Camera appears covered (privacy shutter?) — use your password");
+        return cleanup_and_return!(AuthResult::CameraObstructed);
+    }
+    
+    if opened > 0 && overexposed == opened {
+        info!("Frames too bright (backlit?) — use your password");
+        return cleanup_and_return!(AuthResult::Overexposed);
+    }
+    
+    // All cameras tried — face auth failed
+    let elapsed = deadline.elapsed();
+    let result = counter.failure_result(&deadline);
+    match result.timeout_summary() {
+        Some(summary) => info!("Face not recognized: {} ({:?}) — use your password", summary, elapsed),
+        None if matches!(result, AuthResult::NoFaceDetected) =>
+            info!("No face seen after {:?} — use your password", elapsed),
+        None => info!("Face not recognized after {:?} — use your password", elapsed),
+    }
+    cleanup_and_return!(result)
+}
+
+/// Open one camera and run the frame loop on it until a confirmed match,
+/// the frame budget or the overall timeout
+fn try_camera(
+    config: &AuthConfig,
+    cam_info: &CameraInfo,
+    registered_faces: &[(String, Vec<Vec<f32>>)],
+    deadline: &mut Deadline,
+    counter: &mut FrameCounter,
+    timings: &mut StageTimings,
+) -> CameraAttempt {
+    let camera_label = cam_info.camera_type.label();
+    info!("Trying {} camera video{}", camera_label, cam_info.device_id);
+    
+    // Open camera directly — no redundant detection
+    let stage = Instant::now();
+    let opened = SmartCamera::open_direct(cam_info);
+    timings.camera_open += stage.elapsed();
+    let mut camera = match opened {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("{} camera open failed: {}", camera_label, e);
+            invalidate_probe_cache();
+            return CameraAttempt { opened_as: None, matched: None, obstructed: false, overexposed: false };
+        }
+    };
+    camera.set_max_dimension(config.max_frame_dimension);
+    
+    let attempt = scan_frames(config, &mut camera, None, registered_faces, deadline, counter, timings);
+    if let (Some(camera_type), Some((username, distance))) = (attempt.opened_as, &attempt.matched) {
+        signal_success(config, username, *distance, camera_type);
+    }
+    
+    // Only now, after the success signal and hold, does the LED go out
+    drop(camera);
+    attempt
+}
+
+/// The frame loop: read from `source` until a confirmed match, the frame
+/// budget or the overall timeout. Faces are found and encoded by `embedder`,
+/// or the dlib models when `None`. `opened_as` is always set, since the
+/// source is already open.
+fn scan_frames(
+    config: &AuthConfig,
+    source: &mut dyn FrameSource,
+    embedder: Option<Box<dyn FaceEmbedder>>,
+    registered_faces: &[(String, Vec<Vec<f32>>)],
+    deadline: &mut Deadline,
+    counter: &mut FrameCounter,
+    timings: &mut StageTimings,
+) -> CameraAttempt {
+    let frame_delay = Duration::from_millis(config.frame_delay_ms);
+    
+    // Use actual camera type (in case name detection was wrong)
+    let camera_type = if source.is_ir() { CameraType::Infrared } else { CameraType::Rgb };
+    let camera_label = camera_type.label();
+    let tolerance = if source.is_ir() { config.ir_tolerance } else { config.rgb_tolerance };
+    let failed = CameraAttempt { opened_as: Some(camera_type), matched: None, obstructed: false, overexposed: false };
+    
+    debug!("{}: tolerance {:.2}", camera_label, tolerance);
+    
+    let device = ComputeDevice::select(config.use_gpu);
+    let stage = Instant::now();
+    let loaded = match embedder {
+        Some(embedder) => Ok(FaceRecognizer::with_embedder(embedder, tolerance)),
+        None => FaceRecognizer::with_device(&config.models_dir, tolerance, device),
+    };
+    timings.recognizer_init += stage.elapsed();
+    let mut recognizer = match loaded {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Recognizer init failed: {}", e);
+            return failed;
+        }
+    };
+    recognizer.set_rotation_search(config.rotation_search);
+    recognizer.set_metric(config.metric);
+    recognizer.set_multi_match_policy(config.multi_match_policy);
+    recognizer.set_template_penalty(config.template_tolerance_penalty);
+    recognizer.set_detection_roi(config.detection_roi);
+    recognizer.set_num_jitters(config.num_jitters);
+    recognizer.set_detection_scale(config.detection_scale);
+    if config.background_subtraction {
+        recognizer.set_background(load_background(config, camera_type));
+    }
+    
+    // Target-user filtering only depends on the registered set, so do it once
+    let faces_to_check = Templates(if let Some(ref target) = config.target_user {
+        registered_faces.iter()
+            .filter(|(u, _)| u == target)
+            .cloned()
+            .collect()
+    } else {
+        registered_faces.to_vec()
+    });
+    
+    // --- Quick frame loop ---
+    let mut frames: u32 = 0;
+    let mut faces_seen: u32 = 0;
+    let mut consecutive_failures: u32 = 0;
+    let mut dark_frames: u32 = 0;
+    let mut bright_frames: u32 = 0;
+    let mut last_fingerprint: Option<u64> = None;
+    let mut frozen_frames: u32 = 0;
+    let mut reopened = false;
+    let mut streak = MatchStreak::new(config.required_matches, config.encoding_grace, config.required_presence());
+    let mut settle = FaceSettle::new(config.stabilization_frames);
+    
+    loop {
+        if deadline.expired() {
+            info!("{}: timeout after {} frames", camera_label, frames);
             break;
         }
         
-        let is_ir = cam_info.camera_type == CameraType::Infrared;
-        let tolerance = if is_ir { config.ir_tolerance } else { config.rgb_tolerance };
-        let camera_label = if is_ir { "IR" } else { "RGB" };
+        if frames >= config.max_frames_per_camera && !streak.holding() {
+            info!("{}: {} frames processed, {} faces — moving on",
+                  camera_label, frames, faces_seen);
+            break;
+        }
         
-        info!("Trying {} camera video{} (tolerance: {:.2})", 
-              camera_label, cam_info.device_id, tolerance);
+        if counter.exhausted() && !streak.holding() {
+            info!("{}: auth frame budget used up after {} frames here", camera_label, frames);
+            break;
+        }
         
-        // Init recognizer
-        let recognizer = match FaceRecognizer::new(&config.models_dir, tolerance) {
-            Ok(r) => r,
-            Err(e) => {
-                error!("Recognizer init failed: {}", e);
-                continue;
-            }
-        };
+        if consecutive_failures >= 5 {
+            warn!("{}: too many read failures", camera_label);
+            break;
+        }
         
-        // Open camera directly — no redundant detection
-        let mut camera = match SmartCamera::open_direct(cam_info) {
-            Ok(c) => c,
-            Err(e) => {
-                warn!("{} camera open failed: {}", camera_label, e);
-                continue;
-            }
-        };
+        if frames > 0 {
+            thread::sleep(frame_delay);
+        }
         
-        // Use actual camera type (in case name detection was wrong)
-        let camera_type = if camera.is_ir { CameraType::Infrared } else { CameraType::Rgb };
-        let effective_tolerance = if camera.is_ir { config.ir_tolerance } else { config.rgb_tolerance };
-        let recognizer = if (effective_tolerance - tolerance).abs() > 0.001 {
-            match FaceRecognizer::new(&config.models_dir, effective_tolerance) {
-                Ok(r) => r,
-                Err(_) => recognizer,
+        let mut frame = match source.read() {
+            Ok(f) => {
+                consecutive_failures = 0;
+                f
+            }
+            Err(_) => {
+                consecutive_failures += 1;
+                continue;
             }
-        } else {
-            recognizer
         };
         
-        // --- Quick frame loop ---
-        let mut frames: u32 = 0;
-        let mut faces_seen: u32 = 0;
-        let mut consecutive_failures: u32 = 0;
+        frames += 1;
+        timings.first_frame.get_or_insert_with(|| deadline.elapsed());
         
-        loop {
-            if start_time.elapsed() >= config.timeout {
-                info!("{}: timeout after {} frames", camera_label, frames);
-                break;
+        // A glitched driver can hand back the same buffer forever, which
+        // otherwise looks just like a face that doesn't match
+        if source.detects_stalls() {
+            let fingerprint = frame_fingerprint(&frame).ok();
+            if fingerprint.is_some() && fingerprint == last_fingerprint {
+                frozen_frames += 1;
+            } else {
+                frozen_frames = 0;
             }
+            last_fingerprint = fingerprint;
             
-            if frames >= config.max_frames_per_camera {
-                info!("{}: {} frames processed, {} faces — moving on",
-                      camera_label, frames, faces_seen);
-                break;
+            if frozen_frames + 1 >= FROZEN_FRAMES {
+                wipe_mat(&mut frame);
+                counter.on_frame(false);
+                streak.reset();
+                
+                if reopened {
+                    warn!("{}: still frozen after reopening — skipping", camera_label);
+                    break;
+                }
+                warn!("{}: same frame {} times in a row — reopening", camera_label, FROZEN_FRAMES);
+                if let Err(e) = source.reopen() {
+                    warn!("{}: reopen failed: {}", camera_label, e);
+                    break;
+                }
+                reopened = true;
+                frozen_frames = 0;
+                last_fingerprint = None;
+                continue;
             }
-            
-            if consecutive_failures >= 5 {
-                warn!("{}: too many read failures", camera_label);
-                break;
+        }
+        
+        if config.detect_obstruction {
+            if is_obstructed(&frame).unwrap_or(false) {
+                dark_frames += 1;
+            } else {
+                dark_frames = 0;
             }
             
-            if frames > 0 {
-                thread::sleep(frame_delay);
+            if dark_frames >= OBSTRUCTED_FRAMES {
+                wipe_mat(&mut frame);
+                counter.on_frame(false);
+                info!("{}: lens looks covered after {} frames — skipping", camera_label, frames);
+                return CameraAttempt { opened_as: Some(camera_type), matched: None, obstructed: true, overexposed: false };
             }
-            
-            let frame = match camera.read() {
-                Ok(f) => {
-                    consecutive_failures = 0;
-                    f
-                }
-                Err(_) => {
-                    consecutive_failures += 1;
-                    continue;
-                }
-            };
-            
-            frames += 1;
-            
-            let faces = match recognizer.detect_faces(&frame) {
-                Ok(f) if !f.is_empty() => f,
-                _ => continue,
+        }
+        
+        // A face against a bright window is too washed out to encode well;
+        // skip such frames rather than match on them
+        if is_overexposed(&frame, config.max_brightness).unwrap_or(false) {
+            wipe_mat(&mut frame);
+            counter.on_frame(false);
+            streak.reset();
+            bright_frames += 1;
+            if bright_frames >= OVEREXPOSED_FRAMES {
+                info!("{}: frames too bright (backlit?) after {} frames — skipping", camera_label, frames);
+                return CameraAttempt { opened_as: Some(camera_type), matched: None, obstructed: false, overexposed: true };
+            }
+            continue;
+        }
+        bright_frames = 0;
+        
+        // Locate only until the face stops moving; encoding a face still
+        // coming into view wastes the frame on a blurred, misaligned match
+        if !settle.settled() {
+            let largest = recognizer.locate_face_rects(&frame).ok()
+                .and_then(|rects| rects.into_iter().max_by_key(|(l, t, r, b)| (r - l) * (b - t)));
+            let Some(rect) = largest else {
+                wipe_mat(&mut frame);
+                counter.on_frame(false);
+                settle.reset();
+                streak.reset();
+                continue;
             };
-            
-            faces_seen += 1;
-            debug!("{}: {} face(s) in frame {}", camera_label, faces.len(), frames);
-            
-            for face in &faces {
-                let faces_to_check: Vec<_> = if let Some(ref target) = config.target_user {
-                    registered_faces.iter()
-                        .filter(|(u, _)| u == target)
-                        .cloned()
-                        .collect()
-                } else {
-                    registered_faces.clone()
-                };
-                
-                if let Some((username, distance)) = recognizer.match_face(&face.encoding, &faces_to_check) {
-                    let elapsed = start_time.elapsed();
-                    info!("Authenticated '{}' via {:?} in {:?} (distance: {:.4})",
-                          username, camera_type, elapsed, distance);
-                    
-                    return cleanup_and_return!(AuthResult::Success {
-                        username,
-                        confidence: 1.0 - distance,
-                        camera_type,
-                    });
-                }
+            if !settle.on_rect(rect) {
+                wipe_mat(&mut frame);
+                counter.on_frame(true);
+                debug!("{}: face still settling in frame {}", camera_label, frames);
+                continue;
             }
         }
         
-        drop(camera);
+        let detected = if config.multi_face {
+            recognizer.detect_faces_batch(&frame)
+        } else {
+            recognizer.detect_faces(&frame)
+        };
+        wipe_mat(&mut frame);
+        
+        let faces = match detected {
+            Ok(scan) if scan.encoding_failed() => {
+                counter.on_frame(true);
+                streak.on_encoding_gap();
+                continue;
+            }
+            Ok(scan) if !scan.faces.is_empty() => scan.faces,
+            _ => {
+                counter.on_frame(false);
+                settle.reset();
+                streak.reset();
+                continue;
+            }
+        };
+        
+        if let Some(distance) = recognizer.closest_distance(&faces, &faces_to_check) {
+            counter.on_distance(distance);
+        }
+        counter.on_frame(true);
+        faces_seen += 1;
+        timings.first_face.get_or_insert_with(|| deadline.elapsed());
+        debug!("{}: {} face(s) in frame {}", camera_label, faces.len(), frames);
+        
+        if let Some(ratio) = recognizer.closest_ratio(&faces, &faces_to_check) {
+            if ratio <= EXTENSION_RANGE {
+                deadline.on_face(ratio);
+            }
+        }
+        
+        let matched = if config.multi_face {
+            recognizer.match_faces(&faces, &faces_to_check)
+        } else {
+            faces.iter()
+                .find_map(|face| recognizer.match_face(&face.encoding, &faces_to_check))
+        };
+        
+        let confirmed = match matched {
+            Some((username, distance)) => streak.on_match(username, distance),
+            None => {
+                streak.reset();
+                None
+            }
+        };
+        
+        if confirmed.is_some() {
+            timings.matched = Some(deadline.elapsed());
+            return CameraAttempt { opened_as: Some(camera_type), matched: confirmed, obstructed: false, overexposed: false };
+        }
     }
     
-    // All cameras tried — face auth failed
-    let elapsed = start_time.elapsed();
-    info!("Face not recognized after {:?} — use your password", elapsed);
-    cleanup_and_return!(AuthResult::NoMatch)
+    CameraAttempt { opened_as: Some(camera_type), matched: None, obstructed: false, overexposed: false }
+}
+
+/// Result of a diagnostic run against one specific device
+#[derive(Debug, Clone)]
+pub struct DeviceAuthReport {
+    pub camera: CameraInfo,
+    /// Type the camera was opened as, `None` if it couldn't be opened
+    pub opened_as: Option<CameraType>,
+    pub result: AuthResult,
 }
 
-fn load_registered_faces(config: &AuthConfig) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
-    if config.data_dir.exists() {
-        let faces = load_all_faces(&config.data_dir)?;
-        if !faces.is_empty() {
-            return Ok(faces);
+/// Run authentication on exactly one device, bypassing camera selection.
+/// For triaging "wrong camera selected" reports from the CLI — the PAM
+/// path never calls this.
+pub fn authenticate_on_device(device_id: i32, config: &AuthConfig) -> DeviceAuthReport {
+    let mut deadline = Deadline::new(config);
+    let camera = camera_info_for_device(device_id);
+    
+    info!("Diagnostic auth on video{} ({}, {:?})", device_id, camera.name, camera.camera_type);
+    
+    let registered_faces = match load_registered_faces(config, None) {
+        Ok(faces) => Templates(faces),
+        Err(e) => {
+            let result = AuthResult::Error(format!("Load faces: {}", e));
+            return DeviceAuthReport { camera, opened_as: None, result };
         }
+    };
+    if !has_comparable_templates(config, &registered_faces) {
+        return DeviceAuthReport { camera, opened_as: None, result: AuthResult::NotEnrolled };
     }
     
-    if let Some(home) = std::env::var_os("HOME") {
-        let home_path = Path::new(&home);
-        
-        // Check XDG data directory first
-        let xdg_data = home_path.join(".local/share/glance");
-        if xdg_data.exists() {
-            let faces = load_all_faces(&xdg_data)?;
-            if !faces.is_empty() {
-                return Ok(faces);
+    let mut timings = StageTimings::default();
+    let mut counter = FrameCounter::new(config, None);
+    let stage = Instant::now();
+    let mut ir_emitter = start_ir_emitter(config);
+    timings.emitter = stage.elapsed();
+    let attempt = try_camera(config, &camera, &registered_faces, &mut deadline, &mut counter, &mut timings);
+    if let Some(ref mut emitter) = ir_emitter {
+        let _ = emitter.disable();
+    }
+    timings.log(deadline.elapsed(), &counter.progress);
+    
+    let opened_as = attempt.opened_as;
+    let result = attempt.into_result(counter.failure_result(&deadline))
+        .unwrap_or_else(|| AuthResult::Error(format!("Could not open /dev/video{}", device_id)));
+    
+    DeviceAuthReport { camera, opened_as, result }
+}
+
+/// Run the auth frame loop on any frame source instead of a real camera,
+/// e.g. `fake_camera::ImageFrameSource` in CI. Camera detection, the IR
+/// emitter and the hard timeout thread are all skipped.
+pub fn authenticate_with_source(source: &mut dyn FrameSource, config: &AuthConfig) -> AuthResult {
+    authenticate_source(source, config, None)
+}
+
+/// `authenticate_with_source`, with faces found and encoded by `embedder`
+/// instead of the dlib models, e.g. `embedder::CannedEmbedder` in tests
+pub fn authenticate_with_embedder(
+    source: &mut dyn FrameSource,
+    config: &AuthConfig,
+    embedder: Box<dyn FaceEmbedder>,
+) -> AuthResult {
+    authenticate_source(source, config, Some(embedder))
+}
+
+fn authenticate_source(
+    source: &mut dyn FrameSource,
+    config: &AuthConfig,
+    embedder: Option<Box<dyn FaceEmbedder>>,
+) -> AuthResult {
+    let mut deadline = Deadline::new(config);
+    
+    let registered_faces = match load_registered_faces(config, None) {
+        Ok(faces) => Templates(faces),
+        Err(e) => return AuthResult::Error(format!("Load faces: {}", e)),
+    };
+    if !has_comparable_templates(config, &registered_faces) {
+        return AuthResult::NotEnrolled;
+    }
+    
+    let mut timings = StageTimings::default();
+    let mut counter = FrameCounter::new(config, None);
+    let attempt = scan_frames(config, source, embedder, &registered_faces, &mut deadline, &mut counter, &mut timings);
+    timings.log(deadline.elapsed(), &counter.progress);
+    
+    attempt.into_result(counter.failure_result(&deadline))
+        .unwrap_or(AuthResult::NoMatch)
+}
+
+/// Whether this attempt has any template to compare against: the target
+/// user's, or anyone's without a target. Templates from another model, and
+/// masked ones without `mask_friendly`, were already dropped while loading.
+fn has_comparable_templates(config: &AuthConfig, registered_faces: &[(String, Vec<Vec<f32>>)]) -> bool {
+    registered_faces.iter()
+        .filter(|(user, _)| config.target_user.as_ref().map_or(true, |target| target == user))
+        .any(|(_, encodings)| !encodings.is_empty())
+}
+
+/// The system data directories: the configured one, then the default
+fn system_data_locations(config: &AuthConfig) -> Vec<PathBuf> {
+    let mut locations = vec![config.data_dir.clone(), PathBuf::from("/var/lib/glance")];
+    locations.dedup();
+    locations
+}
+
+/// Directories enrollment data is read from, highest priority first, each
+/// with the uid its files must belong to, see `UserData`
+fn data_locations(config: &AuthConfig) -> Vec<(PathBuf, Option<u32>)> {
+    let user_dirs = |home: &Path| [home.join(".local/share/glance"), home.join(".config/glance")];
+    let system = system_data_locations(config).into_iter().map(|dir| (dir, None));
+    
+    let mut locations = Vec::new();
+    match &config.user_data {
+        UserData::Env => {
+            if let Some(home) = std::env::var_os("HOME") {
+                locations.extend(user_dirs(Path::new(&home)).map(|dir| (dir, None)));
             }
+            locations.extend(system);
         }
-        
-        // Fall back to config directory
-        let user_config = home_path.join(".config/glance");
-        if user_config.exists() {
-            let faces = load_all_faces(&user_config)?;
-            if !faces.is_empty() {
-                return Ok(faces);
+        UserData::Account { home, uid } => {
+            locations.extend(system);
+            locations.extend(user_dirs(home).map(|dir| (dir, Some(*uid))));
+        }
+        UserData::None => locations.extend(system),
+    }
+    locations
+}
+
+/// The first background captured for `camera_type` cameras, see
+/// `background.rs`. It describes the machine, and a planted one could hide
+/// faces, so only the system locations are read
+fn load_background(config: &AuthConfig, camera_type: CameraType) -> Option<Background> {
+    let name = Background::file_name(camera_type);
+    for path in system_data_locations(config).iter().map(|location| location.join(name)) {
+        if !path.exists() {
+            continue;
+        }
+        match Background::load(&path) {
+            Ok(background) => {
+                debug!("Comparing detections against background {:?}", path);
+                return Some(background);
             }
+            Err(e) => warn!("Skipping background {:?}: {}", path, e),
         }
     }
+    debug!("background_subtraction is on but no {} was captured", name);
+    None
+}
+
+/// Enrolled faces from every storage location, limited to templates from
+/// `camera` type cameras when given. A username found in an earlier
+/// location shadows the same one further down
+fn load_registered_faces(config: &AuthConfig, camera: Option<CameraType>) -> Result<Vec<(String, Vec<Vec<f32>>)>> {
+    let current_model = model_id(&config.models_dir);
     
-    let system_data = Path::new("/var/lib/glance");
-    if system_data.exists() {
-        return load_all_faces(system_data);
+    let mut merged: Vec<(String, Vec<Vec<f32>>)> = Vec::new();
+    for (location, owner) in &data_locations(config) {
+        if !location.exists() {
+            continue;
+        }
+        
+        let filter = TemplateFilter {
+            model_id: current_model.as_deref(),
+            include_masked: config.mask_friendly,
+            camera,
+            owner: *owner,
+        };
+        let faces = match load_all_faces(location, &filter) {
+            Ok(faces) => faces,
+            Err(e) => {
+                warn!("Skipping enrollments in {:?}: {}", location, e);
+                continue;
+            }
+        };
+        
+        for (username, encodings) in faces {
+            if merged.iter().any(|(existing, _)| *existing == username) {
+                debug!("{} in {:?} is shadowed by a higher-priority enrollment", username, location);
+                continue;
+            }
+            merged.push((username, encodings));
+        }
     }
     
-    Ok(Vec::new())
+    Ok(merged)
 }
 
 pub fn authenticate_quick(username: &str, timeout_secs: u64) -> bool {
@@ -403,3 +1627,79 @@ pub fn authenticate_any(timeout_secs: u64) -> Option<String> {
         _ => None,
     }
 }
+
+/// Outcome of `verify_user`
+#[derive(Debug, Clone)]
+pub struct VerifyResult {
+    /// The face in view was confirmed as the requested user
+    pub verified: bool,
+    /// Closest the face in view came to the user's templates, matched or
+    /// not. `None` if no face was ever compared
+    pub best_distance: Option<f64>,
+    /// Camera the match was made on, `None` unless verified
+    pub camera_type: Option<CameraType>,
+    /// The underlying attempt, for why it failed
+    pub result: AuthResult,
+}
+
+/// Check that the person in front of the camera is `username` specifically,
+/// e.g. to confirm a sensitive action, as opposed to logging in. Runs like
+/// `authenticate` with `target_user` fixed (aliases resolved), until the
+/// user is confirmed or the attempt times out. Other enrolled users never
+/// match.
+pub fn verify_user(username: &str, config: &AuthConfig) -> VerifyResult {
+    let mut config = config.clone();
+    config.target_user = Some(enrolled_username(username));
+    
+    let best = Arc::new(Mutex::new(None::<f64>));
+    let seen = Arc::clone(&best);
+    let result = authenticate_with_progress(&config, Some(Box::new(move |p| {
+        if let Ok(mut best) = seen.lock() {
+            *best = p.best_distance;
+        }
+    })));
+    let best_distance = best.lock().ok().and_then(|best| *best);
+    
+    match result {
+        AuthResult::Success { confidence, camera_type, .. } => {
+            let distance = 1.0 - confidence;
+            VerifyResult {
+                verified: true,
+                best_distance: Some(best_distance.map_or(distance, |best| best.min(distance))),
+                camera_type: Some(camera_type),
+                result,
+            }
+        }
+        result => VerifyResult { verified: false, best_distance, camera_type: None, result },
+    }
+}
+
+/// Values in a face encoding from the recognition model
+pub const ENCODING_LEN: usize = 128;
+
+/// Check a face encoding made elsewhere, e.g. captured by a phone and sent
+/// in, against `username`'s enrolled templates without opening a camera.
+/// The match distance if it's within tolerance, `None` if not
+pub fn verify_encoding(username: &str, encoding: &[f32]) -> Result<Option<f64>> {
+    verify_encoding_with(&AuthConfig::load(), username, encoding)
+}
+
+/// `verify_encoding` against the templates and tolerances in `config`.
+/// Needs neither a camera nor the models
+pub fn verify_encoding_with(config: &AuthConfig, username: &str, encoding: &[f32]) -> Result<Option<f64>> {
+    if encoding.len() != ENCODING_LEN {
+        anyhow::bail!("Encoding has {} values, expected {}", encoding.len(), ENCODING_LEN);
+    }
+    if encoding.iter().any(|v| !v.is_finite()) {
+        anyhow::bail!("Encoding contains NaN or infinite values");
+    }
+    
+    let enrolled = enrolled_username(username);
+    let registered_faces = Templates(load_registered_faces(config, None)?);
+    let stored = match registered_faces.iter().find(|(user, _)| *user == enrolled) {
+        Some((_, stored)) if !stored.is_empty() => stored,
+        _ => anyhow::bail!("{} has no usable enrollment", enrolled),
+    };
+    
+    Ok(compare_encoding(config.metric, config.tolerance, config.template_tolerance_penalty, encoding, stored))
+}
@@ -0,0 +1,135 @@
+//! Auto-disable: stop running on a machine where face auth can't work
+//!
+//! Safe mode covers a camera that fails for a while. A machine with no
+//! camera at all, or without the models installed, would instead pay for a
+//! failed attempt on every login, indefinitely. After
+//! `auto_disable_threshold` attempts in a row that find no camera or no
+//! models, the module writes a marker and returns `PAM_IGNORE` straight away
+//! until `glance enable` removes it. Unlike safe mode, the marker lives in
+//! /var/lib/glance and survives reboots.
+
+use crate::auth::AuthConfig;
+use crate::camera::detect_cameras_fast_cached;
+use crate::face::models_present;
+use crate::state_file::{self, now_secs};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+pub const STATE_FILE: &str = "/var/lib/glance/auto_disable.json";
+
+/// What an attempt found missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Missing {
+    Camera,
+    Models,
+}
+
+impl Missing {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Missing::Camera => "no camera found",
+            Missing::Models => "face models not installed",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Attempts in a row that found something missing
+    consecutive: u32,
+    #[serde(default)]
+    missing: Option<Missing>,
+    /// Seconds since the Unix epoch the module disabled itself at, 0 = not
+    #[serde(default)]
+    disabled_at: u64,
+}
+
+/// Why and when the module disabled itself
+#[derive(Debug, Clone, Copy)]
+pub struct Disabled {
+    pub missing: Missing,
+    /// Seconds since the Unix epoch
+    pub since: u64,
+}
+
+fn load() -> State {
+    state_file::load(STATE_FILE).unwrap_or_default()
+}
+
+fn save(state: &State) {
+    state_file::save(STATE_FILE, state, "auto-disable state");
+}
+
+/// The marker, if the module has disabled itself, whatever the config says
+pub fn status() -> Option<Disabled> {
+    let state = load();
+    (state.disabled_at > 0).then(|| Disabled {
+        missing: state.missing.unwrap_or(Missing::Camera),
+        since: state.disabled_at,
+    })
+}
+
+/// The marker, if auto-disable is on and has tripped
+pub(crate) fn active(config: &AuthConfig) -> Option<Disabled> {
+    if config.auto_disable_threshold == 0 {
+        return None;
+    }
+    status()
+}
+
+/// What this machine lacks for face auth, checked only when auto-disable
+/// is on and an attempt has just failed. Models come first: without them no
+/// camera helps. Camera detection goes through the probe cache the failed
+/// attempt has just filled
+pub(crate) fn check(config: &AuthConfig) -> Option<Missing> {
+    if config.auto_disable_threshold == 0 {
+        return None;
+    }
+    if !models_present(&config.models_dir) {
+        return Some(Missing::Models);
+    }
+    match detect_cameras_fast_cached(config.probe_cache_ttl) {
+        Ok(cameras) if cameras.is_empty() => Some(Missing::Camera),
+        _ => None,
+    }
+}
+
+/// Count this attempt's `check` result (`None` for a successful attempt,
+/// which isn't checked), disabling the module once `auto_disable_threshold`
+/// attempts in a row found something missing
+pub(crate) fn record(config: &AuthConfig, missing: Option<Missing>) {
+    if config.auto_disable_threshold == 0 {
+        return;
+    }
+    
+    let mut state = load();
+    let Some(missing) = missing else {
+        if state.consecutive > 0 {
+            save(&State::default());
+        }
+        return;
+    };
+    
+    state.consecutive += 1;
+    state.missing = Some(missing);
+    if state.consecutive >= config.auto_disable_threshold {
+        state.disabled_at = now_secs();
+        warn!("{} on {} attempts in a row — disabling face auth until `glance enable`",
+              missing.label(), state.consecutive);
+    }
+    save(&state);
+}
+
+/// Clear the marker and the count. `Ok(false)` if the module wasn't
+/// disabled
+pub fn enable() -> std::io::Result<bool> {
+    let disabled = status().is_some();
+    match fs::remove_file(STATE_FILE) {
+        Ok(()) => Ok(disabled),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,178 @@
+//! Background subtraction: ignore faces that are part of the scenery
+//!
+//! Posters and photos on the wall behind a kiosk can be detected as faces.
+//! With `camera.background_subtraction`, a reference frame of the empty
+//! scene, captured at enrollment with nobody in view, is compared against
+//! each frame. A detection whose area still looks like the reference is
+//! static background rather than someone standing there, and is dropped
+//! before encoding.
+//!
+//! The reference is kept small, grey and blurred: enough to tell what
+//! changed, not to make out the room. It's stored per camera type next to
+//! the face data, as `ir.background` / `rgb.background`.
+
+use opencv::{core::Mat, prelude::*};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::camera::CameraType;
+use crate::embedder::FaceRect;
+use crate::wipe::wipe_mat;
+
+/// Width the reference is stored and compared at
+const BACKGROUND_WIDTH: i32 = 160;
+/// Grey levels a pixel must differ by from the reference to count as changed
+const CHANGED_LEVEL: f64 = 25.0;
+/// A detection with less of its area changed than this is background
+const MIN_CHANGED_FRACTION: f64 = 0.2;
+
+/// On-disk form of a `Background`
+#[derive(Serialize, Deserialize)]
+struct BackgroundFile {
+    width: i32,
+    height: i32,
+    /// Grey pixels, row by row, base64
+    pixels: String,
+}
+
+/// The empty scene in front of one camera, see the module docs
+pub struct Background {
+    gray: Mat,
+}
+
+impl Background {
+    /// Reference from an 8-bit grey or BGR frame with nobody in view
+    pub fn from_frame(frame: &Mat) -> Result<Self> {
+        Ok(Self { gray: small_gray(frame)? })
+    }
+    
+    /// `from_frame` for a tightly packed RGB24 frame, as the GUI captures
+    pub fn from_rgb(rgb: &[u8], width: u32, height: u32) -> Result<Self> {
+        use opencv::imgproc;
+        
+        anyhow::ensure!(rgb.len() == width as usize * height as usize * 3,
+                        "RGB frame is {} bytes, expected {}x{}x3", rgb.len(), width, height);
+        let flat = Mat::from_slice(rgb)?;
+        let image = flat.reshape(3, height as i32)?;
+        let mut bgr = Mat::default();
+        imgproc::cvt_color(&*image, &mut bgr, imgproc::COLOR_RGB2BGR, 0)?;
+        let background = Self::from_frame(&bgr);
+        wipe_mat(&mut bgr);
+        background
+    }
+    
+    /// File name the reference for `camera_type` cameras is stored under
+    pub fn file_name(camera_type: CameraType) -> &'static str {
+        match camera_type {
+            CameraType::Infrared => "ir.background",
+            _ => "rgb.background",
+        }
+    }
+    
+    pub fn load(path: &Path) -> Result<Self> {
+        use base64::{Engine as _, engine::general_purpose};
+        
+        let content = std::fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+        let file: BackgroundFile = serde_json::from_str(&content)?;
+        let pixels = general_purpose::STANDARD.decode(&file.pixels)?;
+        anyhow::ensure!(file.width > 0 && file.height > 0
+                        && pixels.len() == file.width as usize * file.height as usize,
+                        "{:?} holds {} pixels, not {}x{}", path, pixels.len(), file.width, file.height);
+        
+        let flat = Mat::from_slice(&pixels)?;
+        let gray = flat.reshape(1, file.height)?.try_clone()?;
+        Ok(Self { gray })
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        use base64::{Engine as _, engine::general_purpose};
+        
+        let file = BackgroundFile {
+            width: self.gray.cols(),
+            height: self.gray.rows(),
+            pixels: general_purpose::STANDARD.encode(self.gray.data_bytes()?),
+        };
+        std::fs::write(path, serde_json::to_string(&file)?).with_context(|| format!("Writing {:?}", path))
+    }
+    
+    /// `rects` without the ones over unchanged background. If `frame` can't
+    /// be compared (another resolution or aspect), nothing is dropped
+    pub fn drop_static(&self, frame: &Mat, rects: Vec<FaceRect>) -> Result<Vec<FaceRect>> {
+        use opencv::core::{self as cvcore, Rect};
+        use opencv::imgproc;
+        
+        if rects.is_empty() {
+            return Ok(rects);
+        }
+        let mut small = small_gray(frame)?;
+        if small.size()? != self.gray.size()? {
+            wipe_mat(&mut small);
+            log::debug!("Background reference is {}x{}, frame scales to {}x{} — not comparing",
+                        self.gray.cols(), self.gray.rows(), small.cols(), small.rows());
+            return Ok(rects);
+        }
+        
+        let mut diff = Mat::default();
+        let compared = cvcore::absdiff(&small, &self.gray, &mut diff);
+        wipe_mat(&mut small);
+        compared?;
+        let mut changed = Mat::default();
+        imgproc::threshold(&diff, &mut changed, CHANGED_LEVEL, 255.0, imgproc::THRESH_BINARY)?;
+        
+        let scale = small_scale(frame);
+        let (cols, rows) = (changed.cols() as i64, changed.rows() as i64);
+        let mut kept = Vec::with_capacity(rects.len());
+        for rect in rects {
+            let (left, top, right, bottom) = rect;
+            let at = |v: i64, max: i64| ((v as f64 * scale).round() as i64).clamp(0, max);
+            let (x0, y0, x1, y1) = (at(left, cols), at(top, rows), at(right, cols), at(bottom, rows));
+            if x1 <= x0 || y1 <= y0 {
+                kept.push(rect);
+                continue;
+            }
+            
+            let area = Rect::new(x0 as i32, y0 as i32, (x1 - x0) as i32, (y1 - y0) as i32);
+            let region = Mat::roi(&changed, area)?;
+            let fraction = cvcore::count_non_zero(&*region)? as f64 / area.area() as f64;
+            if fraction < MIN_CHANGED_FRACTION {
+                log::debug!("Ignoring a face over static background ({:.0}% changed)", fraction * 100.0);
+            } else {
+                kept.push(rect);
+            }
+        }
+        Ok(kept)
+    }
+}
+
+/// Factor frames are scaled by to `BACKGROUND_WIDTH`
+fn small_scale(frame: &Mat) -> f64 {
+    BACKGROUND_WIDTH as f64 / frame.cols().max(1) as f64
+}
+
+/// `frame` grey, scaled to `BACKGROUND_WIDTH` (aspect kept) and blurred, so
+/// sensor noise and small camera shake don't count as change
+fn small_gray(frame: &Mat) -> Result<Mat> {
+    use opencv::core::{Size, BORDER_DEFAULT};
+    use opencv::imgproc;
+    
+    let mut gray = Mat::default();
+    match frame.channels() {
+        1 => frame.copy_to(&mut gray)?,
+        3 => imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?,
+        n => anyhow::bail!("Background needs a grey or BGR frame, got {} channels", n),
+    }
+    
+    let scale = small_scale(frame);
+    let size = Size::new(BACKGROUND_WIDTH, ((frame.rows() as f64 * scale).round() as i32).max(1));
+    let mut small = Mat::default();
+    let resized = imgproc::resize(&gray, &mut small, size, 0.0, 0.0, imgproc::INTER_AREA);
+    wipe_mat(&mut gray);
+    resized?;
+    
+    let mut blurred = Mat::default();
+    let result = imgproc::gaussian_blur(&small, &mut blurred, Size::new(5, 5), 0.0, 0.0, BORDER_DEFAULT);
+    wipe_mat(&mut small);
+    result?;
+    Ok(blurred)
+}
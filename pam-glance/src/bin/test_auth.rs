@@ -0,0 +1,160 @@
+//! Run face authentication from the command line, outside PAM.
+//!
+//!     test_auth [--device N|/dev/videoN] [--user NAME] [--timeout SECS] [--data-dir DIR] [--frames DIR]
+//!
+//! With `--device` (or its alias `--camera`), camera selection is bypassed
+//! and only that device is tried, which helps isolate "wrong camera
+//! selected" problems over SSH and lets `scripts/loopback-test.sh` point
+//! auth at a v4l2loopback node. `--data-dir` reads face data from DIR
+//! instead of /var/lib/glance.
+//!
+//! In builds with the `fake-camera` feature, `--frames DIR` replays the
+//! images in DIR instead of opening any camera, so the detection and
+//! matching pipeline can be exercised in CI.
+
+use pam_glance::auth::{self, AuthConfig, AuthResult};
+use pam_glance::camera;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+fn parse_device(value: &str) -> Option<i32> {
+    if camera::is_stable_device_path(value) {
+        return camera::resolve_device(value).ok();
+    }
+    value.strip_prefix("/dev/video").unwrap_or(value).parse().ok()
+}
+
+#[cfg(feature = "fake-camera")]
+fn authenticate_frames(dir: &Path, config: &AuthConfig) -> AuthResult {
+    match pam_glance::fake_camera::ImageFrameSource::from_dir(dir, false) {
+        Ok(mut source) => {
+            println!("Frames:      {}", dir.display());
+            auth::authenticate_with_source(&mut source, config)
+        }
+        Err(e) => AuthResult::Error(e.to_string()),
+    }
+}
+
+#[cfg(not(feature = "fake-camera"))]
+fn authenticate_frames(_dir: &Path, _config: &AuthConfig) -> AuthResult {
+    AuthResult::Error("--frames needs a build with --features fake-camera".to_string())
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    
+    let mut config = AuthConfig::load();
+    config.target_user = env::var("USER").ok();
+    let mut device = None;
+    let mut frames_dir = None;
+    
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--device" | "--camera" => match iter.next().and_then(|v| parse_device(v)) {
+                Some(id) => device = Some(id),
+                None => {
+                    eprintln!("--device needs a device number, /dev/videoN or /dev/v4l/by-id/... path");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--frames" => match iter.next() {
+                Some(dir) => frames_dir = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("--frames needs a directory of images");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--user" => config.target_user = iter.next().cloned(),
+            "--data-dir" => match iter.next() {
+                Some(dir) => config.data_dir = PathBuf::from(dir),
+                None => {
+                    eprintln!("--data-dir needs a directory");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--timeout" => match iter.next().and_then(|v| v.parse::<f64>().ok()) {
+                Some(secs) => config.timeout = Duration::from_secs_f64(secs),
+                None => {
+                    eprintln!("--timeout needs a number of seconds");
+                    return ExitCode::FAILURE;
+                }
+            },
+            other => {
+                eprintln!("Unknown argument: {}", other);
+                eprintln!("Usage: test_auth [--device N|/dev/videoN] [--user NAME] [--timeout SECS] [--data-dir DIR] [--frames DIR]");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    
+    let result = if let Some(ref dir) = frames_dir {
+        authenticate_frames(dir, &config)
+    } else {
+        match device {
+            Some(device_id) => {
+                let report = auth::authenticate_on_device(device_id, &config);
+                println!("Device:      {} ({})", report.camera.device_path, report.camera.name);
+                println!("Name says:   {}", report.camera.camera_type.label());
+                match report.opened_as {
+                    Some(camera_type) => println!("Opened as:   {}", camera_type.label()),
+                    None => println!("Opened as:   (could not open)"),
+                }
+                match camera::verify_camera_type(device_id, config.grayscale_threshold) {
+                    Ok(camera_type) => println!("Frames say:  {}", camera_type.label()),
+                    Err(e) => println!("Frames say:  (could not sample: {})", e),
+                }
+                report.result
+            }
+            None => auth::authenticate(&config),
+        }
+    };
+    
+    match result {
+        AuthResult::Success { username, confidence, camera_type } => {
+            println!("Result:      matched '{}' via {} (confidence {:.2})",
+                     username, camera_type.label(), confidence);
+            ExitCode::SUCCESS
+        }
+        AuthResult::NoFaceDetected => {
+            println!("Result:      no face detected");
+            ExitCode::FAILURE
+        }
+        AuthResult::NoMatch => {
+            println!("Result:      no match");
+            ExitCode::FAILURE
+        }
+        AuthResult::NotEnrolled => {
+            println!("Result:      nothing enrolled to compare against (cameras not opened)");
+            ExitCode::FAILURE
+        }
+        AuthResult::SafeMode => {
+            println!("Result:      skipped, cameras kept failing to open (safe mode, see /run/glance/camera_failures.json)");
+            ExitCode::FAILURE
+        }
+        AuthResult::CameraMismatch { enrolled } => {
+            println!("Result:      no camera of the enrolled type ({}), strict_camera_match",
+                     enrolled.map_or("none recorded", |c| c.label()));
+            ExitCode::FAILURE
+        }
+        AuthResult::CameraObstructed => {
+            println!("Result:      camera covered (privacy shutter?)");
+            ExitCode::FAILURE
+        }
+        AuthResult::Overexposed => {
+            println!("Result:      frames too bright (backlit?), see camera.max_brightness");
+            ExitCode::FAILURE
+        }
+        AuthResult::Timeout { .. } => {
+            println!("Result:      {}", result.timeout_summary().unwrap_or_default());
+            ExitCode::FAILURE
+        }
+        AuthResult::Error(e) => {
+            println!("Result:      error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -5,16 +5,91 @@ use opencv::{
 };
 use anyhow::{Result, Context};
 use log::{info, debug, warn};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use crate::state_file::{self, now_secs};
+use crate::wipe::wipe_mat;
+
+/// Longest frame side kept by default; larger frames are scaled down before
+/// detection so a 4K webcam costs about what a 720p one does
+pub const DEFAULT_MAX_FRAME_DIMENSION: u32 = 1280;
+
+/// Reads attempted before an empty frame counts as a failure
+const EMPTY_FRAME_RETRIES: u32 = 3;
+const EMPTY_FRAME_BACKOFF_MS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CameraType {
     Infrared,
     Rgb,
     Unknown,
 }
 
-#[derive(Debug, Clone)]
+impl CameraType {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CameraType::Infrared => "IR",
+            CameraType::Rgb => "RGB",
+            CameraType::Unknown => "Unknown",
+        }
+    }
+}
+
+/// Part of the frame faces are looked for in, as fractions of its width and
+/// height. Everything outside is blacked out before detection, so a face
+/// reflected in a monitor or window at the edge of the view is ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DetectionRoi {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Default for DetectionRoi {
+    fn default() -> Self {
+        Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }
+    }
+}
+
+impl DetectionRoi {
+    /// Covers the whole frame, so there is nothing to mask
+    pub fn is_full(&self) -> bool {
+        self.x <= 0.0 && self.y <= 0.0 && self.x + self.width >= 1.0 && self.y + self.height >= 1.0
+    }
+    
+    /// `(x, y, width, height)` in pixels for a `cols`×`rows` frame, clamped to it
+    pub fn pixel_rect(&self, cols: i32, rows: i32) -> (i32, i32, i32, i32) {
+        let scale = |fraction: f64, size: i32| (fraction.clamp(0.0, 1.0) * size as f64).round() as i32;
+        let (left, top) = (scale(self.x, cols), scale(self.y, rows));
+        let (right, bottom) = (scale(self.x + self.width, cols), scale(self.y + self.height, rows));
+        (left, top, (right - left).max(0), (bottom - top).max(0))
+    }
+    
+    /// Copy of `frame` with everything outside the ROI black. Coordinates
+    /// stay those of the full frame
+    pub fn mask(&self, frame: &Mat) -> Result<Mat> {
+        use opencv::core::{Rect, Scalar, CV_8UC1};
+        use opencv::imgproc;
+        
+        let (cols, rows) = (frame.cols(), frame.rows());
+        let (x, y, width, height) = self.pixel_rect(cols, rows);
+        
+        let mut mask = Mat::new_rows_cols_with_default(rows, cols, CV_8UC1, Scalar::all(0.0))?;
+        if width > 0 && height > 0 {
+            imgproc::rectangle(&mut mask, Rect::new(x, y, width, height), Scalar::all(255.0),
+                               imgproc::FILLED, imgproc::LINE_8, 0)?;
+        }
+        
+        let mut masked = Mat::new_rows_cols_with_default(rows, cols, frame.typ(), Scalar::all(0.0))?;
+        frame.copy_to_masked(&mut masked, &mask)?;
+        Ok(masked)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CameraInfo {
     pub device_id: i32,
     pub device_path: String,
@@ -26,11 +101,14 @@ pub struct SmartCamera {
     capture: VideoCapture,
     pub camera_info: CameraInfo,
     pub is_ir: bool,
+    /// See `set_max_dimension`
+    max_dimension: u32,
 }
 
 impl SmartCamera {
     pub fn open(prefer_ir: bool, ir_device: &str, rgb_device: &str) -> Result<Self> {
-        let cameras = detect_cameras()?;
+        let mut cameras = detect_cameras_cached(DEFAULT_PROBE_CACHE_TTL)?;
+        pin_configured_devices(&mut cameras, ir_device, rgb_device);
         
         if cameras.is_empty() {
             anyhow::bail!("No cameras detected");
@@ -58,12 +136,7 @@ impl SmartCamera {
         // Try each camera until one works
         let mut last_error = String::new();
         for camera_info in cameras_to_try {
-            info!("Trying camera: {} ({})", camera_info.name, 
-                  match camera_info.camera_type {
-                      CameraType::Infrared => "IR",
-                      CameraType::Rgb => "RGB",
-                      CameraType::Unknown => "Unknown",
-                  });
+            info!("Trying camera: {} ({})", camera_info.name, camera_info.camera_type.label());
             
             match VideoCapture::new(camera_info.device_id, videoio::CAP_V4L2) {
                 Ok(mut capture) => {
@@ -81,6 +154,7 @@ impl SmartCamera {
                                 capture,
                                 camera_info: camera_info.clone(),
                                 is_ir,
+                                max_dimension: DEFAULT_MAX_FRAME_DIMENSION,
                             });
                         } else {
                             warn!("Camera video{} opened but couldn't read frames", camera_info.device_id);
@@ -99,18 +173,33 @@ impl SmartCamera {
             }
         }
         
+        invalidate_probe_cache();
         anyhow::bail!("No working camera found. Last error: {}", last_error)
     }
     
+    /// Read a frame. Some drivers return an occasional empty frame between
+    /// valid ones, so a few quick retries happen here before giving up.
     pub fn read(&mut self) -> Result<Mat> {
         let mut frame = Mat::default();
-        self.capture.read(&mut frame)?;
         
-        if frame.empty() {
-            anyhow::bail!("Empty frame captured");
+        for attempt in 0..EMPTY_FRAME_RETRIES {
+            if attempt > 0 {
+                std::thread::sleep(std::time::Duration::from_millis(EMPTY_FRAME_BACKOFF_MS));
+            }
+            
+            self.capture.read(&mut frame)?;
+            if !frame.empty() {
+                return clamp_frame(normalize_frame(frame)?, self.max_dimension);
+            }
         }
         
-        Ok(frame)
+        anyhow::bail!("Empty frame captured ({} attempts)", EMPTY_FRAME_RETRIES)
+    }
+    
+    /// Scale frames down so neither side exceeds `max` pixels. 0 = keep
+    /// the camera's size
+    pub fn set_max_dimension(&mut self, max: u32) {
+        self.max_dimension = max;
     }
     
     pub fn check_brightness(&mut self, min_brightness: f64) -> Result<bool> {
@@ -158,10 +247,65 @@ impl SmartCamera {
             capture,
             camera_info: info.clone(),
             is_ir,
+            max_dimension: DEFAULT_MAX_FRAME_DIMENSION,
         })
     }
 }
 
+impl SmartCamera {
+    /// List the cameras auth would try, in the order it would try them,
+    /// without opening any of them for capture. Uses sysfs only, so it is
+    /// cheap enough for a greeter to call before starting auth.
+    pub fn probe() -> Vec<CameraInfo> {
+        match detect_cameras_fast() {
+            Ok(cameras) => prioritize_cameras(cameras, true),
+            Err(e) => {
+                debug!("Camera probe failed: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Anything the auth frame loop can read frames from. `SmartCamera` is the
+/// real one; `fake_camera::ImageFrameSource` (feature `fake-camera`) replays
+/// images so the pipeline can run without hardware.
+pub trait FrameSource {
+    fn read(&mut self) -> Result<Mat>;
+    
+    /// Whether frames are IR, which picks the tolerance
+    fn is_ir(&self) -> bool;
+    
+    /// Whether a run of identical frames means the stream has stalled.
+    /// Sources replaying still images repeat frames by design.
+    fn detects_stalls(&self) -> bool {
+        true
+    }
+    
+    /// Close and reopen the device after it stalled
+    fn reopen(&mut self) -> Result<()> {
+        anyhow::bail!("This frame source can't be reopened")
+    }
+}
+
+impl FrameSource for SmartCamera {
+    fn read(&mut self) -> Result<Mat> {
+        SmartCamera::read(self)
+    }
+    
+    fn is_ir(&self) -> bool {
+        self.is_ir
+    }
+    
+    fn reopen(&mut self) -> Result<()> {
+        let _ = self.capture.release();
+        let max_dimension = self.max_dimension;
+        *self = SmartCamera::open_direct(&self.camera_info)?;
+        self.max_dimension = max_dimension;
+        Ok(())
+    }
+}
+
 impl Drop for SmartCamera {
     fn drop(&mut self) {
         let _ = self.capture.release();
@@ -209,6 +353,7 @@ pub fn detect_cameras() -> Result<Vec<CameraInfo>> {
         }
     }
     
+    let mut cameras = dedupe_camera_nodes(cameras, |c| c.device_id);
     cameras.sort_by(|a, b| {
         match (&a.camera_type, &b.camera_type) {
             (CameraType::Infrared, CameraType::Infrared) => a.device_id.cmp(&b.device_id),
@@ -226,6 +371,161 @@ pub fn detect_cameras() -> Result<Vec<CameraInfo>> {
     Ok(cameras)
 }
 
+/// Detection results kept between invocations, so back-to-back sudo or
+/// login attempts don't rescan (and, for `detect_cameras`, flash) every device
+const PROBE_CACHE: &str = "/run/glance/cameras.json";
+/// How long a cached probe is reused unless `camera.probe_cache_ttl` says otherwise
+pub const DEFAULT_PROBE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize)]
+struct ProbeCache {
+    /// From `detect_cameras` (devices opened) rather than `detect_cameras_fast`
+    full: bool,
+    /// Seconds since the Unix epoch
+    created: u64,
+    cameras: Vec<CameraInfo>,
+}
+
+fn read_probe_cache(full: bool, ttl: Duration) -> Option<Vec<CameraInfo>> {
+    use std::os::unix::fs::MetadataExt;
+    
+    let metadata = std::fs::metadata(PROBE_CACHE).ok()?;
+    // Camera choice decides what auth looks at, so only root's cache (or
+    // one this user wrote) is trusted
+    let owner = metadata.uid();
+    if owner != 0 && owner != unsafe { libc::geteuid() } {
+        warn!("Ignoring {} owned by uid {}", PROBE_CACHE, owner);
+        return None;
+    }
+    
+    let cache: ProbeCache = state_file::load(PROBE_CACHE)?;
+    if cache.full != full || now_secs().saturating_sub(cache.created) > ttl.as_secs() {
+        return None;
+    }
+    // Unplugged since: rescan rather than hand out a dead node
+    if !cache.cameras.iter().all(|c| Path::new(&c.device_path).exists()) {
+        return None;
+    }
+    Some(cache.cameras)
+}
+
+fn write_probe_cache(full: bool, cameras: &[CameraInfo]) {
+    let cache = ProbeCache { full, created: now_secs(), cameras: cameras.to_vec() };
+    state_file::save(PROBE_CACHE, &cache, "camera probe");
+}
+
+/// `frame` as 8-bit grey or BGR, the formats detection and the brightness
+/// checks understand. 16-bit frames (IR and depth sensors that fill only 10
+/// or 12 bits) are stretched over the range they use, BGRA loses its alpha
+/// and packed YUYV is decoded. Any other format is an error naming it rather
+/// than pixels read the wrong way. A converted original is wiped
+pub fn normalize_frame(mut frame: Mat) -> Result<Mat> {
+    use opencv::core::{self as cvcore, CV_16U, CV_8U};
+    use opencv::imgproc;
+    
+    let channels = frame.channels();
+    let mut frame = match frame.depth() {
+        CV_8U => frame,
+        CV_16U => {
+            let mut scaled = Mat::default();
+            let converted = cvcore::normalize(&frame, &mut scaled, 0.0, 255.0,
+                                              cvcore::NORM_MINMAX, CV_8U, &cvcore::no_array());
+            wipe_mat(&mut frame);
+            converted?;
+            scaled
+        }
+        depth => anyhow::bail!("Unsupported frame format: depth {} with {} channel(s)",
+                               cvcore::depth_to_string(depth).unwrap_or_else(|_| depth.to_string()), channels),
+    };
+    
+    let code = match channels {
+        1 | 3 => return Ok(frame),
+        2 => imgproc::COLOR_YUV2BGR_YUYV,
+        4 => imgproc::COLOR_BGRA2BGR,
+        n => anyhow::bail!("Unsupported frame format: {} channels", n),
+    };
+    let mut bgr = Mat::default();
+    let converted = imgproc::cvt_color(&frame, &mut bgr, code, 0);
+    wipe_mat(&mut frame);
+    converted?;
+    Ok(bgr)
+}
+
+/// `frame`, scaled down (aspect kept) if its longer side exceeds `max`
+/// pixels. The full-size original is wiped. `max` 0 = unchanged
+pub fn clamp_frame(mut frame: Mat, max: u32) -> Result<Mat> {
+    use opencv::core::Size;
+    use opencv::imgproc;
+    
+    let longest = frame.cols().max(frame.rows());
+    if max == 0 || longest <= max as i32 {
+        return Ok(frame);
+    }
+    
+    let scale = max as f64 / longest as f64;
+    let mut small = Mat::default();
+    let resized = imgproc::resize(&frame, &mut small, Size::default(), scale, scale, imgproc::INTER_AREA);
+    wipe_mat(&mut frame);
+    resized?;
+    Ok(small)
+}
+
+/// Drop the cached probe, e.g. after a cached device failed to open
+pub fn invalidate_probe_cache() {
+    if std::fs::remove_file(PROBE_CACHE).is_ok() {
+        debug!("Camera probe cache invalidated");
+    }
+}
+
+fn cached_probe(full: bool, ttl: Duration, detect: fn() -> Result<Vec<CameraInfo>>) -> Result<Vec<CameraInfo>> {
+    if ttl.is_zero() {
+        return detect();
+    }
+    
+    if let Some(cameras) = read_probe_cache(full, ttl) {
+        debug!("Using cached camera probe ({} device(s))", cameras.len());
+        return Ok(cameras);
+    }
+    
+    let cameras = detect()?;
+    if !cameras.is_empty() {
+        write_probe_cache(full, &cameras);
+    }
+    Ok(cameras)
+}
+
+/// `detect_cameras`, reusing a probe younger than `ttl` (zero = always rescan)
+pub fn detect_cameras_cached(ttl: Duration) -> Result<Vec<CameraInfo>> {
+    cached_probe(true, ttl, detect_cameras)
+}
+
+/// `detect_cameras_fast`, reusing a probe younger than `ttl` (zero = always rescan)
+pub fn detect_cameras_fast_cached(ttl: Duration) -> Result<Vec<CameraInfo>> {
+    cached_probe(false, ttl, detect_cameras_fast)
+}
+
+/// Order cameras by preference: the preferred type first, then the other,
+/// then anything unclassified. Device order is kept within each group.
+pub fn prioritize_cameras(cameras: Vec<CameraInfo>, prefer_ir: bool) -> Vec<CameraInfo> {
+    let (first, second) = if prefer_ir {
+        (CameraType::Infrared, CameraType::Rgb)
+    } else {
+        (CameraType::Rgb, CameraType::Infrared)
+    };
+    
+    let mut sorted = cameras;
+    sorted.sort_by_key(|c| {
+        if c.camera_type == first {
+            0
+        } else if c.camera_type == second {
+            1
+        } else {
+            2
+        }
+    });
+    sorted
+}
+
 /// Fast camera detection using sysfs only — no opencv opens.
 /// Returns camera devices based on sysfs metadata (near-instant).
 /// Actual camera verification happens when open_direct() is called.
@@ -276,10 +576,116 @@ pub fn detect_cameras_fast() -> Result<Vec<CameraInfo>> {
     }
 
     cameras.sort_by_key(|c| c.device_id);
+    let cameras = dedupe_camera_nodes(cameras, |c| c.device_id);
     debug!("Fast-detected {} camera device(s)", cameras.len());
     Ok(cameras)
 }
 
+/// sysfs path of the hardware behind `/dev/videoN`: the USB interface (or
+/// platform device) that every node of one sensor hangs off. An IR+RGB
+/// module has one interface per sensor, so the two stay apart
+pub fn physical_device_path(device_id: i32) -> Option<PathBuf> {
+    std::fs::canonicalize(format!("/sys/class/video4linux/video{}/device", device_id)).ok()
+}
+
+/// The node's `index` within its device; 0 is normally the capture node
+fn node_index(device_id: i32) -> i32 {
+    std::fs::read_to_string(format!("/sys/class/video4linux/video{}/index", device_id))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Keep one node per physical camera, so a sensor exposing several
+/// `/dev/videoN` nodes is listed (and tried) once. Of nodes sharing a
+/// device the lowest index, then the lowest number, is kept, in the
+/// position of the first one. Nodes without a sysfs device are kept as
+/// they are
+pub fn dedupe_camera_nodes<T>(nodes: Vec<T>, device_id: impl Fn(&T) -> i32) -> Vec<T> {
+    let mut kept: Vec<(Option<PathBuf>, T)> = Vec::new();
+    for node in nodes {
+        let id = device_id(&node);
+        let physical = physical_device_path(id);
+        let same = physical.as_ref()
+            .and_then(|path| kept.iter_mut().find(|(other, _)| other.as_ref() == Some(path)));
+        match same {
+            Some((path, existing)) => {
+                let existing_id = device_id(existing);
+                let primary = (node_index(id), id) < (node_index(existing_id), existing_id);
+                let (used, dropped) = if primary { (id, existing_id) } else { (existing_id, id) };
+                info!("video{} and video{} are the same camera ({:?}), using video{}",
+                      used, dropped, path.as_deref().unwrap_or(Path::new("?")), used);
+                if primary {
+                    *existing = node;
+                }
+            }
+            None => kept.push((physical, node)),
+        }
+    }
+    kept.into_iter().map(|(_, node)| node).collect()
+}
+
+/// Describe one device from sysfs, whether or not detection would pick it
+/// Resolve a device path to its current `/dev/videoN` index. Accepts the
+/// udev symlinks under `/dev/v4l/by-id/` and `/dev/v4l/by-path/`, which stay
+/// the same across reboots while the numbering doesn't.
+pub fn resolve_device(path: &str) -> Result<i32> {
+    let target = std::fs::canonicalize(path)
+        .with_context(|| format!("Camera device {} not found", path))?;
+    
+    let device_id = target.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.strip_prefix("video"))
+        .and_then(|n| n.parse::<i32>().ok())
+        .filter(|_| target.parent() == Some(Path::new("/dev")))
+        .with_context(|| format!("{} points to {:?}, not a video device", path, target))?;
+    
+    if !Path::new(&format!("/sys/class/video4linux/video{}", device_id)).exists() {
+        anyhow::bail!("{} points to {:?}, which isn't a video4linux node", path, target);
+    }
+    
+    Ok(device_id)
+}
+
+/// Whether `path` is one of udev's stable camera symlinks
+pub fn is_stable_device_path(path: &str) -> bool {
+    path.starts_with("/dev/v4l/")
+}
+
+/// Trust the configured IR/RGB devices over the name-based type guess, but
+/// only when they're stable symlinks. Plain `/dev/videoN` settings can point
+/// at a different camera after a reboot, so those are left to detection.
+pub fn pin_configured_devices(cameras: &mut [CameraInfo], ir_device: &str, rgb_device: &str) {
+    for (path, camera_type) in [(ir_device, CameraType::Infrared), (rgb_device, CameraType::Rgb)] {
+        if !is_stable_device_path(path) {
+            continue;
+        }
+        
+        match resolve_device(path) {
+            Ok(device_id) => {
+                if let Some(camera) = cameras.iter_mut().find(|c| c.device_id == device_id) {
+                    debug!("{} is video{}, using it as the {} camera", path, device_id, camera_type.label());
+                    camera.camera_type = camera_type;
+                }
+            }
+            Err(e) => warn!("Configured {} camera unavailable: {}", camera_type.label(), e),
+        }
+    }
+}
+
+pub fn camera_info_for_device(device_id: i32) -> CameraInfo {
+    let name = std::fs::read_to_string(format!("/sys/class/video4linux/video{}/name", device_id))
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|_| format!("Camera {}", device_id));
+    
+    CameraInfo {
+        device_id,
+        device_path: format!("/dev/video{}", device_id),
+        camera_type: detect_camera_type(&name),
+        name,
+    }
+}
+
 fn detect_camera_type(name: &str) -> CameraType {
     let name_lower = name.to_lowercase();
     
@@ -363,50 +769,178 @@ fn calculate_brightness(frame: &Mat) -> Result<f64> {
     Ok(mean[0])
 }
 
-pub fn verify_camera_type(device_id: i32) -> Result<CameraType> {
+/// A covered lens gives a frame that is both very dark and nearly flat; a dim
+/// room still has some texture, which keeps the deviation above this
+const OBSTRUCTED_MAX_MEAN: f64 = 10.0;
+const OBSTRUCTED_MAX_STDDEV: f64 = 4.0;
+/// Even a dim room has a brighter patch somewhere (a window, a screen); a
+/// shutter leaves the whole frame black. Measured on 8x8 block averages so
+/// single hot pixels don't count.
+const OBSTRUCTED_MAX_BRIGHTNESS: f64 = 24.0;
+
+/// Mean brightness above which a frame counts as washed out by default
+pub const DEFAULT_MAX_BRIGHTNESS: f64 = 230.0;
+
+/// Whether a frame's mean brightness is above `max_brightness` (0 – 255),
+/// e.g. a face in front of a bright window. `max_brightness` 0 = never
+pub fn is_overexposed(frame: &Mat, max_brightness: f64) -> Result<bool> {
+    Ok(max_brightness > 0.0 && calculate_brightness(frame)? > max_brightness)
+}
+
+/// Whether a frame looks like the lens is covered, e.g. by a privacy shutter,
+/// as opposed to merely too dark for detection
+pub fn is_obstructed(frame: &Mat) -> Result<bool> {
+    use opencv::imgproc;
+    
+    let gray = if frame.channels() > 1 {
+        let mut gray = Mat::default();
+        imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+        gray
+    } else {
+        frame.clone()
+    };
+    
+    let mut mean = Vector::<f64>::new();
+    let mut stddev = Vector::<f64>::new();
+    opencv::core::mean_std_dev(&gray, &mut mean, &mut stddev, &Mat::default())?;
+    
+    if mean.get(0)? >= OBSTRUCTED_MAX_MEAN || stddev.get(0)? >= OBSTRUCTED_MAX_STDDEV {
+        return Ok(false);
+    }
+    
+    let mut blocks = Mat::default();
+    let size = opencv::core::Size::new((gray.cols() / 8).max(1), (gray.rows() / 8).max(1));
+    imgproc::resize(&gray, &mut blocks, size, 0.0, 0.0, imgproc::INTER_AREA)?;
+    
+    let mut max = 0.0;
+    opencv::core::min_max_loc(&blocks, None, Some(&mut max), None, None, &Mat::default())?;
+    
+    Ok(max < OBSTRUCTED_MAX_BRIGHTNESS)
+}
+
+/// Fingerprint of a frame for spotting a driver that keeps returning the
+/// same buffer. Pixels are sampled on a grid rather than averaged, so sensor
+/// noise still tells two frames of a static scene apart.
+pub fn frame_fingerprint(frame: &Mat) -> Result<u64> {
+    use std::hash::{Hash, Hasher};
+    
+    let bytes = frame.data_bytes()?;
+    let row_len = frame.cols() as usize * frame.elem_size()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    
+    for row in (0..frame.rows() as usize).step_by(8) {
+        let start = row * row_len;
+        let Some(line) = bytes.get(start..start + row_len) else { break };
+        line.iter().step_by(7).for_each(|b| b.hash(&mut hasher));
+    }
+    
+    Ok(hasher.finish())
+}
+
+/// Below this mean brightness a frame is too dark to tell colour from grayscale:
+/// an IR sensor with its emitter off and an RGB sensor in the dark both look black.
+const DARK_FRAME_BRIGHTNESS: f64 = 15.0;
+
+/// Default for `camera.grayscale_threshold`: mean per-pixel channel
+/// difference below which a frame counts as grayscale
+pub const DEFAULT_GRAYSCALE_THRESHOLD: f64 = 10.0;
+
+/// Frames read per classification attempt; the median of the usable ones
+/// decides, so one odd frame can't flip the result
+const CLASSIFY_FRAMES: u32 = 9;
+
+/// Classify a camera from its frames rather than its name.
+///
+/// Frames whose median channel difference is under `grayscale_threshold`
+/// count as IR. Near-black frames are ambiguous, so they are left out. If no
+/// frame was bright enough to decide, the IR emitter is switched on briefly
+/// and the camera re-sampled, so pure IR sensors are judged on lit frames.
+pub fn verify_camera_type(device_id: i32, grayscale_threshold: f64) -> Result<CameraType> {
     let mut cap = VideoCapture::new(device_id, videoio::CAP_V4L2)?;
     
     if !cap.is_opened()? {
         return Ok(CameraType::Unknown);
     }
     
-    let mut color_scores = 0;
-    let mut gray_scores = 0;
+    let mut difference = median_channel_difference(&mut cap)?;
     
-    for _ in 0..5 {
-        let mut frame = Mat::default();
-        if cap.read(&mut frame).is_ok() && !frame.empty() {
-            if is_grayscale_frame(&frame)? {
-                gray_scores += 1;
-            } else {
-                color_scores += 1;
+    if difference.is_none() {
+        let device_path = format!("/dev/video{}", device_id);
+        info!("video{}: frames too dark to classify, retrying with IR emitter on", device_id);
+        
+        let mut emitter = crate::ir_emitter::IrEmitter::new(&device_path);
+        match emitter.enable() {
+            Ok(()) if emitter.is_running() => {
+                difference = median_channel_difference(&mut cap)?;
+                let _ = emitter.disable();
             }
+            Ok(()) => debug!("video{}: IR emitter not available for re-sampling", device_id),
+            Err(e) => warn!("video{}: could not enable IR emitter: {}", device_id, e),
         }
     }
     
     let _ = cap.release();
     
-    if gray_scores > color_scores {
-        Ok(CameraType::Infrared)
-    } else if color_scores > 0 {
-        Ok(CameraType::Rgb)
-    } else {
-        Ok(CameraType::Unknown)
+    match difference {
+        Some(d) if d < grayscale_threshold => {
+            info!("video{}: grayscale frames (channel difference {:.1}) — treating as IR-only camera", device_id, d);
+            Ok(CameraType::Infrared)
+        }
+        Some(d) => {
+            debug!("video{}: colour frames (channel difference {:.1})", device_id, d);
+            Ok(CameraType::Rgb)
+        }
+        None => {
+            warn!("video{}: frames stayed dark, camera type unknown", device_id);
+            Ok(CameraType::Unknown)
+        }
+    }
+}
+
+/// Read a few frames and return their `median_difference`
+fn median_channel_difference(cap: &mut VideoCapture) -> Result<Option<f64>> {
+    let mut frames = Vec::new();
+    
+    for _ in 0..CLASSIFY_FRAMES {
+        let mut frame = Mat::default();
+        if cap.read(&mut frame).unwrap_or(false) && !frame.empty() {
+            frames.push(frame);
+        }
     }
+    
+    median_difference(&frames)
 }
 
-fn is_grayscale_frame(frame: &Mat) -> Result<bool> {
+/// Median channel difference of `frames`, skipping frames too dark to
+/// judge. `None` if every frame was too dark.
+fn median_difference(frames: &[Mat]) -> Result<Option<f64>> {
+    let mut differences = Vec::new();
+    
+    for frame in frames {
+        if calculate_brightness(frame)? < DARK_FRAME_BRIGHTNESS {
+            continue;
+        }
+        
+        differences.push(channel_difference(frame)?);
+    }
+    
+    if differences.is_empty() {
+        return Ok(None);
+    }
+    differences.sort_by(|a, b| a.total_cmp(b));
+    Ok(Some(differences[differences.len() / 2]))
+}
+
+/// Mean per-pixel difference between the blue channel and the other two,
+/// whichever is larger. Near zero for an IR feed, even a slightly tinted one.
+fn channel_difference(frame: &Mat) -> Result<f64> {
     if frame.channels() != 3 {
-        return Ok(true);
+        return Ok(0.0);
     }
     
     let mut channels = Vector::<Mat>::new();
     opencv::core::split(frame, &mut channels)?;
     
-    if channels.len() < 3 {
-        return Ok(false);
-    }
-    
     let mut diff1 = Mat::default();
     let mut diff2 = Mat::default();
     
@@ -416,5 +950,50 @@ fn is_grayscale_frame(frame: &Mat) -> Result<bool> {
     let mean1 = opencv::core::mean(&diff1, &Mat::default())?[0];
     let mean2 = opencv::core::mean(&diff2, &Mat::default())?[0];
     
-    Ok(mean1 < 10.0 && mean2 < 10.0)
+    Ok(mean1.max(mean2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Scalar, CV_8UC3};
+    
+    /// Flat BGR frame
+    fn frame(b: f64, g: f64, r: f64) -> Mat {
+        Mat::new_rows_cols_with_default(48, 64, CV_8UC3, Scalar::new(b, g, r, 0.0)).unwrap()
+    }
+    
+    fn is_grayscale(frames: &[Mat]) -> Option<bool> {
+        median_difference(frames).unwrap().map(|d| d < DEFAULT_GRAYSCALE_THRESHOLD)
+    }
+    
+    #[test]
+    fn tinted_gray_just_under_the_threshold_is_grayscale() {
+        assert_eq!(median_difference(&[frame(109.0, 100.0, 100.0)]).unwrap(), Some(9.0));
+        assert_eq!(is_grayscale(&[frame(109.0, 100.0, 100.0)]), Some(true));
+    }
+    
+    #[test]
+    fn tint_at_the_threshold_is_colour() {
+        assert_eq!(is_grayscale(&[frame(110.0, 100.0, 100.0)]), Some(false));
+        assert_eq!(is_grayscale(&[frame(90.0, 100.0, 100.0)]), Some(false));
+    }
+    
+    #[test]
+    fn clearly_colour_frame_is_colour() {
+        assert_eq!(is_grayscale(&[frame(200.0, 60.0, 40.0)]), Some(false));
+    }
+    
+    #[test]
+    fn median_outvotes_odd_frames() {
+        let mut frames: Vec<Mat> = (0..5).map(|_| frame(105.0, 100.0, 100.0)).collect();
+        frames.extend((0..4).map(|_| frame(200.0, 60.0, 40.0)));
+        assert_eq!(is_grayscale(&frames), Some(true));
+    }
+    
+    #[test]
+    fn dark_frames_are_left_out() {
+        assert_eq!(is_grayscale(&[frame(0.0, 0.0, 0.0), frame(8.0, 0.0, 0.0)]), None);
+        assert_eq!(is_grayscale(&[frame(0.0, 0.0, 0.0), frame(200.0, 60.0, 40.0)]), Some(false));
+    }
 }
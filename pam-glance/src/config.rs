@@ -1,14 +1,31 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 use anyhow::Result;
+use log::warn;
+
+use crate::camera::DetectionRoi;
+use crate::face::{DistanceMetric, MultiMatchPolicy, valid_model_set};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlanceConfig {
+    #[serde(default)]
     pub camera: CameraConfig,
+    #[serde(default)]
     pub recognition: RecognitionConfig,
+    #[serde(default)]
     pub ir_emitter: IrEmitterConfig,
     #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    /// PAM username → name the face was enrolled under, for accounts PAM
+    /// sees differently (`user@REALM`, other case). System config only
+    #[serde(default)]
+    pub user_aliases: BTreeMap<String, String>,
+    #[serde(default)]
     pub version: u32,
 }
 
@@ -22,10 +39,53 @@ pub struct CameraConfig {
     pub rgb_device: String,
     #[serde(default = "default_min_brightness")]
     pub min_brightness: f64,
+    /// Frames with a higher mean brightness (0 – 255) are skipped as
+    /// washed out, e.g. backlit by a window. 0 = never
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: f64,
     #[serde(default = "default_frame_width")]
     pub frame_width: u32,
     #[serde(default = "default_frame_height")]
     pub frame_height: u32,
+    /// Give up early on uniformly black frames (closed privacy shutter)
+    #[serde(default = "default_true")]
+    pub detect_obstruction: bool,
+    /// Frames with a smaller mean channel difference are treated as IR when
+    /// classifying a camera by its frames. Raise it for tinted IR feeds
+    #[serde(default = "default_grayscale_threshold")]
+    pub grayscale_threshold: f64,
+    /// Seconds camera detection results are reused from /run/glance across
+    /// auth attempts. 0 = probe every time
+    #[serde(default = "default_probe_cache_ttl")]
+    pub probe_cache_ttl: u64,
+    /// Auth attempts in a row where no camera opens before face auth is
+    /// skipped for `safe_mode_cooldown` seconds. 0 = keep trying
+    #[serde(default = "default_safe_mode_threshold")]
+    pub safe_mode_threshold: u32,
+    #[serde(default = "default_safe_mode_cooldown")]
+    pub safe_mode_cooldown: u64,
+    /// Seconds after a failed attempt during which the module skips face
+    /// auth for the same user, so a greeter that re-runs PAM straight away
+    /// doesn't flash the camera during password entry. 0 = off
+    #[serde(default)]
+    pub failure_cooldown: u64,
+    /// Auth attempts in a row that find no camera or no models before the
+    /// module disables itself until `glance enable`. 0 = never
+    #[serde(default)]
+    pub auto_disable_threshold: u32,
+    /// Fractions of the frame faces are looked for in; the rest is masked
+    /// out before detection (e.g. a monitor reflecting into the lens)
+    #[serde(default)]
+    pub detection_roi: DetectionRoi,
+    /// Frames whose longer side is above this many pixels are scaled down
+    /// before detection. 0 = never
+    #[serde(default = "default_max_frame_dimension")]
+    pub max_frame_dimension: u32,
+    /// Ignore faces over parts of the scene that match the background
+    /// captured with `glance enroll --background` (posters, photos on the
+    /// wall). No effect until one is captured
+    #[serde(default)]
+    pub background_subtraction: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,8 +96,89 @@ pub struct RecognitionConfig {
     pub rgb_tolerance: f64,
     #[serde(default = "default_auth_timeout")]
     pub auth_timeout: f64,
+    /// Seconds `auth_timeout` may stretch to while a face in view is getting
+    /// closer to a match. Values at or below `auth_timeout` (the default, 0)
+    /// disable this
+    #[serde(default = "default_max_extended_timeout")]
+    pub max_extended_timeout: f64,
     #[serde(default = "default_max_auth_frames")]
     pub max_auth_frames: u32,
+    /// Extra passes over the cameras, after power-cycling the IR emitter,
+    /// when a pass saw no face at all with the emitter on. Only run while
+    /// `auth_timeout` and `max_auth_frames` have some budget left
+    #[serde(default = "default_internal_retries")]
+    pub internal_retries: u32,
+    /// Encode every face in view in one batch and take the best match (kiosk mode)
+    #[serde(default)]
+    pub multi_face: bool,
+    /// Use the CNN detector in builds with the `cuda` feature, on the GPU
+    /// when the system dlib has CUDA
+    #[serde(default = "default_true")]
+    pub use_gpu: bool,
+    /// Consecutive matching frames needed before authenticating
+    #[serde(default = "default_required_matches")]
+    pub required_matches: u32,
+    /// Frames in a row where a face is seen but cannot be encoded that are
+    /// tolerated without breaking the match streak
+    #[serde(default = "default_encoding_grace")]
+    pub encoding_grace: u32,
+    /// Retry detection on rotated frames to catch tilted heads (slower)
+    #[serde(default)]
+    pub rotation_search: bool,
+    /// Encoding comparison; tolerances stay in Euclidean units either way
+    #[serde(default)]
+    pub metric: DistanceMetric,
+    /// What happens when a face is within tolerance of several enrolled
+    /// users: "highest_confidence", "first_match" or "reject"
+    #[serde(default)]
+    pub multi_match_policy: MultiMatchPolicy,
+    /// Also match templates enrolled while wearing a mask (less secure)
+    #[serde(default)]
+    pub mask_friendly: bool,
+    /// Use the models in this directory instead of the default ones. Empty =
+    /// default. Templates enrolled with other models stop matching
+    #[serde(default)]
+    pub models_dir: String,
+    /// Subdirectory of the models directory holding the set of models to
+    /// use, e.g. `dlib-v1`, so several versions can be installed side by
+    /// side. Empty = the models directory itself
+    #[serde(default)]
+    pub model_set: String,
+    /// Tolerance taken off per enrolled template beyond the first, so users
+    /// with many poses don't get more lookalike matches. 0 = off
+    #[serde(default)]
+    pub template_tolerance_penalty: f64,
+    /// Milliseconds the camera stays on after a match, with the success hook
+    /// already fired, so a greeter can show "recognized" before the LED goes
+    /// out. Capped at 1000. 0 = release straight away
+    #[serde(default)]
+    pub success_hold_ms: u64,
+    /// Preset the GUI last wrote `use_gpu`, `num_jitters` and
+    /// `detection_scale` from ("fast", "accurate" or "custom"); informational
+    #[serde(default)]
+    pub profile: String,
+    /// Encoding passes per face with small distortions, averaged. 0 or 1 = one
+    #[serde(default)]
+    pub num_jitters: u32,
+    /// Factor frames are scaled by before detection; below 1.0 is faster
+    /// but misses distant faces
+    #[serde(default = "default_detection_scale")]
+    pub detection_scale: f64,
+    /// Never compare a face against templates enrolled on the other kind of
+    /// camera (IR vs RGB); skip cameras the user has no templates for
+    #[serde(default)]
+    pub strict_camera_match: bool,
+    /// Only succeed once the matched face has stayed in view, matching, for
+    /// `continuous_presence_ms` (for sudo-style re-auth)
+    #[serde(default)]
+    pub continuous_presence: bool,
+    #[serde(default = "default_continuous_presence_ms")]
+    pub continuous_presence_ms: u64,
+    /// Frames the face must hold roughly still before it is encoded and
+    /// matched, so someone still moving into view isn't matched blurred.
+    /// 0 = match from the first detection
+    #[serde(default)]
+    pub stabilization_frames: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,19 +191,53 @@ pub struct IrEmitterConfig {
     pub config_path: String,
     #[serde(default = "default_ir_device")]
     pub device: String,
+    /// Profile in `/etc/linux-enable-ir-emitter` to use per device, by
+    /// file name, e.g. `{"/dev/video2": "video2.ini"}`. Devices not listed
+    /// leave the choice to the tool
+    #[serde(default)]
+    pub profiles: BTreeMap<String, String>,
+}
+
+/// Commands run after an auth attempt, e.g. to play a sound.
+/// Only honoured from the system config, see `hooks.rs`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub on_success_cmd: String,
+    #[serde(default)]
+    pub on_failure_cmd: String,
+}
+
+/// Local counters of auth outcomes, see `stats.rs`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatsConfig {
+    #[serde(default)]
+    pub enabled: bool,
 }
 
 fn default_prefer_ir() -> bool { true }
+fn default_detection_scale() -> f64 { 1.0 }
+fn default_continuous_presence_ms() -> u64 { 1500 }
 fn default_ir_device() -> String { "/dev/video2".to_string() }
 fn default_rgb_device() -> String { "/dev/video0".to_string() }
 fn default_min_brightness() -> f64 { 70.0 }
+fn default_max_brightness() -> f64 { crate::camera::DEFAULT_MAX_BRIGHTNESS }
+fn default_grayscale_threshold() -> f64 { crate::camera::DEFAULT_GRAYSCALE_THRESHOLD }
 fn default_frame_width() -> u32 { 640 }
 fn default_frame_height() -> u32 { 480 }
+fn default_probe_cache_ttl() -> u64 { crate::camera::DEFAULT_PROBE_CACHE_TTL.as_secs() }
+fn default_safe_mode_threshold() -> u32 { 3 }
+fn default_max_frame_dimension() -> u32 { crate::camera::DEFAULT_MAX_FRAME_DIMENSION }
+fn default_safe_mode_cooldown() -> u64 { 300 }
 fn default_ir_tolerance() -> f64 { 0.45 }
 fn default_rgb_tolerance() -> f64 { 0.50 }
 fn default_auth_timeout() -> f64 { 5.0 }
+fn default_max_extended_timeout() -> f64 { 0.0 }
 fn default_max_auth_frames() -> u32 { 30 }
+fn default_internal_retries() -> u32 { 1 }
 fn default_true() -> bool { true }
+fn default_required_matches() -> u32 { 1 }
+fn default_encoding_grace() -> u32 { 2 }
 fn default_ir_config_path() -> String {
     dirs::home_dir()
         .map(|p| p.join(".config/linux-enable-ir-emitter.toml").to_string_lossy().to_string())
@@ -75,6 +250,9 @@ impl Default for GlanceConfig {
             camera: CameraConfig::default(),
             recognition: RecognitionConfig::default(),
             ir_emitter: IrEmitterConfig::default(),
+            hooks: HooksConfig::default(),
+            stats: StatsConfig::default(),
+            user_aliases: BTreeMap::new(),
             version: 1,
         }
     }
@@ -87,8 +265,19 @@ impl Default for CameraConfig {
             ir_device: default_ir_device(),
             rgb_device: default_rgb_device(),
             min_brightness: default_min_brightness(),
+            max_brightness: default_max_brightness(),
             frame_width: default_frame_width(),
             frame_height: default_frame_height(),
+            detect_obstruction: default_true(),
+            grayscale_threshold: default_grayscale_threshold(),
+            probe_cache_ttl: default_probe_cache_ttl(),
+            safe_mode_threshold: default_safe_mode_threshold(),
+            safe_mode_cooldown: default_safe_mode_cooldown(),
+            failure_cooldown: 0,
+            auto_disable_threshold: 0,
+            detection_roi: DetectionRoi::default(),
+            max_frame_dimension: default_max_frame_dimension(),
+            background_subtraction: false,
         }
     }
 }
@@ -99,7 +288,28 @@ impl Default for RecognitionConfig {
             ir_tolerance: default_ir_tolerance(),
             rgb_tolerance: default_rgb_tolerance(),
             auth_timeout: default_auth_timeout(),
+            max_extended_timeout: default_max_extended_timeout(),
             max_auth_frames: default_max_auth_frames(),
+            internal_retries: default_internal_retries(),
+            multi_face: false,
+            use_gpu: default_true(),
+            required_matches: default_required_matches(),
+            encoding_grace: default_encoding_grace(),
+            rotation_search: false,
+            metric: DistanceMetric::default(),
+            multi_match_policy: MultiMatchPolicy::default(),
+            mask_friendly: false,
+            models_dir: String::new(),
+            model_set: String::new(),
+            template_tolerance_penalty: 0.0,
+            success_hold_ms: 0,
+            profile: String::new(),
+            num_jitters: 0,
+            detection_scale: default_detection_scale(),
+            strict_camera_match: false,
+            continuous_presence: false,
+            continuous_presence_ms: default_continuous_presence_ms(),
+            stabilization_frames: 0,
         }
     }
 }
@@ -111,11 +321,82 @@ impl Default for IrEmitterConfig {
             binary_path: String::new(),
             config_path: default_ir_config_path(),
             device: default_ir_device(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+/// Keys a user config may not override: they run commands, pick the models,
+/// map accounts or switch the module off for everyone, and the module often
+/// runs as root
+const SYSTEM_ONLY_KEYS: [&[&str]; 5] = [
+    &["hooks"],
+    &["user_aliases"],
+    &["recognition", "models_dir"],
+    &["recognition", "model_set"],
+    &["camera", "auto_disable_threshold"],
+];
+
+/// Drop every `SYSTEM_ONLY_KEYS` entry from a user config layer, so tools
+/// reading the same files see what the module does
+pub fn remove_system_only_keys(overlay: &mut Value) {
+    for key_path in SYSTEM_ONLY_KEYS {
+        let (last, parents) = key_path.split_last().expect("non-empty key path");
+        let parent = parents.iter().try_fold(&mut *overlay, |v, k| v.get_mut(*k));
+        if let Some(Value::Object(parent)) = parent {
+            parent.remove(*last);
+        }
+    }
+}
+
+/// Merge `overlay` into `base`: objects key by key, recursively; any other
+/// value in `overlay` replaces the one in `base`
+pub fn merge_json(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A config file as JSON; `None` if it doesn't exist or can't be used
+fn read_layer(path: &Path) -> Option<Value> {
+    if !path.exists() {
+        return None;
+    }
+    match fs::read_to_string(path).map_err(anyhow::Error::from)
+        .and_then(|content| Ok(serde_json::from_str::<Value>(&content)?)) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring config {:?}: {}", path, e);
+            None
         }
     }
 }
 
 impl GlanceConfig {
+    /// `system` as the base with `user` merged on top key by key, so an
+    /// admin's settings hold wherever the user file is silent. Keys in
+    /// `SYSTEM_ONLY_KEYS` come from `system` alone
+    pub fn load_layered(system: &Path, user: Option<&Path>) -> Result<Self> {
+        let mut merged = read_layer(system).unwrap_or_else(|| Value::Object(Default::default()));
+        
+        if let Some(mut overlay) = user.and_then(read_layer) {
+            remove_system_only_keys(&mut overlay);
+            merge_json(&mut merged, overlay);
+        }
+        
+        Ok(serde_json::from_value(merged)?)
+    }
+    
     pub fn load(path: &str) -> Result<Self> {
         if Path::new(path).exists() {
             let content = fs::read_to_string(path)?;
@@ -126,6 +407,55 @@ impl GlanceConfig {
         }
     }
     
+    /// Values auth would misbehave on, each as a message naming the key.
+    /// Types were already checked by deserializing
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, key: &str, value: String, expected: &str| {
+            if !ok {
+                problems.push(format!("{} = {}: expected {}", key, value, expected));
+            }
+        };
+        
+        let r = &self.recognition;
+        check(r.ir_tolerance > 0.0 && r.ir_tolerance <= 1.0, "recognition.ir_tolerance",
+              r.ir_tolerance.to_string(), "a distance above 0 and at most 1.0");
+        check(r.rgb_tolerance > 0.0 && r.rgb_tolerance <= 1.0, "recognition.rgb_tolerance",
+              r.rgb_tolerance.to_string(), "a distance above 0 and at most 1.0");
+        check(r.auth_timeout > 0.0 && r.auth_timeout <= 60.0, "recognition.auth_timeout",
+              r.auth_timeout.to_string(), "seconds above 0 and at most 60");
+        check(r.max_extended_timeout >= 0.0 && r.max_extended_timeout <= 60.0, "recognition.max_extended_timeout",
+              r.max_extended_timeout.to_string(), "seconds from 0 to 60");
+        check(r.max_auth_frames >= 1, "recognition.max_auth_frames",
+              r.max_auth_frames.to_string(), "at least 1");
+        check(r.internal_retries <= 5, "recognition.internal_retries",
+              r.internal_retries.to_string(), "at most 5");
+        check(r.required_matches >= 1, "recognition.required_matches",
+              r.required_matches.to_string(), "at least 1");
+        check(r.detection_scale > 0.0 && r.detection_scale <= 1.0, "recognition.detection_scale",
+              r.detection_scale.to_string(), "a factor above 0 and at most 1.0");
+        check(r.template_tolerance_penalty >= 0.0, "recognition.template_tolerance_penalty",
+              r.template_tolerance_penalty.to_string(), "0 or more");
+        check(r.num_jitters <= 100, "recognition.num_jitters",
+              r.num_jitters.to_string(), "at most 100");
+        check(valid_model_set(&r.model_set), "recognition.model_set",
+              format!("{:?}", r.model_set), "a plain directory name such as \"dlib-v1\"");
+        
+        let c = &self.camera;
+        check(c.frame_width > 0 && c.frame_height > 0, "camera.frame_width/frame_height",
+              format!("{}x{}", c.frame_width, c.frame_height), "a non-zero size");
+        check((0.0..=255.0).contains(&c.min_brightness), "camera.min_brightness",
+              c.min_brightness.to_string(), "a level from 0 to 255");
+        check((0.0..=255.0).contains(&c.max_brightness), "camera.max_brightness",
+              c.max_brightness.to_string(), "a level from 0 to 255 (0 = off)");
+        let roi = c.detection_roi;
+        let fractions = [roi.x, roi.y, roi.width, roi.height];
+        check(fractions.iter().all(|f| (0.0..=1.0).contains(f)) && roi.width > 0.0 && roi.height > 0.0,
+              "camera.detection_roi", format!("{:?}", roi), "fractions of the frame from 0 to 1, with a non-zero size");
+        
+        problems
+    }
+    
     pub fn get_tolerance(&self, is_ir: bool) -> f64 {
         if is_ir {
             self.recognition.ir_tolerance
@@ -299,14 +629,18 @@ impl FaceData {
             }
         }
         
-        let json_path = format!("{}/{}.json", data_dir, username);
-        if Path::new(&json_path).exists() {
-            let content = fs::read_to_string(&json_path)?;
-            let data = FaceData::from_json(&content)?;
-            return Ok(Some(data));
+        let stem = crate::userfile::file_stem(username);
+        let stems = std::iter::once(stem.as_str()).chain(crate::userfile::legacy_stem(username));
+        for stem in stems {
+            let json_path = format!("{}/{}.json", data_dir, stem);
+            if Path::new(&json_path).exists() {
+                let content = fs::read_to_string(&json_path)?;
+                let data = FaceData::from_json(&content)?;
+                return Ok(Some(data));
+            }
         }
         
-        let pkl_path = format!("{}/{}.pkl", data_dir, username);
+        let pkl_path = format!("{}/{}.pkl", data_dir, stem);
         if Path::new(&pkl_path).exists() {
             log::warn!("Legacy .pkl file found for {}. Please run the GUI to migrate.", username);
             return Ok(None);
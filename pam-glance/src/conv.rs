@@ -0,0 +1,77 @@
+//! Informational messages through the PAM conversation
+//!
+//! The `pam` crate only wraps what authentication needs, so the conversation
+//! function is fetched with `pam_get_item(PAM_CONV)` directly. Messages are
+//! sent as `PAM_TEXT_INFO`, which greeters and terminals show without
+//! expecting a reply.
+
+use log::debug;
+use pam::PamHandle;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_CONV: c_int = 5;
+const PAM_TEXT_INFO: c_int = 4;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<extern "C" fn(
+        num_msg: c_int,
+        msg: *mut *const PamMessage,
+        resp: *mut *mut PamResponse,
+        appdata_ptr: *mut c_void,
+    ) -> c_int>,
+    appdata_ptr: *mut c_void,
+}
+
+extern "C" {
+    fn pam_get_item(pamh: *const c_void, item_type: c_int, item: *mut *const c_void) -> c_int;
+}
+
+/// Show `text` to the user as an informational message. Failures are only
+/// logged: a greeter without a conversation must not break auth.
+pub fn send_info(handle: &PamHandle, text: &str) {
+    let Ok(text) = CString::new(text) else { return };
+    
+    unsafe {
+        let mut item: *const c_void = std::ptr::null();
+        let pamh = handle as *const PamHandle as *const c_void;
+        if pam_get_item(pamh, PAM_CONV, &mut item) != PAM_SUCCESS || item.is_null() {
+            debug!("No PAM conversation available for messages");
+            return;
+        }
+        
+        let conv = &*(item as *const PamConv);
+        let Some(conv_fn) = conv.conv else { return };
+        
+        let message = PamMessage { msg_style: PAM_TEXT_INFO, msg: text.as_ptr() };
+        let mut message_ptr: *const PamMessage = &message;
+        let mut response: *mut PamResponse = std::ptr::null_mut();
+        
+        let ret = conv_fn(1, &mut message_ptr, &mut response, conv.appdata_ptr);
+        if ret != PAM_SUCCESS {
+            debug!("PAM conversation rejected info message ({})", ret);
+        }
+        
+        // The application allocates the reply, the module frees it
+        if !response.is_null() {
+            if !(*response).resp.is_null() {
+                libc::free((*response).resp as *mut c_void);
+            }
+            libc::free(response as *mut c_void);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Failure cooldown: don't reopen the camera right after a failed attempt
+//!
+//! Some greeters re-run the whole PAM stack as soon as face auth falls
+//! through, so the camera flashes again just as the user starts typing
+//! their password. After a failed attempt the module skips face auth for
+//! the same user until `failure_cooldown` has passed. This is a courtesy,
+//! not a lockout: any other user, or the same one later, gets the camera.
+//! Only a hash of the username is kept, in /run/glance, so it is per boot.
+
+use crate::state_file::{self, now_ms};
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::Duration;
+
+const STATE_FILE: &str = "/run/glance/last_failure.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Hash of the user the last failed attempt was for
+    user: u64,
+    /// Milliseconds since the Unix epoch the attempt ended at
+    at_ms: u64,
+}
+
+fn user_hash(username: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    username.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Time left before `username` gets the camera again, `None` if they can
+/// have it now
+pub fn remaining(username: &str, cooldown: Duration) -> Option<Duration> {
+    if cooldown.is_zero() {
+        return None;
+    }
+    
+    let state: State = state_file::load(STATE_FILE)?;
+    if state.user != user_hash(username) {
+        return None;
+    }
+    let elapsed = Duration::from_millis(now_ms().saturating_sub(state.at_ms));
+    cooldown.checked_sub(elapsed).filter(|left| !left.is_zero())
+}
+
+/// An attempt for `username` had the camera on and ended without a match:
+/// no match, no face, timeout or a covered lens
+pub fn record_failure(username: &str, cooldown: Duration) {
+    if cooldown.is_zero() {
+        return;
+    }
+    state_file::save(STATE_FILE, &State { user: user_hash(username), at_ms: now_ms() }, "last failure");
+}
+
+/// An attempt succeeded: nothing to hold back any more
+pub fn clear() {
+    if Path::new(STATE_FILE).exists() {
+        let _ = fs::remove_file(STATE_FILE);
+    }
+}
@@ -0,0 +1,309 @@
+//! Face detection and encoding backends
+//!
+//! `FaceRecognizer` owns the matching rules (tolerances, metric, rotation
+//! search, ROI, detection scale) and leaves finding and encoding faces to a
+//! `FaceEmbedder`, as does the GUI's `FaceProcessor` for enrollment.
+//! `DlibEmbedder` is the real one, built on dlib's models.
+//! `CannedEmbedder` returns fixed faces for every frame, so matching can be
+//! exercised without models or a camera, and another model (e.g. ONNX) only
+//! has to implement the trait.
+
+use dlib_face_recognition::{
+    FaceDetector, FaceDetectorCnn, FaceDetectorTrait, FaceLocations,
+    LandmarkPredictor, LandmarkPredictorTrait,
+    FaceEncoderNetwork, FaceEncoderTrait,
+    ImageMatrix, Rectangle,
+};
+use opencv::prelude::*;
+use opencv::core::Mat;
+use anyhow::Result;
+use log::{debug, warn};
+use std::path::Path;
+
+use crate::wipe::wipe_mat;
+
+pub use crate::face::ComputeDevice;
+
+/// A face's `(left, top, right, bottom)` in frame pixels
+pub type FaceRect = (i64, i64, i64, i64);
+
+pub trait FaceEmbedder {
+    /// Where faces are in `frame`
+    fn detect(&self, frame: &Mat) -> Result<Vec<FaceRect>>;
+    
+    /// Encoding of the face at `rect` in `frame`, averaged over
+    /// `num_jitters` distorted copies where the backend supports it.
+    /// `None` if no usable encoding came out
+    fn encode(&self, frame: &Mat, rect: FaceRect, num_jitters: u32) -> Result<Option<Vec<f32>>>;
+    
+    /// Encodings of several faces in one frame, in order. Backends that can
+    /// batch override this
+    fn encode_all(&self, frame: &Mat, rects: &[FaceRect], num_jitters: u32) -> Result<Vec<Option<Vec<f32>>>> {
+        rects.iter().map(|&rect| self.encode(frame, rect, num_jitters)).collect()
+    }
+    
+    /// Landmark points of the face at `rect`, for drawing. Empty where the
+    /// backend has none
+    fn landmarks(&self, _frame: &Mat, _rect: FaceRect) -> Result<Vec<(i64, i64)>> {
+        Ok(Vec::new())
+    }
+    
+    /// Whether `encode` can return encodings at all, rather than always `None`
+    fn can_encode(&self) -> bool {
+        true
+    }
+}
+
+enum Detector {
+    Hog(FaceDetector),
+    Cnn(FaceDetectorCnn),
+}
+
+impl Detector {
+    /// dlib's CNN detector for `Cuda`, falling back to HOG on the CPU when
+    /// it can't be loaded
+    fn load(models_dir: &Path, device: ComputeDevice) -> (Self, ComputeDevice) {
+        let cnn_detector_path = models_dir.join("mmod_human_face_detector.dat");
+        match device {
+            ComputeDevice::Cuda if cnn_detector_path.exists() => {
+                match FaceDetectorCnn::open(&cnn_detector_path) {
+                    Ok(d) => (Detector::Cnn(d), ComputeDevice::Cuda),
+                    Err(e) => {
+                        warn!("CNN detector failed to load ({}), using CPU HOG detector", e);
+                        (Detector::Hog(FaceDetector::new()), ComputeDevice::Cpu)
+                    }
+                }
+            }
+            ComputeDevice::Cuda => {
+                warn!("CNN detector model not found at {:?}, using CPU HOG detector", cnn_detector_path);
+                (Detector::Hog(FaceDetector::new()), ComputeDevice::Cpu)
+            }
+            ComputeDevice::Cpu => (Detector::Hog(FaceDetector::new()), ComputeDevice::Cpu),
+        }
+    }
+    
+    fn face_locations(&self, image: &ImageMatrix) -> FaceLocations {
+        match self {
+            Detector::Hog(d) => d.face_locations(image),
+            Detector::Cnn(d) => d.face_locations(image),
+        }
+    }
+}
+
+/// dlib's HOG or CNN detector, 68-point landmarks and ResNet encoder
+pub struct DlibEmbedder {
+    detector: Detector,
+    /// Landmark predictor and encoder; `None` when only detecting
+    models: Option<(LandmarkPredictor, FaceEncoderNetwork)>,
+    device: ComputeDevice,
+}
+
+impl DlibEmbedder {
+    /// Load the models from `models_dir`. `Cuda` uses the CNN detector and
+    /// falls back to HOG on the CPU when it can't be loaded
+    pub fn open(models_dir: &Path, device: ComputeDevice) -> Result<Self> {
+        let shape_predictor_path = models_dir.join("shape_predictor_68_face_landmarks.dat");
+        let face_rec_path = models_dir.join("dlib_face_recognition_resnet_model_v1.dat");
+        
+        let (detector, device) = Detector::load(models_dir, device);
+        debug!("Face detection: {}", device.label());
+        
+        let predictor = if shape_predictor_path.exists() {
+            LandmarkPredictor::open(shape_predictor_path).map_err(|e| anyhow::anyhow!(e))?
+        } else {
+            warn!("Shape predictor model not found at {:?}", shape_predictor_path);
+            anyhow::bail!("Shape predictor model not found");
+        };
+        
+        let encoder = if face_rec_path.exists() {
+            FaceEncoderNetwork::open(face_rec_path).map_err(|e| anyhow::anyhow!(e))?
+        } else {
+            warn!("Face recognition model not found at {:?}", face_rec_path);
+            anyhow::bail!("Face recognition model not found");
+        };
+        
+        Ok(Self { detector, models: Some((predictor, encoder)), device })
+    }
+    
+    /// Only the detector, e.g. for the GUI to show where a face is before
+    /// the other models are downloaded. Encodes nothing
+    pub fn detector_only(models_dir: &Path, device: ComputeDevice) -> Self {
+        let (detector, device) = Detector::load(models_dir, device);
+        debug!("Face detection: {} (no encoder)", device.label());
+        Self { detector, models: None, device }
+    }
+    
+    /// The device detection actually runs on after any fallback
+    pub fn device(&self) -> ComputeDevice {
+        self.device
+    }
+}
+
+impl FaceEmbedder for DlibEmbedder {
+    fn detect(&self, frame: &Mat) -> Result<Vec<FaceRect>> {
+        let image = opencv_to_dlib(frame)?;
+        Ok(self.detector.face_locations(&image).iter()
+            .map(|r| (r.left, r.top, r.right, r.bottom))
+            .collect())
+    }
+    
+    fn encode(&self, frame: &Mat, rect: FaceRect, num_jitters: u32) -> Result<Option<Vec<f32>>> {
+        Ok(self.encode_all(frame, &[rect], num_jitters)?.pop().flatten())
+    }
+    
+    /// One conversion and a single encoder call for every face
+    fn encode_all(&self, frame: &Mat, rects: &[FaceRect], num_jitters: u32) -> Result<Vec<Option<Vec<f32>>>> {
+        let Some((predictor, encoder)) = &self.models else {
+            return Ok(vec![None; rects.len()]);
+        };
+        if rects.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let image = opencv_to_dlib(frame)?;
+        let landmarks: Vec<_> = rects.iter()
+            .map(|&(left, top, right, bottom)| {
+                predictor.face_landmarks(&image, &Rectangle { left, top, right, bottom })
+            })
+            .collect();
+        
+        let encodings = encoder.get_face_encodings(&image, &landmarks, num_jitters);
+        let mut encoded: Vec<Option<Vec<f32>>> = encodings.iter()
+            .map(|encoding| Some(encoding.as_ref().iter().map(|&x| x as f32).collect()))
+            .collect();
+        encoded.resize(rects.len(), None);
+        Ok(encoded)
+    }
+    
+    fn landmarks(&self, frame: &Mat, rect: FaceRect) -> Result<Vec<(i64, i64)>> {
+        let Some((predictor, _)) = &self.models else {
+            return Ok(Vec::new());
+        };
+        let image = opencv_to_dlib(frame)?;
+        let (left, top, right, bottom) = rect;
+        let points = predictor.face_landmarks(&image, &Rectangle { left, top, right, bottom });
+        Ok(points.iter().map(|p| (p.x(), p.y())).collect())
+    }
+    
+    fn can_encode(&self) -> bool {
+        self.models.is_some()
+    }
+}
+
+/// Fixed faces for every frame, whatever is in it: a stand-in for the models
+/// when testing the matching logic. A rect is encoded as the canned face
+/// whose centre is nearest, so scaled or rotated rects still find theirs
+#[derive(Debug, Clone, Default)]
+pub struct CannedEmbedder {
+    faces: Vec<(FaceRect, Vec<f32>)>,
+}
+
+impl CannedEmbedder {
+    pub fn new(faces: Vec<(FaceRect, Vec<f32>)>) -> Self {
+        Self { faces }
+    }
+}
+
+impl FaceEmbedder for CannedEmbedder {
+    fn detect(&self, _frame: &Mat) -> Result<Vec<FaceRect>> {
+        Ok(self.faces.iter().map(|(rect, _)| *rect).collect())
+    }
+    
+    fn encode(&self, _frame: &Mat, rect: FaceRect, _num_jitters: u32) -> Result<Option<Vec<f32>>> {
+        let centre_offset = |(l, t, r, b): FaceRect| {
+            (l + r - rect.0 - rect.2).abs() + (t + b - rect.1 - rect.3).abs()
+        };
+        Ok(self.faces.iter()
+            .min_by_key(|(canned, _)| centre_offset(*canned))
+            .map(|(_, encoding)| encoding.clone()))
+    }
+}
+
+/// Encoding of the largest face `embedder` finds in `rgb`, `None` if there
+/// is no face or no usable encoding. For tools that need one encoding from
+/// an image without the matching rules around it.
+///
+/// `rgb` is packed RGB24: `width * height * 3` bytes, rows top to bottom
+/// with no padding, each pixel as R, G, B.
+pub fn encode_rgb(embedder: &dyn FaceEmbedder, rgb: &[u8], width: u32, height: u32) -> Result<Option<Vec<f32>>> {
+    use opencv::core::Vec3b;
+    use opencv::imgproc;
+    
+    anyhow::ensure!(rgb.len() == width as usize * height as usize * 3,
+                    "RGB frame is {} bytes, expected {}x{}x3", rgb.len(), width, height);
+    let image = Mat::new_rows_cols_with_bytes::<Vec3b>(height as i32, width as i32, rgb)?;
+    let mut frame = Mat::default();
+    imgproc::cvt_color(&*image, &mut frame, imgproc::COLOR_RGB2BGR, 0)?;
+    
+    let encoding = embedder.detect(&frame).and_then(|rects| {
+        match rects.into_iter().max_by_key(|(l, t, r, b)| (r - l) * (b - t)) {
+            Some(rect) => embedder.encode(&frame, rect, 0),
+            None => Ok(None),
+        }
+    });
+    wipe_mat(&mut frame);
+    encoding
+}
+
+/// dlib image of `mat`, which must be 8-bit grey or BGR (see
+/// `camera::normalize_frame`). dlib reads exactly rows × cols × 3 bytes
+fn opencv_to_dlib(mat: &Mat) -> Result<ImageMatrix> {
+    use opencv::core::CV_8U;
+    use opencv::imgproc;
+    
+    let code = match (mat.depth(), mat.channels()) {
+        (CV_8U, 3) => imgproc::COLOR_BGR2RGB,
+        (CV_8U, 1) => imgproc::COLOR_GRAY2RGB,
+        (depth, channels) => anyhow::bail!(
+            "Frame must be 8-bit grey or BGR for dlib, got depth {} with {} channel(s)", depth, channels),
+    };
+    let mut rgb = Mat::default();
+    imgproc::cvt_color(mat, &mut rgb, code, 0)?;
+    
+    let rows = rgb.rows() as usize;
+    let cols = rgb.cols() as usize;
+    
+    let data = rgb.data_bytes()?;
+    if data.len() != rows * cols * 3 {
+        wipe_mat(&mut rgb);
+        anyhow::bail!("Converted frame is {} bytes, expected {}", data.len(), rows * cols * 3);
+    }
+    
+    let image = unsafe { ImageMatrix::new(cols, rows, data.as_ptr()) };
+    
+    // dlib has its own copy now; scrub the converted pixels
+    wipe_mat(&mut rgb);
+    
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn grey_rgb(width: u32, height: u32) -> Vec<u8> {
+        vec![128; (width * height * 3) as usize]
+    }
+    
+    #[test]
+    fn encode_rgb_picks_the_largest_face() {
+        let embedder = CannedEmbedder::new(vec![
+            ((10, 10, 50, 50), vec![1.0; 128]),
+            ((200, 100, 400, 300), vec![2.0; 128]),
+        ]);
+        let encoding = encode_rgb(&embedder, &grey_rgb(640, 480), 640, 480).unwrap();
+        assert_eq!(encoding, Some(vec![2.0; 128]));
+    }
+    
+    #[test]
+    fn encode_rgb_without_a_face_is_none() {
+        let embedder = CannedEmbedder::default();
+        assert_eq!(encode_rgb(&embedder, &grey_rgb(640, 480), 640, 480).unwrap(), None);
+    }
+    
+    #[test]
+    fn encode_rgb_rejects_a_short_buffer() {
+        let embedder = CannedEmbedder::default();
+        assert!(encode_rgb(&embedder, &grey_rgb(640, 479), 640, 480).is_err());
+    }
+}
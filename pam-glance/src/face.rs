@@ -1,165 +1,696 @@
-use dlib_face_recognition::{
-    FaceDetector, FaceDetectorTrait,
-    LandmarkPredictor, LandmarkPredictorTrait,
-    FaceEncoderNetwork, FaceEncoderTrait,
-    FaceEncoding, ImageMatrix,
-};
 use opencv::prelude::*;
 use opencv::core::Mat;
 use anyhow::Result;
-use log::{debug, warn};
-use std::path::Path;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::background::Background;
+use crate::camera::{CameraType, DetectionRoi};
+use crate::embedder::{DlibEmbedder, FaceEmbedder, FaceRect};
+use crate::userfile;
+use crate::wipe::wipe_mat;
+
+/// Which detector runs. `Cuda` selects dlib's CNN detector, in builds with
+/// the `cuda` feature. Whether that runs on the GPU is decided when the
+/// system dlib is compiled (`DLIB_USE_CUDA`); the feature only makes the
+/// choice available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComputeDevice {
+    Cpu,
+    Cuda,
+}
+
+impl ComputeDevice {
+    /// Use the GPU only if this build supports it, the config allows it and
+    /// an NVIDIA device node is present; otherwise fall back to the CPU.
+    pub fn select(use_gpu: bool) -> Self {
+        if cfg!(feature = "cuda") && use_gpu && Path::new("/dev/nvidia0").exists() {
+            ComputeDevice::Cuda
+        } else {
+            ComputeDevice::Cpu
+        }
+    }
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            ComputeDevice::Cpu => "CPU (HOG detector)",
+            ComputeDevice::Cuda => "CNN detector (GPU if dlib has CUDA)",
+        }
+    }
+}
+
+/// How two encodings are compared.
+///
+/// Tolerances are always configured in Euclidean units. For `Cosine` they are
+/// converted with `d² / 2`, which is exact for unit-length vectors and close
+/// for dlib's near-unit embeddings, so one set of thresholds serves both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    /// dlib's own distance
+    #[default]
+    Euclidean,
+    /// `1 - cos θ`, in `[0, 2]`
+    Cosine,
+}
+
+impl DistanceMetric {
+    /// Distance between two encodings. Computed in `f32` like the
+    /// encodings themselves, widened for comparing against tolerances
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => {
+                a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f32>().sqrt() as f64
+            }
+            DistanceMetric::Cosine => {
+                let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+                let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+                let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm_a == 0.0 || norm_b == 0.0 {
+                    return 2.0;
+                }
+                1.0 - (dot / (norm_a * norm_b)) as f64
+            }
+        }
+    }
+    
+    /// Convert a Euclidean tolerance into this metric's units
+    pub fn scale_tolerance(&self, euclidean: f64) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => euclidean,
+            DistanceMetric::Cosine => (euclidean * euclidean / 2.0).clamp(0.0, 2.0),
+        }
+    }
+}
+
+/// What `match_face` does when one face is within tolerance of more than
+/// one enrolled user, e.g. someone enrolled under two accounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiMatchPolicy {
+    /// The user with the smallest distance
+    #[default]
+    HighestConfidence,
+    /// The first matching user in load order: tools read `$HOME` before the
+    /// system locations, the PAM module the system locations first
+    FirstMatch,
+    /// No match: the face is ambiguous
+    Reject,
+}
+
+impl MultiMatchPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MultiMatchPolicy::HighestConfidence => "highest_confidence",
+            MultiMatchPolicy::FirstMatch => "first_match",
+            MultiMatchPolicy::Reject => "reject",
+        }
+    }
+}
 
 pub struct FaceRecognizer {
-    detector: FaceDetector,
-    predictor: LandmarkPredictor,
-    encoder: FaceEncoderNetwork,
+    embedder: Box<dyn FaceEmbedder>,
     tolerance: f64,
+    device: ComputeDevice,
+    rotation_search: bool,
+    metric: DistanceMetric,
+    template_penalty: f64,
+    roi: DetectionRoi,
+    num_jitters: u32,
+    detection_scale: f64,
+    multi_match: MultiMatchPolicy,
+    background: Option<Background>,
 }
 
+/// Tilts tried, in order, when `rotation_search` is on and the upright
+/// frame has no face
+const ROTATION_ANGLES: [f64; 4] = [15.0, -15.0, 30.0, -30.0];
+
+/// Decompressed sizes of the models dlib.net ships. A file of any other size
+/// is truncated or from another release
+const MODEL_SIZES: [(&str, u64); 2] = [
+    ("shape_predictor_68_face_landmarks.dat", 99_693_937),
+    ("dlib_face_recognition_resnet_model_v1.dat", 22_466_066),
+];
+
+/// `template_penalty` never takes the tolerance below this fraction of itself
+const MIN_TOLERANCE_FACTOR: f64 = 0.5;
+
 #[derive(Clone)]
 pub struct DetectedFace {
-    pub rect: (i64, i64, i64, i64),
-    pub encoding: FaceEncoding,
+    pub rect: FaceRect,
+    pub encoding: Vec<f32>,
+}
+
+/// Faces found by `locate_faces`, and the frame they were found in when
+/// that is a masked or rotated copy rather than the caller's
+struct Located {
+    image: Option<Mat>,
+    rects: Vec<FaceRect>,
+    angle: f64,
+}
+
+impl Located {
+    fn frame<'a>(&'a self, original: &'a Mat) -> &'a Mat {
+        self.image.as_ref().unwrap_or(original)
+    }
+}
+
+impl Drop for Located {
+    fn drop(&mut self) {
+        if let Some(image) = self.image.as_mut() {
+            wipe_mat(image);
+        }
+    }
+}
+
+/// Faces found in one frame. `detected` counts every face the detector saw,
+/// including ones dlib then failed to encode.
+#[derive(Clone, Default)]
+pub struct FaceScan {
+    pub detected: usize,
+    pub faces: Vec<DetectedFace>,
+}
+
+impl FaceScan {
+    /// A face was in view but no usable encoding came out of it
+    pub fn encoding_failed(&self) -> bool {
+        self.detected > 0 && self.faces.is_empty()
+    }
+}
+
+/// Distance to the nearest of `stored`, ignoring the tolerance. Templates
+/// of another length (another model) are skipped
+fn nearest_distance(metric: DistanceMetric, detected: &[f32], stored: &[Vec<f32>]) -> Option<f64> {
+    stored.iter()
+        .filter(|stored_vec| stored_vec.len() == detected.len())
+        .map(|stored_vec| metric.distance(detected, stored_vec))
+        .min_by(f64::total_cmp)
+}
+
+/// Tolerance in `metric` units for a user with `templates` stored, with
+/// `tolerance` and `penalty` in Euclidean units
+fn tolerance_for(metric: DistanceMetric, tolerance: f64, penalty: f64, templates: usize) -> f64 {
+    let penalty = penalty * templates.saturating_sub(1) as f64;
+    metric.scale_tolerance((tolerance - penalty).max(tolerance * MIN_TOLERANCE_FACTOR))
+}
+
+/// The distance from `detected` to the nearest of one user's `stored`
+/// templates if it is within tolerance, `None` if not. Needs no models, so
+/// encodings made elsewhere can be checked too; `FaceRecognizer` matches
+/// through it with its own settings
+pub fn compare_encoding(
+    metric: DistanceMetric,
+    tolerance: f64,
+    penalty: f64,
+    detected: &[f32],
+    stored: &[Vec<f32>],
+) -> Option<f64> {
+    let min_distance = nearest_distance(metric, detected, stored)?;
+    let tolerance = tolerance_for(metric, tolerance, penalty, stored.len());
+    debug!("Best {:?} match distance: {:.4} (tolerance: {:.4})", metric, min_distance, tolerance);
+    
+    (min_distance <= tolerance).then_some(min_distance)
 }
 
 impl FaceRecognizer {
     pub fn new(models_dir: &Path, tolerance: f64) -> Result<Self> {
-        let shape_predictor_path = models_dir.join("shape_predictor_68_face_landmarks.dat");
-        let face_rec_path = models_dir.join("dlib_face_recognition_resnet_model_v1.dat");
-        
-        let detector = FaceDetector::new();
-        
-        let predictor = if shape_predictor_path.exists() {
-            LandmarkPredictor::open(shape_predictor_path).map_err(|e| anyhow::anyhow!(e))?
-        } else {
-            warn!("Shape predictor model not found at {:?}", shape_predictor_path);
-            anyhow::bail!("Shape predictor model not found");
-        };
-        
-        let encoder = if face_rec_path.exists() {
-            FaceEncoderNetwork::open(face_rec_path).map_err(|e| anyhow::anyhow!(e))?
-        } else {
-            warn!("Face recognition model not found at {:?}", face_rec_path);
-            anyhow::bail!("Face recognition model not found");
-        };
-        
-        Ok(Self {
-            detector,
-            predictor,
-            encoder,
+        Self::with_device(models_dir, tolerance, ComputeDevice::Cpu)
+    }
+    
+    pub fn with_device(models_dir: &Path, tolerance: f64, device: ComputeDevice) -> Result<Self> {
+        check_model_sizes(models_dir)?;
+        let embedder = DlibEmbedder::open(models_dir, device)?;
+        let device = embedder.device();
+        let mut recognizer = Self::with_embedder(Box::new(embedder), tolerance);
+        recognizer.device = device;
+        Ok(recognizer)
+    }
+    
+    /// Match with faces from `embedder` instead of dlib's models, e.g. a
+    /// `CannedEmbedder` in tests
+    pub fn with_embedder(embedder: Box<dyn FaceEmbedder>, tolerance: f64) -> Self {
+        Self {
+            embedder,
             tolerance,
+            device: ComputeDevice::Cpu,
+            rotation_search: false,
+            metric: DistanceMetric::default(),
+            template_penalty: 0.0,
+            roi: DetectionRoi::default(),
+            num_jitters: 0,
+            detection_scale: 1.0,
+            multi_match: MultiMatchPolicy::default(),
+            background: None,
+        }
+    }
+    
+    /// Encode each face this many times with small random distortions and
+    /// average the results: steadier encodings, proportionally slower.
+    /// 0 or 1 = a single pass
+    pub fn set_num_jitters(&mut self, num_jitters: u32) {
+        self.num_jitters = num_jitters;
+    }
+    
+    /// Run the detector on frames scaled by `scale` (e.g. 0.5), then map the
+    /// faces back to the full frame for landmarks and encoding. Faster, but
+    /// misses small, distant faces. Clamped to 0.25 – 1.0
+    pub fn set_detection_scale(&mut self, scale: f64) {
+        self.detection_scale = scale.clamp(0.25, 1.0);
+    }
+    
+    /// Only look for faces inside `roi`, see `DetectionRoi`
+    pub fn set_detection_roi(&mut self, roi: DetectionRoi) {
+        self.roi = roi;
+    }
+    
+    /// Retry detection on frames rotated ±15°/±30° when the upright frame
+    /// has no face. Up to five detector passes per empty frame.
+    pub fn set_rotation_search(&mut self, enabled: bool) {
+        self.rotation_search = enabled;
+    }
+    
+    /// Compare encodings with `metric`; the tolerance passed at construction
+    /// stays in Euclidean units and is scaled to match
+    pub fn set_metric(&mut self, metric: DistanceMetric) {
+        self.metric = metric;
+    }
+    
+    /// Tighten the tolerance by `penalty` (Euclidean units) for each of a
+    /// user's templates beyond the first, since every extra template is
+    /// another chance for a lookalike to land close to one. 0 = off
+    pub fn set_template_penalty(&mut self, penalty: f64) {
+        self.template_penalty = penalty.max(0.0);
+    }
+    
+    /// How a face matching several users is resolved, see `MultiMatchPolicy`
+    pub fn set_multi_match_policy(&mut self, policy: MultiMatchPolicy) {
+        self.multi_match = policy;
+    }
+    
+    /// Drop upright detections over unchanged parts of `background`, see
+    /// `background.rs`. None = keep every detection
+    pub fn set_background(&mut self, background: Option<Background>) {
+        self.background = background;
+    }
+    
+    /// `rects` found upright in `frame`, minus any the background says are
+    /// scenery. A failed comparison keeps them all
+    fn drop_background(&self, frame: &Mat, rects: Vec<FaceRect>) -> Vec<FaceRect> {
+        let Some(background) = &self.background else {
+            return rects;
+        };
+        let fallback = rects.clone();
+        background.drop_static(frame, rects).unwrap_or_else(|e| {
+            debug!("Background comparison failed, keeping all faces: {}", e);
+            fallback
         })
     }
     
+    /// Tolerance in `metric` units for a user with `templates` stored
+    fn tolerance_for(&self, templates: usize) -> f64 {
+        tolerance_for(self.metric, self.tolerance, self.template_penalty, templates)
+    }
+    
+    /// The device detection actually runs on after any fallback
+    pub fn device(&self) -> ComputeDevice {
+        self.device
+    }
+    
     pub fn with_defaults(tolerance: f64) -> Result<Self> {
-        let models_dir = Path::new("/usr/share/glance/models");
-        Self::new(models_dir, tolerance)
+        Self::new(&default_models_dir(), tolerance)
+    }
+    
+    /// Find faces in the frame, falling back to rotated copies if enabled.
+    /// Landmarks and encoding work best on the upright face, so the rotated
+    /// copy the faces were found in comes back with them.
+    fn locate_faces(&self, frame: &Mat) -> Result<Located> {
+        if self.roi.is_full() {
+            return self.locate_faces_in(frame);
+        }
+        
+        let mut masked = self.roi.mask(frame)?;
+        match self.locate_faces_in(&masked) {
+            Ok(mut located) if located.image.is_none() => {
+                located.image = Some(masked);
+                Ok(located)
+            }
+            other => {
+                wipe_mat(&mut masked);
+                other
+            }
+        }
+    }
+    
+    fn locate_faces_in(&self, frame: &Mat) -> Result<Located> {
+        let face_rects = self.drop_background(frame, self.detect(frame)?);
+        
+        if !face_rects.is_empty() || !self.rotation_search {
+            return Ok(Located { image: None, rects: face_rects, angle: 0.0 });
+        }
+        
+        for angle in ROTATION_ANGLES {
+            let mut rotated = rotate_frame(frame, angle)?;
+            let rects = match self.detect(&rotated) {
+                Ok(rects) => rects,
+                Err(e) => {
+                    wipe_mat(&mut rotated);
+                    return Err(e);
+                }
+            };
+            if !rects.is_empty() {
+                debug!("Found {} face(s) in frame rotated {}°", rects.len(), angle);
+                return Ok(Located { image: Some(rotated), rects, angle });
+            }
+            wipe_mat(&mut rotated);
+        }
+        
+        Ok(Located { image: None, rects: face_rects, angle: 0.0 })
     }
     
-    pub fn detect_faces(&self, frame: &Mat) -> Result<Vec<DetectedFace>> {
-        let image = opencv_to_dlib(frame)?;
+    /// Faces in `frame`, detected on a copy scaled by `detection_scale`
+    fn detect(&self, frame: &Mat) -> Result<Vec<FaceRect>> {
+        if self.detection_scale >= 1.0 {
+            return self.embedder.detect(frame);
+        }
         
-        let face_rects = self.detector.face_locations(&image);
+        let mut small = Mat::default();
+        opencv::imgproc::resize(frame, &mut small, opencv::core::Size::default(),
+                                self.detection_scale, self.detection_scale, opencv::imgproc::INTER_AREA)?;
+        let detected = self.embedder.detect(&small);
+        wipe_mat(&mut small);
+        
+        let scale_up = |v: i64| (v as f64 / self.detection_scale).round() as i64;
+        Ok(detected?.into_iter()
+            .map(|(left, top, right, bottom)| (scale_up(left), scale_up(top), scale_up(right), scale_up(bottom)))
+            .collect())
+    }
+    
+    /// Where faces are in the frame, without landmarks or encoding. Cheap
+    /// enough to check a face has settled before paying for `detect_faces`
+    pub fn locate_face_rects(&self, frame: &Mat) -> Result<Vec<FaceRect>> {
+        let located = self.locate_faces(frame)?;
+        let size = (frame.cols(), frame.rows());
+        Ok(located.rects.iter()
+            .map(|&rect| unrotate_rect(rect, located.angle, size))
+            .collect())
+    }
+    
+    pub fn detect_faces(&self, frame: &Mat) -> Result<FaceScan> {
+        let located = self.locate_faces(frame)?;
+        let size = (frame.cols(), frame.rows());
         
-        if face_rects.is_empty() {
-            return Ok(Vec::new());
+        if located.rects.is_empty() {
+            return Ok(FaceScan::default());
         }
         
-        debug!("Detected {} face(s)", face_rects.len());
+        debug!("Detected {} face(s)", located.rects.len());
         
+        let image = located.frame(frame);
         let mut faces = Vec::new();
         
-        for rect in face_rects.iter() {
-            let landmarks = self.predictor.face_landmarks(&image, &rect);
-            
-            let encodings = self.encoder.get_face_encodings(
-                &image, 
-                &[landmarks], 
-                0,
-            );
-            
-            if !encodings.is_empty() {
+        for &rect in &located.rects {
+            if let Some(encoding) = self.embedder.encode(image, rect, self.num_jitters)? {
                 faces.push(DetectedFace {
-                    rect: (rect.left, rect.top, rect.right, rect.bottom),
-                    encoding: encodings[0].clone(),
+                    rect: unrotate_rect(rect, located.angle, size),
+                    encoding,
                 });
             }
         }
         
-        Ok(faces)
+        Ok(FaceScan { detected: located.rects.len(), faces })
     }
     
-    pub fn compare_face(&self, detected: &FaceEncoding, stored: &[Vec<f64>]) -> Option<f64> {
-        if stored.is_empty() {
-            return None;
+    /// Detect every face in the frame and encode them in a single batched
+    /// encoder call, rather than one network pass per face.
+    /// Cheaper than `detect_faces` when several people are in view.
+    pub fn detect_faces_batch(&self, frame: &Mat) -> Result<FaceScan> {
+        let located = self.locate_faces(frame)?;
+        let size = (frame.cols(), frame.rows());
+        
+        if located.rects.is_empty() {
+            return Ok(FaceScan::default());
         }
         
-        let mut min_distance = f64::MAX;
+        debug!("Detected {} face(s), batch encoding", located.rects.len());
         
-        for stored_vec in stored {
-            if let Ok(stored_enc) = FaceEncoding::from_vec(stored_vec) {
-                let distance = detected.distance(&stored_enc);
-                if distance < min_distance {
-                    min_distance = distance;
-                }
-            }
-        }
+        let encodings = self.embedder.encode_all(located.frame(frame), &located.rects, self.num_jitters)?;
         
-        debug!("Best match distance: {:.4} (tolerance: {:.4})", min_distance, self.tolerance);
+        let faces = located.rects.iter()
+            .zip(encodings)
+            .filter_map(|(&rect, encoding)| Some(DetectedFace {
+                rect: unrotate_rect(rect, located.angle, size),
+                encoding: encoding?,
+            }))
+            .collect();
         
-        if min_distance <= self.tolerance {
-            Some(min_distance)
-        } else {
-            None
-        }
+        Ok(FaceScan { detected: located.rects.len(), faces })
+    }
+    
+    /// Distance to the nearest of `stored`, ignoring the tolerance
+    fn nearest_distance(&self, detected: &[f32], stored: &[Vec<f32>]) -> Option<f64> {
+        nearest_distance(self.metric, detected, stored)
+    }
+    
+    pub fn compare_face(&self, detected: &[f32], stored: &[Vec<f32>]) -> Option<f64> {
+        compare_encoding(self.metric, self.tolerance, self.template_penalty, detected, stored)
     }
     
-    pub fn match_face(&self, detected: &FaceEncoding, users_faces: &[(String, Vec<Vec<f64>>)]) -> Option<(String, f64)> {
-        let mut best_match: Option<(String, f64)> = None;
+    /// The enrolled user `detected` matches. Several users within tolerance
+    /// are resolved by the `MultiMatchPolicy`
+    pub fn match_face(&self, detected: &[f32], users_faces: &[(String, Vec<Vec<f32>>)]) -> Option<(String, f64)> {
+        let matches: Vec<(&String, f64)> = users_faces.iter()
+            .filter_map(|(username, face_encodings)| {
+                self.compare_face(detected, face_encodings).map(|distance| (username, distance))
+            })
+            .collect();
         
-        for (username, face_encodings) in users_faces {
-            if let Some(distance) = self.compare_face(detected, face_encodings) {
-                if best_match.is_none() || distance < best_match.as_ref().unwrap().1 {
-                    best_match = Some((username.clone(), distance));
-                }
-            }
+        if matches.len() > 1 {
+            let names: Vec<&str> = matches.iter().map(|(username, _)| username.as_str()).collect();
+            info!("Face matches {} users ({}) — resolving with {}",
+                  matches.len(), names.join(", "), self.multi_match.label());
         }
         
-        best_match
+        let chosen = match self.multi_match {
+            MultiMatchPolicy::HighestConfidence => matches.into_iter()
+                .min_by(|a, b| a.1.total_cmp(&b.1)),
+            MultiMatchPolicy::FirstMatch => matches.into_iter().next(),
+            MultiMatchPolicy::Reject if matches.len() > 1 => {
+                warn!("Ambiguous face rejected (multi_match_policy = reject)");
+                None
+            }
+            MultiMatchPolicy::Reject => matches.into_iter().next(),
+        };
+        chosen.map(|(username, distance)| (username.clone(), distance))
+    }
+    
+    /// How close the nearest registered template is to any detected face,
+    /// matched or not, as a fraction of the tolerance (1.0 = on the edge)
+    pub fn closest_ratio(&self, faces: &[DetectedFace], users_faces: &[(String, Vec<Vec<f32>>)]) -> Option<f64> {
+        faces.iter()
+            .flat_map(|face| users_faces.iter()
+                .filter_map(|(_, stored)| self.nearest_distance(&face.encoding, stored)
+                    .map(|distance| distance / self.tolerance_for(stored.len()))))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+    
+    /// Distance from any detected face to the nearest registered template,
+    /// matched or not
+    pub fn closest_distance(&self, faces: &[DetectedFace], users_faces: &[(String, Vec<Vec<f32>>)]) -> Option<f64> {
+        faces.iter()
+            .flat_map(|face| users_faces.iter()
+                .filter_map(|(_, stored)| self.nearest_distance(&face.encoding, stored)))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+    
+    /// Match every detected face against the registered set and return the
+    /// best `(username, distance)` across all of them.
+    pub fn match_faces(&self, faces: &[DetectedFace], users_faces: &[(String, Vec<Vec<f32>>)]) -> Option<(String, f64)> {
+        faces.iter()
+            .filter_map(|face| self.match_face(&face.encoding, users_faces))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+    }
+}
+
+/// Fail with an actionable message instead of letting dlib load a truncated
+/// or mismatched model. A custom `recognition.models_dir` may hold another
+/// encoder on purpose, so there a mismatch is only logged
+fn check_model_sizes(models_dir: &Path) -> Result<()> {
+    let custom = models_dir != default_models_dir();
+    for (name, expected) in MODEL_SIZES {
+        let Ok(size) = std::fs::metadata(models_dir.join(name)).map(|m| m.len()) else {
+            continue;
+        };
+        if size == expected {
+            continue;
+        }
+        if custom {
+            warn!("{} in {:?} is {} bytes, not the stock {}", name, models_dir, size, expected);
+        } else {
+            anyhow::bail!("Model file {} appears to be the wrong version ({} bytes, expected {}) — re-download it",
+                          name, size, expected);
+        }
+    }
+    Ok(())
+}
+
+/// Whether both model files are in `models_dir`
+pub fn models_present(models_dir: &Path) -> bool {
+    MODEL_SIZES.iter().all(|(name, _)| models_dir.join(name).exists())
+}
+
+/// Whether `name` can select a model set: one plain path component, e.g.
+/// `dlib-v1`. Empty = none, the flat layout
+pub fn valid_model_set(name: &str) -> bool {
+    name.is_empty() || (name != "." && name != ".."
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.')))
+}
+
+/// The `model_set` subdirectory of a models directory, or the directory
+/// itself for no set. A set name that isn't valid is ignored
+pub fn model_set_dir(dir: &Path, model_set: &str) -> PathBuf {
+    if model_set.is_empty() {
+        return dir.to_path_buf();
+    }
+    if !valid_model_set(model_set) {
+        warn!("recognition.model_set={:?} is not a plain directory name, ignoring", model_set);
+        return dir.to_path_buf();
     }
+    dir.join(model_set)
 }
 
-fn opencv_to_dlib(mat: &Mat) -> Result<ImageMatrix> {
+/// Models directory: `GLANCE_MODELS_DIR` if it holds both models (for Nix,
+/// Guix and other packagers with non-standard paths), else the system path.
+pub fn default_models_dir() -> PathBuf {
+    models_dir_for_set("")
+}
+
+/// `default_models_dir` with `model_set` selecting a subdirectory of it,
+/// e.g. /usr/share/glance/models/dlib-v1
+pub fn models_dir_for_set(model_set: &str) -> PathBuf {
+    if let Some(dir) = std::env::var_os("GLANCE_MODELS_DIR").map(PathBuf::from) {
+        let dir = model_set_dir(&dir, model_set);
+        if models_present(&dir) {
+            return dir;
+        }
+        warn!("GLANCE_MODELS_DIR={:?} does not contain the models, ignoring", dir);
+    }
+    
+    model_set_dir(Path::new("/usr/share/glance/models"), model_set)
+}
+
+/// `recognition.models_dir` if it's set and holds both models, else
+/// `models_dir_for_set` with `recognition.model_set`. A custom directory is
+/// used as it is, without the set
+pub fn configured_models_dir(configured: &str, model_set: &str) -> PathBuf {
+    if !configured.is_empty() {
+        let dir = PathBuf::from(configured);
+        if models_present(&dir) {
+            return dir;
+        }
+        warn!("recognition.models_dir={:?} does not contain the models, ignoring", dir);
+    }
+    
+    let dir = models_dir_for_set(model_set);
+    if !model_set.is_empty() && !models_present(&dir) {
+        warn!("Model set {:?} is not installed in {:?}", model_set, dir);
+    }
+    dir
+}
+
+/// Rotate a frame about its centre, keeping its size (corners are cropped)
+fn rotate_frame(frame: &Mat, angle: f64) -> Result<Mat> {
+    use opencv::core::{Point2f, Scalar, BORDER_CONSTANT};
     use opencv::imgproc;
     
-    let rgb = if mat.channels() == 3 {
-        let mut rgb = Mat::default();
-        imgproc::cvt_color(mat, &mut rgb, imgproc::COLOR_BGR2RGB, 0)?;
-        rgb
-    } else if mat.channels() == 1 {
-        let mut rgb = Mat::default();
-        imgproc::cvt_color(mat, &mut rgb, imgproc::COLOR_GRAY2RGB, 0)?;
-        rgb
-    } else {
-        mat.clone()
-    };
+    let center = Point2f::new(frame.cols() as f32 / 2.0, frame.rows() as f32 / 2.0);
+    let matrix = imgproc::get_rotation_matrix_2d(center, angle, 1.0)?;
+    
+    let mut rotated = Mat::default();
+    imgproc::warp_affine(frame, &mut rotated, &matrix, frame.size()?,
+                         imgproc::INTER_LINEAR, BORDER_CONSTANT, Scalar::default())?;
+    Ok(rotated)
+}
+
+/// Map a `(left, top, right, bottom)` rect found in a frame rotated by
+/// `angle` back to the original frame: the bounding box of its corners
+/// rotated by `-angle` about the frame centre.
+fn unrotate_rect(rect: FaceRect, angle: f64, size: (i32, i32)) -> FaceRect {
+    if angle == 0.0 {
+        return rect;
+    }
+    
+    let (cx, cy) = (size.0 as f64 / 2.0, size.1 as f64 / 2.0);
+    let (sin, cos) = (-angle).to_radians().sin_cos();
+    let (l, t, r, b) = rect;
     
-    let rows = rgb.rows() as usize;
-    let cols = rgb.cols() as usize;
+    let corners = [(l, t), (r, t), (l, b), (r, b)].map(|(x, y)| {
+        let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+        // Same convention as getRotationMatrix2D (positive = counter-clockwise)
+        (cx + dx * cos + dy * sin, cy - dx * sin + dy * cos)
+    });
     
-    let data = rgb.data_bytes()?;
+    let min_x = corners.iter().map(|c| c.0).fold(f64::MAX, f64::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f64::MIN, f64::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f64::MAX, f64::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f64::MIN, f64::max);
     
-    let image = unsafe { ImageMatrix::new(cols, rows, data.as_ptr()) };
+    (min_x as i64, min_y as i64, max_x as i64, max_y as i64)
+}
+
+/// Pose recorded by the GUI for templates captured while wearing a mask
+pub const MASKED_POSE: &str = "masked";
+
+/// Which stored templates are usable for this auth attempt
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateFilter<'a> {
+    /// Recognition model in use; templates tagged with another are skipped
+    pub model_id: Option<&'a str>,
+    /// Include templates captured while wearing a mask (`mask_friendly`)
+    pub include_masked: bool,
+    /// Only templates enrolled on this kind of camera. Untagged ones are
+    /// left out, since their modality is unknown
+    pub camera: Option<CameraType>,
+    /// Only read files owned by this uid, e.g. in a home directory whose
+    /// owner might not be the one logging in. None = any file
+    pub owner: Option<u32>,
+}
+
+impl TemplateFilter<'_> {
+    /// Whether templates enrolled on a `tag` ("ir", "rgb" or "") camera pass
+    fn allows_camera(&self, tag: &str) -> bool {
+        match self.camera {
+            None => true,
+            Some(CameraType::Infrared) => tag == "ir",
+            Some(CameraType::Rgb) => tag == "rgb",
+            Some(CameraType::Unknown) => false,
+        }
+    }
     
-    Ok(image)
+    /// `path`'s content, if `owner` allows the file
+    fn read(&self, path: &Path) -> Result<String> {
+        use std::os::unix::fs::MetadataExt;
+        
+        if let Some(owner) = self.owner {
+            let uid = std::fs::metadata(path)?.uid();
+            anyhow::ensure!(uid == owner, "{:?} is owned by uid {}, not {}", path, uid, owner);
+        }
+        Ok(std::fs::read_to_string(path)?)
+    }
 }
 
-pub fn load_user_faces(data_dir: &Path, username: &str) -> Result<Vec<Vec<f64>>> {
-    let paths_to_try = [
-        data_dir.join(format!("{}_face.json", username)),
-        data_dir.join(format!("{}.json", username)),
+pub fn load_user_faces(data_dir: &Path, username: &str, filter: &TemplateFilter) -> Result<Vec<Vec<f32>>> {
+    let stem = userfile::file_stem(username);
+    let mut paths_to_try = vec![
+        data_dir.join(format!("{}_face.json", stem)),
+        data_dir.join(format!("{}.json", stem)),
     ];
+    // Written under the raw name before usernames were encoded
+    if let Some(legacy) = userfile::legacy_stem(username) {
+        paths_to_try.push(data_dir.join(format!("{}_face.json", legacy)));
+        paths_to_try.push(data_dir.join(format!("{}.json", legacy)));
+    }
     
     let mut face_data_path = None;
     for path in &paths_to_try {
@@ -171,9 +702,10 @@ pub fn load_user_faces(data_dir: &Path, username: &str) -> Result<Vec<Vec<f64>>>
     
     if face_data_path.is_none() {
         let config_path = data_dir.join("config.json");
-        if config_path.exists() {
+        // Entries there carry no camera type
+        if config_path.exists() && filter.camera.is_none() {
             let config: serde_json::Value = serde_json::from_str(
-                &std::fs::read_to_string(&config_path)?
+                &filter.read(&config_path)?
             )?;
             
             if let Some(faces) = config.get("registered_faces") {
@@ -183,8 +715,8 @@ pub fn load_user_faces(data_dir: &Path, username: &str) -> Result<Vec<Vec<f64>>>
                             let mut result = Vec::new();
                             for enc in arr {
                                 if let Some(enc_arr) = enc.as_array() {
-                                    let encoding: Vec<f64> = enc_arr.iter()
-                                        .filter_map(|v| v.as_f64())
+                                    let encoding: Vec<f32> = enc_arr.iter()
+                                        .filter_map(|v| v.as_f64().map(|x| x as f32))
                                         .collect();
                                     if !encoding.is_empty() {
                                         result.push(encoding);
@@ -201,25 +733,31 @@ pub fn load_user_faces(data_dir: &Path, username: &str) -> Result<Vec<Vec<f64>>>
     }
     
     let face_file = face_data_path.unwrap();
-    let content = std::fs::read_to_string(&face_file)?;
+    let content = filter.read(&face_file)?;
     let data: serde_json::Value = serde_json::from_str(&content)?;
     
     let mut encodings = Vec::new();
     
+    let mut skipped = 0;
+    
     // Load legacy encodings
     if let Some(arr) = data.get("encodings").and_then(|e| e.as_array()) {
         for enc in arr {
-            if let Some(nested_enc) = enc.get("encoding").and_then(|e| e.as_array()) {
-                let encoding: Vec<f64> = nested_enc.iter()
-                    .filter_map(|v| v.as_f64())
-                    .collect();
-                if !encoding.is_empty() {
+            if enc.get("encoding").is_some() {
+                let tag = enc.get("camera_type").and_then(|t| t.as_str()).unwrap_or("");
+                if !filter.allows_camera(tag) {
+                    continue;
+                }
+                if let Some(encoding) = nested_encoding(enc, filter, &mut skipped) {
                     encodings.push(encoding);
                 }
             }
+            else if !filter.allows_camera("") {
+                continue;
+            }
             else if let Some(enc_arr) = enc.as_array() {
-                let encoding: Vec<f64> = enc_arr.iter()
-                    .filter_map(|v| v.as_f64())
+                let encoding: Vec<f32> = enc_arr.iter()
+                    .filter_map(|v| v.as_f64().map(|x| x as f32))
                     .collect();
                 if !encoding.is_empty() {
                     encodings.push(encoding);
@@ -229,43 +767,97 @@ pub fn load_user_faces(data_dir: &Path, username: &str) -> Result<Vec<Vec<f64>>>
     }
     
     // Load IR encodings (new format)
-    if let Some(arr) = data.get("ir_encodings").and_then(|e| e.as_array()) {
+    if let Some(arr) = data.get("ir_encodings").and_then(|e| e.as_array()).filter(|_| filter.allows_camera("ir")) {
         for enc in arr {
-            if let Some(nested_enc) = enc.get("encoding").and_then(|e| e.as_array()) {
-                let encoding: Vec<f64> = nested_enc.iter()
-                    .filter_map(|v| v.as_f64())
-                    .collect();
-                if !encoding.is_empty() {
-                    encodings.push(encoding);
-                }
+            if let Some(encoding) = nested_encoding(enc, filter, &mut skipped) {
+                encodings.push(encoding);
             }
         }
     }
     
     // Load RGB encodings (new format)
-    if let Some(arr) = data.get("rgb_encodings").and_then(|e| e.as_array()) {
+    if let Some(arr) = data.get("rgb_encodings").and_then(|e| e.as_array()).filter(|_| filter.allows_camera("rgb")) {
         for enc in arr {
-            if let Some(nested_enc) = enc.get("encoding").and_then(|e| e.as_array()) {
-                let encoding: Vec<f64> = nested_enc.iter()
-                    .filter_map(|v| v.as_f64())
-                    .collect();
-                if !encoding.is_empty() {
-                    encodings.push(encoding);
-                }
+            if let Some(encoding) = nested_encoding(enc, filter, &mut skipped) {
+                encodings.push(encoding);
             }
         }
     }
     
+    if skipped > 0 {
+        warn!("Skipped {} encoding(s) for '{}' made with a different recognition model — re-enroll with the Glance app",
+              skipped, username);
+    }
+    
     Ok(encodings)
 }
 
-pub fn load_all_faces(data_dir: &Path) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
+/// Read a nested `{"encoding": [...], "model_id": "..."}` entry. Entries tagged
+/// with a different model live in another embedding space and can't be
+/// compared, so they are skipped and counted. Untagged entries predate
+/// tagging and are kept. Masked templates are dropped unless the filter
+/// allows them.
+fn nested_encoding(enc: &serde_json::Value, filter: &TemplateFilter, skipped: &mut usize) -> Option<Vec<f32>> {
+    if !filter.include_masked && enc.get("pose").and_then(|p| p.as_str()) == Some(MASKED_POSE) {
+        return None;
+    }
+    
+    let encoding: Vec<f32> = enc.get("encoding")?
+        .as_array()?
+        .iter()
+        .filter_map(|v| v.as_f64().map(|x| x as f32))
+        .collect();
+    
+    if encoding.is_empty() {
+        return None;
+    }
+    
+    let tag = enc.get("model_id").and_then(|t| t.as_str()).unwrap_or("");
+    if let Some(current) = filter.model_id {
+        if !tag.is_empty() && tag != current {
+            *skipped += 1;
+            return None;
+        }
+    }
+    
+    Some(encoding)
+}
+
+/// Identify the recognition model so encodings from a different model can be
+/// told apart. Hashes the file size plus its first and last 64 KiB, which is
+/// enough to distinguish model files without reading all of them at auth time.
+pub fn model_id(models_dir: &Path) -> Option<String> {
+    use sha2::{Digest, Sha256};
+    use std::io::{Read, Seek, SeekFrom};
+    
+    const CHUNK: u64 = 64 * 1024;
+    
+    let mut file = std::fs::File::open(models_dir.join("dlib_face_recognition_resnet_model_v1.dat")).ok()?;
+    let len = file.metadata().ok()?.len();
+    
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_le_bytes());
+    
+    let mut buf = Vec::with_capacity(CHUNK as usize);
+    file.by_ref().take(CHUNK).read_to_end(&mut buf).ok()?;
+    hasher.update(&buf);
+    
+    buf.clear();
+    file.seek(SeekFrom::Start(len.saturating_sub(CHUNK))).ok()?;
+    file.take(CHUNK).read_to_end(&mut buf).ok()?;
+    hasher.update(&buf);
+    
+    let digest = hasher.finalize();
+    Some(digest.iter().take(8).map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn load_all_faces(data_dir: &Path, filter: &TemplateFilter) -> Result<Vec<(String, Vec<Vec<f32>>)>> {
     let mut all_faces = Vec::new();
     
     let config_path = data_dir.join("config.json");
-    if config_path.exists() {
+    if config_path.exists() && filter.camera.is_none() {
         let config: serde_json::Value = serde_json::from_str(
-            &std::fs::read_to_string(&config_path)?
+            &filter.read(&config_path)?
         )?;
         
         if let Some(faces) = config.get("registered_faces") {
@@ -276,8 +868,8 @@ pub fn load_all_faces(data_dir: &Path) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
                             let mut user_encodings = Vec::new();
                             for enc in arr {
                                 if let Some(enc_arr) = enc.as_array() {
-                                    let encoding: Vec<f64> = enc_arr.iter()
-                                        .filter_map(|v| v.as_f64())
+                                    let encoding: Vec<f32> = enc_arr.iter()
+                                        .filter_map(|v| v.as_f64().map(|x| x as f32))
                                         .collect();
                                     if !encoding.is_empty() {
                                         user_encodings.push(encoding);
@@ -299,9 +891,9 @@ pub fn load_all_faces(data_dir: &Path) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
             let name = entry.file_name().to_string_lossy().to_string();
             
             let username = if name.ends_with("_face.json") {
-                name.strip_suffix("_face.json").unwrap().to_string()
+                userfile::username_from_stem(name.strip_suffix("_face.json").unwrap())
             } else if name.ends_with(".json") && name != "config.json" {
-                name.strip_suffix(".json").unwrap().to_string()
+                userfile::username_from_stem(name.strip_suffix(".json").unwrap())
             } else {
                 continue;
             };
@@ -310,7 +902,7 @@ pub fn load_all_faces(data_dir: &Path) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
                 continue;
             }
             
-            if let Ok(encodings) = load_user_faces(data_dir, &username) {
+            if let Ok(encodings) = load_user_faces(data_dir, &username, filter) {
                 if !encodings.is_empty() {
                     all_faces.push((username, encodings));
                 }
@@ -320,3 +912,98 @@ pub fn load_all_faces(data_dir: &Path) -> Result<Vec<(String, Vec<Vec<f64>>)>> {
     
     Ok(all_faces)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedder::CannedEmbedder;
+    use opencv::core::{Scalar, CV_8UC3};
+    
+    const FACE: FaceRect = (200, 120, 440, 360);
+    
+    fn frame() -> Mat {
+        Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(128.0)).unwrap()
+    }
+    
+    /// 128-d encoding that is `value` along `axis` and zero elsewhere
+    fn encoding(axis: usize, value: f32) -> Vec<f32> {
+        let mut encoding = vec![0.0; 128];
+        encoding[axis] = value;
+        encoding
+    }
+    
+    fn recognizer(faces: Vec<(FaceRect, Vec<f32>)>) -> FaceRecognizer {
+        FaceRecognizer::with_embedder(Box::new(CannedEmbedder::new(faces)), 0.5)
+    }
+    
+    fn enrolled() -> Vec<(String, Vec<Vec<f32>>)> {
+        vec![
+            ("alice".to_string(), vec![encoding(0, 1.0)]),
+            ("bob".to_string(), vec![encoding(1, 1.0)]),
+        ]
+    }
+    
+    #[test]
+    fn canned_faces_are_detected_and_encoded() {
+        let recognizer = recognizer(vec![(FACE, encoding(0, 1.0))]);
+        for scan in [recognizer.detect_faces(&frame()).unwrap(), recognizer.detect_faces_batch(&frame()).unwrap()] {
+            assert_eq!(scan.detected, 1);
+            assert_eq!(scan.faces.len(), 1);
+            assert_eq!(scan.faces[0].rect, FACE);
+            assert_eq!(scan.faces[0].encoding, encoding(0, 1.0));
+        }
+    }
+    
+    #[test]
+    fn no_canned_face_is_an_empty_scan() {
+        let scan = recognizer(Vec::new()).detect_faces(&frame()).unwrap();
+        assert_eq!(scan.detected, 0);
+        assert!(!scan.encoding_failed());
+    }
+    
+    #[test]
+    fn close_face_matches_its_user() {
+        let recognizer = recognizer(vec![(FACE, encoding(0, 1.3))]);
+        let scan = recognizer.detect_faces(&frame()).unwrap();
+        let (username, distance) = recognizer.match_face(&scan.faces[0].encoding, &enrolled()).unwrap();
+        assert_eq!(username, "alice");
+        assert!((distance - 0.3).abs() < 1e-6, "distance {}", distance);
+    }
+    
+    #[test]
+    fn distant_face_matches_nobody() {
+        let recognizer = recognizer(vec![(FACE, encoding(2, 1.0))]);
+        let scan = recognizer.detect_faces(&frame()).unwrap();
+        assert!(recognizer.match_face(&scan.faces[0].encoding, &enrolled()).is_none());
+        assert!(recognizer.closest_distance(&scan.faces, &enrolled()).unwrap() > 1.0);
+    }
+    
+    #[test]
+    fn template_penalty_tightens_tolerance() {
+        let templates = vec![encoding(0, 1.0), encoding(2, 1.0), encoding(3, 1.0)];
+        let users = vec![("alice".to_string(), templates)];
+        let mut recognizer = recognizer(Vec::new());
+        let detected = encoding(0, 1.45);
+        
+        assert!(recognizer.match_face(&detected, &users).is_some());
+        recognizer.set_template_penalty(0.05);
+        assert!(recognizer.match_face(&detected, &users).is_none());
+    }
+    
+    #[test]
+    fn multi_match_policies() {
+        let users = vec![
+            ("carol".to_string(), vec![encoding(0, 1.3)]),
+            ("alice".to_string(), vec![encoding(0, 1.0)]),
+        ];
+        let detected = encoding(0, 1.1);
+        let mut recognizer = recognizer(Vec::new());
+        
+        let matched = |recognizer: &FaceRecognizer| recognizer.match_face(&detected, &users).map(|(user, _)| user);
+        assert_eq!(matched(&recognizer).as_deref(), Some("alice"));
+        recognizer.set_multi_match_policy(MultiMatchPolicy::FirstMatch);
+        assert_eq!(matched(&recognizer).as_deref(), Some("carol"));
+        recognizer.set_multi_match_policy(MultiMatchPolicy::Reject);
+        assert!(matched(&recognizer).is_none());
+    }
+}
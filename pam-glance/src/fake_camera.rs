@@ -0,0 +1,80 @@
+//! Frame source that replays still images instead of opening a camera.
+//!
+//! Only built with the `fake-camera` feature. Used by `test_auth --frames`
+//! to drive the real detection and matching pipeline from a directory of
+//! images, e.g. in CI where there is no camera.
+
+use crate::camera::FrameSource;
+
+use anyhow::{Context, Result};
+use opencv::{
+    prelude::*,
+    core::{Mat, Scalar, CV_8UC3},
+    imgcodecs,
+};
+use std::path::Path;
+
+/// Replays a fixed list of frames in a loop
+pub struct ImageFrameSource {
+    frames: Vec<Mat>,
+    next: usize,
+    is_ir: bool,
+}
+
+impl ImageFrameSource {
+    pub fn new(frames: Vec<Mat>, is_ir: bool) -> Result<Self> {
+        if frames.is_empty() {
+            anyhow::bail!("Fake camera needs at least one frame");
+        }
+        Ok(Self { frames, next: 0, is_ir })
+    }
+    
+    /// Load every `.png`/`.jpg`/`.jpeg` in `dir`, in file name order
+    pub fn from_dir(dir: &Path, is_ir: bool) -> Result<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {:?}", dir))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| matches!(e.to_ascii_lowercase().as_str(), "png" | "jpg" | "jpeg"))
+                    .unwrap_or(false)
+            })
+            .collect();
+        paths.sort();
+        
+        let mut frames = Vec::with_capacity(paths.len());
+        for path in &paths {
+            let frame = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+            if frame.empty() {
+                anyhow::bail!("Could not decode {:?}", path);
+            }
+            frames.push(frame);
+        }
+        
+        Self::new(frames, is_ir)
+    }
+    
+    /// A source that only ever returns black frames, like a covered lens
+    pub fn black(width: i32, height: i32) -> Result<Self> {
+        let frame = Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(0.0))?;
+        Self::new(vec![frame], false)
+    }
+}
+
+impl FrameSource for ImageFrameSource {
+    fn read(&mut self) -> Result<Mat> {
+        let frame = self.frames[self.next].try_clone()?;
+        self.next = (self.next + 1) % self.frames.len();
+        Ok(frame)
+    }
+    
+    fn is_ir(&self) -> bool {
+        self.is_ir
+    }
+    
+    fn detects_stalls(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,67 @@
+//! Feedback hooks: run a configured command after each auth attempt
+//!
+//! Lets users get an audible or visual cue (e.g. `canberra-gtk-play`) during
+//! screen unlock, where the GUI isn't visible. Commands come from the system
+//! config only — the module often runs as root (sudo, login), so a command
+//! from a user-writable file must never be executed here.
+//!
+//! Hook commands run as root whenever the module does. Under `su` the
+//! caller's environment is still in place, so the shell is spawned by
+//! absolute path with a cleared environment: only `GLANCE_RESULT`,
+//! `GLANCE_USER` and a fixed `PATH` reach the command.
+
+use crate::auth::AuthResult;
+use crate::config::{GlanceConfig, HooksConfig};
+
+use log::{debug, warn};
+use std::process::{Command, Stdio};
+
+const SYSTEM_CONFIG: &str = "/etc/glance/config.json";
+const SHELL: &str = "/bin/sh";
+const HOOK_PATH: &str = "/usr/sbin:/usr/bin:/sbin:/bin";
+
+fn load_hooks() -> HooksConfig {
+    match GlanceConfig::load(SYSTEM_CONFIG) {
+        Ok(config) => config.hooks,
+        Err(e) => {
+            warn!("Could not read hooks from {}: {}", SYSTEM_CONFIG, e);
+            HooksConfig::default()
+        }
+    }
+}
+
+/// Run `on_success_cmd` or `on_failure_cmd` for this result, if configured.
+/// Never blocks auth on the command itself.
+pub fn run_result_hook(result: &AuthResult, username: &str) {
+    let hooks = load_hooks();
+    let (cmd, outcome) = match result {
+        AuthResult::Success { .. } => (hooks.on_success_cmd, "success"),
+        _ => (hooks.on_failure_cmd, "failure"),
+    };
+    
+    if cmd.trim().is_empty() {
+        return;
+    }
+    
+    debug!("Running {} hook: {}", outcome, cmd);
+    
+    // The outer shell backgrounds the command and exits straight away, so the
+    // hook is reparented to init and there is no child left for us to reap.
+    let spawned = Command::new(SHELL)
+        .arg("-c")
+        .arg("/bin/sh -c \"$0\" &")
+        .arg(&cmd)
+        .env_clear()
+        .env("PATH", HOOK_PATH)
+        .env("GLANCE_RESULT", outcome)
+        .env("GLANCE_USER", username)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .and_then(|mut child| child.wait());
+    
+    if let Err(e) = spawned {
+        warn!("Failed to run {} hook: {}", outcome, e);
+    }
+}
@@ -2,12 +2,18 @@ use anyhow::{Result, Context};
 use log::{info, debug, warn};
 use std::process::{Command, Child, Stdio};
 use std::time::Duration;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+/// Where `linux-enable-ir-emitter configure` saves its `.ini` profiles
+pub const CONFIG_DIR: &str = "/etc/linux-enable-ir-emitter";
+
+#[derive(Debug)]
 pub struct IrEmitter {
     device: String,
     enabled: bool,
     child_process: Option<Child>,
+    /// Profile passed as `--config`; the tool picks its own when `None`
+    config: Option<PathBuf>,
 }
 
 impl IrEmitter {
@@ -16,9 +22,42 @@ impl IrEmitter {
             device: device.to_string(),
             enabled: false,
             child_process: None,
+            config: None,
         }
     }
     
+    /// Use `profile` instead of letting the tool choose
+    pub fn set_config(&mut self, profile: Option<PathBuf>) {
+        self.config = profile;
+    }
+    
+    /// The `.ini` profiles in `CONFIG_DIR`, sorted by name
+    pub fn list_configs() -> Vec<PathBuf> {
+        let mut configs: Vec<PathBuf> = std::fs::read_dir(CONFIG_DIR)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file() && p.extension().map_or(false, |e| e == "ini"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        configs.sort();
+        configs
+    }
+    
+    /// The listed profile `name` refers to, by file name or full path.
+    /// Anything outside `CONFIG_DIR` is refused: the tool runs as root
+    pub fn find_config(name: &str) -> Option<PathBuf> {
+        let found = Self::list_configs().into_iter().find(|p| {
+            p == Path::new(name) || p.file_name().and_then(|n| n.to_str()) == Some(name)
+        });
+        if found.is_none() {
+            warn!("IR emitter profile {:?} not found in {}", name, CONFIG_DIR);
+        }
+        found
+    }
+    
     pub fn is_installed() -> bool {
         Self::find_executable().is_some()
     }
@@ -54,7 +93,7 @@ impl IrEmitter {
     }
     
     pub fn is_configured(device: &str) -> bool {
-        let config_dir = Path::new("/etc/linux-enable-ir-emitter");
+        let config_dir = Path::new(CONFIG_DIR);
         if !config_dir.exists() {
             return false;
         }
@@ -99,9 +138,13 @@ impl IrEmitter {
         info!("Enabling IR emitter for {} using {}", self.device, executable);
         
         // Start the IR emitter as a background process so we can kill it later
-        let child = Command::new(&executable)
-            .arg("--device")
-            .arg(&self.device)
+        let mut cmd = Command::new(&executable);
+        cmd.arg("--device").arg(&self.device);
+        if let Some(config) = &self.config {
+            debug!("Using IR emitter profile {:?}", config);
+            cmd.arg("--config").arg(config);
+        }
+        let child = cmd
             .arg("run")
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -166,7 +209,7 @@ impl IrEmitter {
         
         cmd.arg("--device").arg(&self.device);
         
-        if let Some(config) = config_file {
+        if let Some(config) = config_file.map(Path::new).or(self.config.as_deref()) {
             cmd.arg("--config").arg(config);
         }
         
@@ -0,0 +1,29 @@
+//! PAM items the `pam` crate doesn't expose
+//!
+//! `get_user` only returns `PAM_USER`, the account being authenticated. For
+//! `su` that is the target (often root), while the person at the keyboard
+//! is in `PAM_RUSER`, set by the application.
+
+use pam::PamHandle;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_RUSER: c_int = 8;
+
+extern "C" {
+    fn pam_get_item(pamh: *const c_void, item_type: c_int, item: *mut *const c_void) -> c_int;
+}
+
+/// `PAM_RUSER`: the user requesting the service, if the application set it
+pub fn requesting_user(handle: &PamHandle) -> Option<String> {
+    unsafe {
+        let mut item: *const c_void = std::ptr::null();
+        let pamh = handle as *const PamHandle as *const c_void;
+        if pam_get_item(pamh, PAM_RUSER, &mut item) != PAM_SUCCESS || item.is_null() {
+            return None;
+        }
+        let name = CStr::from_ptr(item as *const c_char).to_string_lossy().into_owned();
+        (!name.is_empty()).then_some(name)
+    }
+}
@@ -1,8 +1,23 @@
-mod config;
-mod camera;
-mod face;
-mod auth;
-mod ir_emitter;
+pub mod config;
+pub mod camera;
+pub mod face;
+pub mod embedder;
+pub mod auth;
+pub mod stats;
+pub mod ir_emitter;
+pub mod userfile;
+mod hooks;
+mod safe_mode;
+mod cooldown;
+mod state_file;
+pub mod auto_disable;
+pub mod background;
+mod wipe;
+mod logging;
+mod conv;
+mod items;
+#[cfg(feature = "fake-camera")]
+pub mod fake_camera;
 
 use pam::{PamHandle, PamModule, PamReturnCode, export_pam_module, get_user};
 use std::ffi::CStr;
@@ -15,9 +30,12 @@ export_pam_module!(PamGlance);
 
 impl PamModule for PamGlance {
     fn authenticate(handle: &PamHandle, args: Vec<&CStr>, _flags: c_uint) -> PamReturnCode {
-        init_logging();
+        let parsed = parse_args(&args);
+        let log_config = parsed.as_ref().cloned().unwrap_or_default();
+        logging::init(log_config.log_target, &log_config.log_file,
+                      log_config.log_max_size, log_config.debug);
         
-        let config = match parse_args(&args) {
+        let config = match parsed {
             Ok(c) => c,
             Err(e) => {
                 error!("Failed to parse PAM arguments: {}", e);
@@ -25,7 +43,7 @@ impl PamModule for PamGlance {
             }
         };
         
-        let username = match get_user(handle, None) {
+        let pam_user = match get_user(handle, None) {
             Ok(u) => u.to_string(),
             Err(e) => {
                 error!("Failed to get username: {:?}", e);
@@ -33,44 +51,93 @@ impl PamModule for PamGlance {
             }
         };
         
+        // Under su, PAM_USER is the target account and PAM_RUSER the caller;
+        // which face counts is the admin's choice, never a guess
+        let ruser = items::requesting_user(handle);
+        if let Some(ref ruser) = ruser {
+            if *ruser != pam_user {
+                info!("PAM_USER is '{}', PAM_RUSER is '{}' (identity={})",
+                      pam_user, ruser, config.identity.label());
+            }
+        }
+        let Some(username) = config.identity.username(pam_user, ruser) else {
+            info!("Glance: identity=ruser but PAM_RUSER is not set — skipping face auth");
+            return PamReturnCode::Ignore;
+        };
+        
         info!("Glance authentication attempt for user: {}", username);
         
-        let mut auth_config = auth::AuthConfig::load();
-        auth_config.target_user = Some(username.clone());
+        let mut auth_config = auth::AuthConfig::for_module_user(&username);
         auth_config.timeout = std::time::Duration::from_secs_f64(config.timeout);
         auth_config.prefer_ir = config.prefer_ir;
+        auth_config.run_hooks = true;
+        if config.continuous_presence {
+            auth_config.continuous_presence = true;
+        }
+        if let Some(ms) = config.continuous_presence_ms {
+            auth_config.presence_time = std::time::Duration::from_millis(ms);
+        }
         
         if !config.data_dir.is_empty() {
             auth_config.data_dir = std::path::PathBuf::from(&config.data_dir);
         }
         
-        match auth::authenticate(&auth_config) {
-            auth::AuthResult::Success { username: matched_user, confidence, camera_type } => {
-                info!("Glance: User '{}' authenticated via {:?} (confidence: {:.2})", 
-                      matched_user, camera_type, confidence);
-                PamReturnCode::Success
-            }
-            auth::AuthResult::NoFaceDetected => {
-                info!("Glance: No face detected — use your password");
-                PamReturnCode::Auth_Err
-            }
-            auth::AuthResult::NoMatch => {
-                info!("Glance: Face not recognized — use your password");
-                PamReturnCode::Auth_Err
-            }
-            auth::AuthResult::Timeout => {
-                info!("Glance: Timed out — use your password");
-                PamReturnCode::Auth_Err
+        if let Some(disabled) = auto_disable::active(&auth_config) {
+            info!("Glance: disabled ({}) — skipping face auth until `glance enable`",
+                  disabled.missing.label());
+            return PamReturnCode::Ignore;
+        }
+        
+        if let Some(left) = cooldown::remaining(&username, auth_config.failure_cooldown) {
+            info!("Glance: face auth failed moments ago — leaving the camera off for {:.1}s",
+                  left.as_secs_f64());
+            return PamReturnCode::Ignore;
+        }
+        
+        let result = auth::authenticate(&auth_config);
+        if matches!(result, auth::AuthResult::Success { .. }) {
+            cooldown::clear();
+            auto_disable::record(&auth_config, None);
+        } else {
+            // Skips (not enrolled, safe mode, ...) never opened the camera,
+            // so there is no flash to hold back
+            if matches!(result, auth::AuthResult::NoMatch | auth::AuthResult::NoFaceDetected
+                        | auth::AuthResult::Timeout { .. } | auth::AuthResult::CameraObstructed) {
+                cooldown::record_failure(&username, auth_config.failure_cooldown);
             }
-            auth::AuthResult::Error(e) => {
-                error!("Glance: Error ({}) — use your password", e);
-                PamReturnCode::Auth_Err
+            // Only a failed attempt pays for looking at what's missing
+            auto_disable::record(&auth_config, auto_disable::check(&auth_config));
+        }
+        if auth_config.record_stats {
+            stats::record(&result);
+        }
+        
+        if config.pam_messages {
+            if let Some(message) = result.user_message() {
+                conv::send_info(handle, message);
             }
         }
+        
+        return_code(&result)
     }
 
-    fn set_credentials(_handle: &PamHandle, _args: Vec<&CStr>, _flags: c_uint) -> PamReturnCode {
-        PamReturnCode::Success
+    /// Glance verifies a face but holds no credentials (no tickets, keys or
+    /// group memberships), so every operation is `PAM_IGNORE` by default:
+    ///
+    /// - `PAM_ESTABLISH_CRED`: nothing to establish
+    /// - `PAM_DELETE_CRED`: nothing to delete
+    /// - `PAM_REINITIALIZE_CRED` / `PAM_REFRESH_CRED`: nothing to renew
+    ///
+    /// The `setcred=success` argument restores the old blanket `PAM_SUCCESS`
+    /// for stacks where glance is the only module in the setcred phase.
+    fn set_credentials(_handle: &PamHandle, args: Vec<&CStr>, _flags: c_uint) -> PamReturnCode {
+        let setcred_success = parse_args(&args).map(|c| c.setcred_success).unwrap_or(false);
+        
+        if setcred_success {
+            PamReturnCode::Success
+        } else {
+            PamReturnCode::Ignore
+        }
     }
 
     fn account_management(_handle: &PamHandle, _args: Vec<&CStr>, _flags: c_uint) -> PamReturnCode {
@@ -78,6 +145,83 @@ impl PamModule for PamGlance {
     }
 }
 
+/// The PAM return code for an attempt's result, logging the outcome
+fn return_code(result: &auth::AuthResult) -> PamReturnCode {
+    match result {
+        auth::AuthResult::Success { username: matched_user, confidence, camera_type } => {
+            info!("Glance: User '{}' authenticated via {:?} (confidence: {:.2})", 
+                  matched_user, camera_type, confidence);
+            PamReturnCode::Success
+        }
+        auth::AuthResult::NoFaceDetected => {
+            info!("Glance: No face detected — use your password");
+            PamReturnCode::Auth_Err
+        }
+        auth::AuthResult::NoMatch => {
+            info!("Glance: Face not recognized — use your password");
+            PamReturnCode::Auth_Err
+        }
+        auth::AuthResult::NotEnrolled => {
+            info!("Glance: No face enrolled for this user — skipping face auth");
+            PamReturnCode::Ignore
+        }
+        auth::AuthResult::SafeMode => {
+            info!("Glance: Cameras keep failing — skipping face auth for now");
+            PamReturnCode::Ignore
+        }
+        auth::AuthResult::CameraMismatch { .. } => {
+            info!("Glance: No camera of the enrolled type — skipping face auth");
+            PamReturnCode::Ignore
+        }
+        auth::AuthResult::CameraObstructed => {
+            info!("Glance: Camera covered — skipping face auth");
+            PamReturnCode::Ignore
+        }
+        auth::AuthResult::Overexposed => {
+            info!("Glance: Frames too bright (backlit?) — skipping face auth");
+            PamReturnCode::Ignore
+        }
+        auth::AuthResult::Timeout { .. } => {
+            info!("Glance: {} — use your password",
+                  result.timeout_summary().unwrap_or_default());
+            PamReturnCode::Auth_Err
+        }
+        auth::AuthResult::Error(e) => {
+            error!("Glance: Error ({}) — use your password", e);
+            PamReturnCode::Auth_Err
+        }
+    }
+}
+
+/// Whose face is checked, from the `identity=` argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Identity {
+    /// `PAM_USER`, the account being logged into (the target of `su`)
+    #[default]
+    User,
+    /// `PAM_RUSER`, the user asking for it (the caller of `su`)
+    RUser,
+}
+
+impl Identity {
+    fn label(&self) -> &'static str {
+        match self {
+            Identity::User => "user",
+            Identity::RUser => "ruser",
+        }
+    }
+    
+    /// Whose face to check given `PAM_USER` and `PAM_RUSER`. `None` when
+    /// that is `PAM_RUSER` and it isn't set: the module then ignores the
+    /// attempt rather than fall back to the target account
+    fn username(&self, pam_user: String, ruser: Option<String>) -> Option<String> {
+        match self {
+            Identity::User => Some(pam_user),
+            Identity::RUser => ruser,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PamConfig {
     pub timeout: f64,
@@ -85,6 +229,18 @@ pub struct PamConfig {
     pub data_dir: String,
     pub config_file: String,
     pub debug: bool,
+    pub log_target: logging::LogTarget,
+    pub log_file: String,
+    pub log_max_size: u64,
+    /// Return `PAM_SUCCESS` rather than `PAM_IGNORE` from pam_setcred
+    pub setcred_success: bool,
+    /// Tell the user why face auth failed via `PAM_TEXT_INFO`
+    pub pam_messages: bool,
+    /// Require continuous presence for this service even if the config
+    /// doesn't, e.g. only in /etc/pam.d/sudo
+    pub continuous_presence: bool,
+    pub continuous_presence_ms: Option<u64>,
+    pub identity: Identity,
 }
 
 impl Default for PamConfig {
@@ -99,6 +255,14 @@ impl Default for PamConfig {
             data_dir: "/var/lib/glance".to_string(),
             config_file: format!("{}/.config/glance/config.json", home),
             debug: false,
+            log_target: logging::LogTarget::Syslog,
+            log_file: logging::DEFAULT_LOG_FILE.to_string(),
+            log_max_size: logging::DEFAULT_LOG_MAX_SIZE,
+            setcred_success: false,
+            pam_messages: true,
+            continuous_presence: false,
+            continuous_presence_ms: None,
+            identity: Identity::User,
         }
     }
 }
@@ -119,27 +283,129 @@ fn parse_args(args: &[&CStr]) -> anyhow::Result<PamConfig> {
             config.prefer_ir = true;
         } else if arg_str == "prefer_rgb" {
             config.prefer_ir = false;
+        } else if arg_str == "continuous_presence" {
+            config.continuous_presence = true;
+        } else if let Some(value) = arg_str.strip_prefix("continuous_presence=") {
+            config.continuous_presence = true;
+            config.continuous_presence_ms = Some(value.parse()?);
         } else if arg_str == "debug" {
             config.debug = true;
+        } else if let Some(value) = arg_str.strip_prefix("log=") {
+            config.log_target = logging::LogTarget::parse(value)
+                .ok_or_else(|| anyhow::anyhow!("Invalid log target: {}", value))?;
+        } else if let Some(value) = arg_str.strip_prefix("log_file=") {
+            config.log_file = value.to_string();
+        } else if let Some(value) = arg_str.strip_prefix("log_max_size=") {
+            config.log_max_size = value.parse::<u64>()? * 1024;
+        } else if let Some(value) = arg_str.strip_prefix("pam_messages=") {
+            config.pam_messages = match value {
+                "on" => true,
+                "off" => false,
+                _ => anyhow::bail!("Invalid pam_messages value: {}", value),
+            };
+        } else if let Some(value) = arg_str.strip_prefix("identity=") {
+            config.identity = match value {
+                "user" => Identity::User,
+                "ruser" => Identity::RUser,
+                _ => anyhow::bail!("Invalid identity: {}", value),
+            };
+        } else if let Some(value) = arg_str.strip_prefix("setcred=") {
+            config.setcred_success = match value {
+                "success" => true,
+                "ignore" => false,
+                _ => anyhow::bail!("Invalid setcred behaviour: {}", value),
+            };
         }
     }
     
     Ok(config)
 }
 
-fn init_logging() {
-    use syslog::{Facility, Formatter3164, BasicLogger};
-    use log::LevelFilter;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    
+    fn parse(args: &[&str]) -> anyhow::Result<PamConfig> {
+        let owned: Vec<CString> = args.iter().map(|a| CString::new(*a).unwrap()).collect();
+        let args: Vec<&CStr> = owned.iter().map(CString::as_c_str).collect();
+        parse_args(&args)
+    }
+    
+    #[test]
+    fn identity_defaults_to_user() {
+        assert_eq!(parse(&[]).unwrap().identity, Identity::User);
+    }
+    
+    #[test]
+    fn identity_values_parse() {
+        assert_eq!(parse(&["identity=user"]).unwrap().identity, Identity::User);
+        assert_eq!(parse(&["identity=ruser"]).unwrap().identity, Identity::RUser);
+        assert_eq!(parse(&["timeout=2", "identity=ruser", "debug"]).unwrap().identity, Identity::RUser);
+    }
+    
+    #[test]
+    fn unknown_identity_is_rejected() {
+        assert!(parse(&["identity=bogus"]).is_err());
+        assert!(parse(&["identity="]).is_err());
+    }
+    
+    #[test]
+    fn user_identity_checks_the_target_account() {
+        let (user, ruser) = ("root".to_string(), Some("alice".to_string()));
+        assert_eq!(Identity::User.username(user.clone(), ruser), Some(user.clone()));
+        assert_eq!(Identity::User.username(user.clone(), None), Some(user));
+    }
     
-    let formatter = Formatter3164 {
-        facility: Facility::LOG_AUTH,
-        hostname: None,
-        process: "pam_glance".into(),
-        pid: std::process::id(),
-    };
+    #[test]
+    fn ruser_identity_checks_the_caller() {
+        assert_eq!(Identity::RUser.username("root".to_string(), Some("alice".to_string())),
+                   Some("alice".to_string()));
+    }
     
-    if let Ok(logger) = syslog::unix(formatter) {
-        let _ = log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
-            .map(|()| log::set_max_level(LevelFilter::Info));
+    #[test]
+    fn ruser_identity_without_ruser_is_ignored() {
+        assert_eq!(Identity::RUser.username("root".to_string(), None), None);
+    }
+    
+    /// `su` to root by alice, who is enrolled, on a machine where root isn't:
+    /// with identity=user alice's face must not open root's account
+    #[cfg(feature = "fake-camera")]
+    #[test]
+    fn su_to_unenrolled_root_is_ignored() {
+        use crate::auth::{AuthConfig, AuthResult, UserData};
+        use crate::embedder::CannedEmbedder;
+        use crate::fake_camera::ImageFrameSource;
+        use opencv::core::{Mat, Scalar, CV_8UC3};
+        use std::time::Duration;
+        
+        let alice: Vec<f32> = (0..128).map(|i| (i as f32 / 128.0) - 0.5).collect();
+        let dir = std::env::temp_dir().join(format!("glance-su-root-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = serde_json::json!({
+            "username": "alice",
+            "rgb_encodings": [{ "encoding": alice, "camera_type": "rgb" }],
+        });
+        std::fs::write(dir.join("alice.json"), data.to_string()).unwrap();
+        
+        let username = Identity::User.username("root".to_string(), Some("alice".to_string())).unwrap();
+        let config = AuthConfig {
+            models_dir: dir.clone(),
+            data_dir: dir.clone(),
+            user_data: UserData::None,
+            target_user: Some(username),
+            timeout: Duration::from_secs(10),
+            frame_delay_ms: 0,
+            ..AuthConfig::default()
+        };
+        let frame = Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(128.0)).unwrap();
+        let mut camera = ImageFrameSource::new(vec![frame], false).unwrap();
+        let embedder = CannedEmbedder::new(vec![((200, 120, 440, 360), alice)]);
+        
+        let result = auth::authenticate_with_embedder(&mut camera, &config, Box::new(embedder));
+        let _ = std::fs::remove_dir_all(dir);
+        
+        assert!(matches!(result, AuthResult::NotEnrolled), "got {:?}", result);
+        assert_eq!(return_code(&result), PamReturnCode::Ignore);
     }
 }
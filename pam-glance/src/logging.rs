@@ -0,0 +1,184 @@
+//! Log output: syslog, a size-rotated file, or both
+//!
+//! Syslog is the default. When it can't be reached (containers, minimal
+//! systems without a syslog daemon) the file logger takes over, and if
+//! that can't be opened either, logs go to stderr, so diagnostics are
+//! never silently lost.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const DEFAULT_LOG_FILE: &str = "/var/log/glance/pam_glance.log";
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    Syslog,
+    File,
+    Both,
+}
+
+impl LogTarget {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "syslog" => Some(LogTarget::Syslog),
+            "file" => Some(LogTarget::File),
+            "both" => Some(LogTarget::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Appends to a log file, moving it to `<path>.1` once it passes `max_size`
+pub struct FileLogger {
+    path: PathBuf,
+    max_size: u64,
+    file: Mutex<Option<File>>,
+}
+
+impl FileLogger {
+    pub fn new(path: PathBuf, max_size: u64) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        
+        Ok(Self {
+            path,
+            max_size,
+            file: Mutex::new(Some(file)),
+        })
+    }
+    
+    fn rotate_if_needed(&self, file: &mut Option<File>, incoming: usize) {
+        let size = file.as_ref()
+            .and_then(|f| f.metadata().ok())
+            .map(|m| m.len())
+            .unwrap_or(0);
+        
+        if size + incoming as u64 <= self.max_size {
+            return;
+        }
+        
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        *file = None;
+        let _ = fs::rename(&self.path, &rotated);
+        *file = OpenOptions::new().create(true).append(true).open(&self.path).ok();
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+    
+    fn log(&self, record: &Record) {
+        let line = format!(
+            "{} pam_glance[{}] {}: {}\n",
+            timestamp(),
+            std::process::id(),
+            record.level(),
+            record.args()
+        );
+        
+        let Ok(mut guard) = self.file.lock() else {
+            return;
+        };
+        self.rotate_if_needed(&mut guard, line.len());
+        if let Some(file) = guard.as_mut() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+    
+    fn flush(&self) {
+        if let Ok(mut guard) = self.file.lock() {
+            if let Some(file) = guard.as_mut() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// Seconds since the epoch; avoids pulling a date crate into the PAM module
+fn timestamp() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+/// Fans records out to several loggers
+struct MultiLogger(Vec<Box<dyn Log>>);
+
+impl Log for MultiLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.iter().any(|l| l.enabled(metadata))
+    }
+    
+    fn log(&self, record: &Record) {
+        for logger in &self.0 {
+            logger.log(record);
+        }
+    }
+    
+    fn flush(&self) {
+        for logger in &self.0 {
+            logger.flush();
+        }
+    }
+}
+
+fn syslog_logger() -> Option<Box<dyn Log>> {
+    use syslog::{BasicLogger, Facility, Formatter3164};
+    
+    let formatter = Formatter3164 {
+        facility: Facility::LOG_AUTH,
+        hostname: None,
+        process: "pam_glance".into(),
+        pid: std::process::id(),
+    };
+    
+    syslog::unix(formatter)
+        .ok()
+        .map(|logger| Box::new(BasicLogger::new(logger)) as Box<dyn Log>)
+}
+
+/// Install the process logger. If syslog is requested but unreachable, the
+/// file logger is used instead, and stderr if neither works. Only the first
+/// call in a process takes effect.
+pub fn init(target: LogTarget, path: &str, max_size: u64, debug: bool) {
+    let mut loggers: Vec<Box<dyn Log>> = Vec::new();
+    
+    let mut want_file = target != LogTarget::Syslog;
+    if target != LogTarget::File {
+        match syslog_logger() {
+            Some(logger) => loggers.push(logger),
+            None => want_file = true,
+        }
+    }
+    
+    if want_file {
+        if let Ok(logger) = FileLogger::new(PathBuf::from(path), max_size) {
+            loggers.push(Box::new(logger));
+        }
+    }
+    
+    let level = if debug { LevelFilter::Debug } else { LevelFilter::Info };
+    
+    // Last resort so failures are never silent: PAM stderr usually reaches
+    // the journal (gdm, systemd units) or the terminal (sudo)
+    if loggers.is_empty() {
+        let stderr_logger = env_logger::Builder::new()
+            .filter_level(level)
+            .target(env_logger::Target::Stderr)
+            .build();
+        loggers.push(Box::new(stderr_logger));
+    }
+    
+    let _ = log::set_boxed_logger(Box::new(MultiLogger(loggers)))
+        .map(|()| log::set_max_level(level));
+}
@@ -0,0 +1,77 @@
+//! Camera safe mode: stop trying a camera that keeps failing to open
+//!
+//! With a dead camera (unplugged dock, crashed driver) every login would
+//! wait out the open attempts before the password prompt. After
+//! `safe_mode_threshold` attempts in a row where no camera opened, face auth
+//! is skipped for `safe_mode_cooldown`, then the camera gets another chance.
+//! The count lives in /run/glance, so it is per boot.
+
+use crate::auth::AuthConfig;
+use crate::state_file::{self, now_secs};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+const STATE_FILE: &str = "/run/glance/camera_failures.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Attempts in a row where no camera could be opened
+    consecutive: u32,
+    /// Seconds since the Unix epoch until which the camera is skipped
+    #[serde(default)]
+    safe_until: u64,
+}
+
+fn load() -> State {
+    state_file::load(STATE_FILE).unwrap_or_default()
+}
+
+fn save(state: &State) {
+    state_file::save(STATE_FILE, state, "camera failure count");
+}
+
+/// Whether the camera should be skipped for this attempt
+pub fn active(config: &AuthConfig) -> bool {
+    if config.safe_mode_threshold == 0 {
+        return false;
+    }
+    
+    let state = load();
+    let now = now_secs();
+    if state.safe_until > now {
+        info!("Camera safe mode: skipping face auth for another {}s", state.safe_until - now);
+        return true;
+    }
+    false
+}
+
+/// No camera could be opened on this attempt
+pub fn record_failure(config: &AuthConfig) {
+    if config.safe_mode_threshold == 0 {
+        return;
+    }
+    
+    let mut state = load();
+    state.consecutive += 1;
+    if state.consecutive >= config.safe_mode_threshold {
+        state.safe_until = now_secs() + config.safe_mode_cooldown.as_secs();
+        warn!("No camera opened {} times in a row — entering safe mode for {:?}",
+              state.consecutive, config.safe_mode_cooldown);
+        // After the cooldown the next failure re-enters straight away
+        state.consecutive = config.safe_mode_threshold - 1;
+    }
+    save(&state);
+}
+
+/// A camera opened: leave safe mode and forget earlier failures
+pub fn record_open() {
+    let state = load();
+    if state.consecutive == 0 && state.safe_until == 0 {
+        return;
+    }
+    if state.safe_until > 0 {
+        info!("Camera opened again — leaving safe mode");
+    }
+    save(&State::default());
+}
@@ -0,0 +1,85 @@
+//! Small JSON state files kept between attempts
+//!
+//! Safe mode, the failure cooldown, auto-disable and the camera probe cache
+//! each keep one serde struct in a file under /run/glance or
+//! /var/lib/glance. A missing or unreadable file reads as `None`, and a
+//! failed write (usually: not running as root) is only logged, since losing
+//! the state costs at most an extra camera attempt.
+
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// The state stored at `path`, `None` if there is none or it doesn't parse
+pub fn load<T: DeserializeOwned>(path: &str) -> Option<T> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
+
+/// Store `state` at `path`, creating its directory. Written to a temporary
+/// file and renamed, so a concurrent `load` never sees half of it. `what`
+/// names the state in the log if that fails
+pub fn save<T: Serialize>(path: &str, state: &T, what: &str) {
+    let path = Path::new(path);
+    let tmp = path.with_extension("json.tmp");
+    
+    let written = path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| serde_json::to_string(state).map_err(std::io::Error::other))
+        .and_then(|content| fs::write(&tmp, content))
+        .and_then(|_| fs::rename(&tmp, path));
+    
+    if let Err(e) = written {
+        debug!("Could not save {} in {}: {}", what, path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct State {
+        count: u32,
+        at: u64,
+    }
+    
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("glance-state-file-{}", std::process::id()));
+        dir.join(name).to_string_lossy().into_owned()
+    }
+    
+    #[test]
+    fn saved_state_loads_back() {
+        let path = temp_path("round_trip.json");
+        let state = State { count: 3, at: now_secs() };
+        save(&path, &state, "test state");
+        assert_eq!(load::<State>(&path), Some(state));
+        assert!(!Path::new(&path).with_extension("json.tmp").exists());
+        let _ = fs::remove_file(&path);
+    }
+    
+    #[test]
+    fn missing_or_garbled_state_is_none() {
+        let path = temp_path("garbled.json");
+        assert_eq!(load::<State>(&path), None);
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        fs::write(&path, "{not json").unwrap();
+        assert_eq!(load::<State>(&path), None);
+        let _ = fs::remove_file(&path);
+    }
+}
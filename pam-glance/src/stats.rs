@@ -0,0 +1,118 @@
+//! Opt-in local counters of auth outcomes
+//!
+//! With `stats.enabled` set, every attempt adds its `AuthResult::reason` and
+//! a timestamp to a small JSON file, so `glance stats` can show whether face
+//! login mostly times out (lighting, IR) or mostly fails to match (templates).
+//! No images, encodings or usernames are stored, and nothing leaves the machine.
+
+use crate::auth::AuthResult;
+
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Attempts kept with their timestamp; older ones only remain in `counts`
+const MAX_EVENTS: usize = 1000;
+
+const SYSTEM_STATS: &str = "/var/lib/glance/stats.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Event {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Attempts per reason since the file was created
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    /// The most recent attempts, oldest first
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+/// Per-user file outside root, since screen lockers run the module as the
+/// user and can't write to /var/lib/glance
+pub fn user_stats_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("glance").join("stats.json"))
+}
+
+/// Where this process records attempts
+fn stats_path() -> Option<PathBuf> {
+    if unsafe { libc::geteuid() } == 0 {
+        Some(PathBuf::from(SYSTEM_STATS))
+    } else {
+        user_stats_path()
+    }
+}
+
+/// Every file attempts may have been recorded in, for reporting
+pub fn stats_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(SYSTEM_STATS)];
+    paths.extend(user_stats_path());
+    paths
+}
+
+impl Stats {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+    
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+    
+    fn add(&mut self, reason: &str, at: u64) {
+        *self.counts.entry(reason.to_string()).or_insert(0) += 1;
+        self.events.push(Event { at, reason: reason.to_string() });
+        if self.events.len() > MAX_EVENTS {
+            let excess = self.events.len() - MAX_EVENTS;
+            self.events.drain(..excess);
+        }
+    }
+    
+    /// Combine counts and events from several files
+    pub fn merge(&mut self, other: Stats) {
+        for (reason, count) in other.counts {
+            *self.counts.entry(reason).or_insert(0) += count;
+        }
+        self.events.extend(other.events);
+        self.events.sort_by_key(|e| e.at);
+    }
+}
+
+/// Add this result to the stats file. Failures are only logged: stats must
+/// never get in the way of authentication.
+pub fn record(result: &AuthResult) {
+    let Some(path) = stats_path() else {
+        return;
+    };
+    let at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    
+    let outcome = Stats::load(&path).or_else(|e| {
+        debug!("Starting over with unreadable stats {:?}: {}", path, e);
+        Ok::<_, anyhow::Error>(Stats::default())
+    }).and_then(|mut stats| {
+        stats.add(result.reason(), at);
+        stats.save(&path)
+    });
+    
+    if let Err(e) = outcome {
+        debug!("Could not record auth stats in {:?}: {}", path, e);
+    }
+}
@@ -0,0 +1,125 @@
+//! Usernames as file names
+//!
+//! Face data lives in `{username}.json`, but directory accounts can contain
+//! characters a file name can't (`EXAMPLE\bob`, `a/b`) or shouldn't (spaces,
+//! a leading dot). Anything outside a conservative set is percent-encoded,
+//! so `alice@corp.example` stays readable while `EXAMPLE\bob` becomes
+//! `EXAMPLE%5Cbob`. `%` itself is encoded too (`%25`), so every stem decodes
+//! one way. Plain names map to themselves, so existing files for them keep
+//! working unchanged.
+//!
+//! Files written before encoding existed used the raw name. A raw name that
+//! happens to be another name's encoding (`EXAMPLE%5Cbob` the account, not
+//! `EXAMPLE\bob`) is ambiguous; the encoded reading wins and the raw file is
+//! no longer picked up, so one account can never load another's faces.
+
+/// Bytes kept as they are anywhere in a file stem
+fn is_safe(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'@')
+}
+
+/// File stem face data for `username` is stored under
+pub fn file_stem(username: &str) -> String {
+    let mut stem = String::with_capacity(username.len());
+    for (i, &b) in username.as_bytes().iter().enumerate() {
+        // A leading dot would hide the file, or make `.`/`..`
+        if is_safe(b) && !(i == 0 && b == b'.') {
+            stem.push(b as char);
+        } else {
+            stem.push_str(&format!("%{:02X}", b));
+        }
+    }
+    stem
+}
+
+/// Username a file stem was made from. Stems that aren't valid encodings
+/// (files written before encoding existed) are returned as they are
+pub fn username_from_stem(stem: &str) -> String {
+    decode(stem).unwrap_or_else(|| stem.to_string())
+}
+
+/// The name `stem` is the encoding of, if `file_stem` would produce exactly
+/// `stem` for it
+fn decode(stem: &str) -> Option<String> {
+    let bytes = stem.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| stem.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let username = String::from_utf8(decoded).ok()?;
+    (file_stem(&username) == stem).then_some(username)
+}
+
+/// The unencoded stem older versions used for `username`, if it differs
+/// from `file_stem`, is still a single path component, and isn't the
+/// encoding of some other name
+pub fn legacy_stem(username: &str) -> Option<&str> {
+    let usable = !username.is_empty() && !username.contains('/') && !username.contains('\0');
+    (usable && file_stem(username) != username && decode(username).is_none()).then_some(username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn plain_names_are_unchanged() {
+        assert_eq!(file_stem("alice"), "alice");
+        assert_eq!(file_stem("alice@corp.example"), "alice@corp.example");
+        assert_eq!(username_from_stem("alice@corp.example"), "alice@corp.example");
+        assert_eq!(legacy_stem("alice@corp.example"), None);
+    }
+    
+    #[test]
+    fn domain_names_round_trip() {
+        assert_eq!(file_stem("EXAMPLE\\bob"), "EXAMPLE%5Cbob");
+        assert_eq!(username_from_stem("EXAMPLE%5Cbob"), "EXAMPLE\\bob");
+        assert_eq!(legacy_stem("EXAMPLE\\bob"), Some("EXAMPLE\\bob"));
+    }
+    
+    #[test]
+    fn unsafe_names_round_trip() {
+        for name in [".hidden", "..", "a/b", "with space", "ünïcode", "100%", "%41"] {
+            let stem = file_stem(name);
+            assert!(!stem.starts_with('.') && !stem.contains('/'), "{:?} -> {:?}", name, stem);
+            assert_eq!(username_from_stem(&stem), name);
+        }
+    }
+    
+    #[test]
+    fn literal_percent_is_escaped() {
+        assert_eq!(file_stem("%41"), "%2541");
+        assert_eq!(username_from_stem("%2541"), "%41");
+    }
+    
+    #[test]
+    fn legacy_stems_that_are_not_encodings_stay_raw() {
+        // Not what file_stem("A") gives, so a file from before encoding
+        assert_eq!(username_from_stem("%41"), "%41");
+        assert_eq!(username_from_stem("50%"), "50%");
+        assert_eq!(legacy_stem("%41"), Some("%41"));
+    }
+    
+    #[test]
+    fn legacy_stem_never_names_another_account() {
+        // Raw `EXAMPLE%5Cbob.json` is EXAMPLE\bob's encoded file
+        assert_eq!(legacy_stem("EXAMPLE%5Cbob"), None);
+        assert_eq!(file_stem("EXAMPLE%5Cbob"), "EXAMPLE%255Cbob");
+        assert_eq!(legacy_stem("a/b"), None);
+        assert_eq!(legacy_stem(""), None);
+    }
+}
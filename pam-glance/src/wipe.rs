@@ -0,0 +1,53 @@
+//! Best-effort scrubbing of biometric data from memory
+//!
+//! Camera frames and face templates are overwritten before their buffers
+//! are freed so they don't linger in reused heap memory. Volatile writes
+//! keep the compiler from optimising the zeroing away.
+
+use opencv::core::Mat;
+use opencv::prelude::*;
+use std::ops::Deref;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+pub fn zero_bytes(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+pub fn zero_f32s(buf: &mut [f32]) {
+    for v in buf.iter_mut() {
+        unsafe { std::ptr::write_volatile(v, 0.0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Zero a frame's pixels in place. Non-continuous Mats (ROIs into a larger
+/// buffer) are left alone; the frames we capture and convert are continuous.
+pub fn wipe_mat(mat: &mut Mat) {
+    if let Ok(bytes) = mat.data_bytes_mut() {
+        zero_bytes(bytes);
+    }
+}
+
+/// Registered face templates that are zeroed when dropped
+pub struct Templates(pub Vec<(String, Vec<Vec<f32>>)>);
+
+impl Deref for Templates {
+    type Target = [(String, Vec<Vec<f32>>)];
+    
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for Templates {
+    fn drop(&mut self) {
+        for (_, encodings) in self.0.iter_mut() {
+            for encoding in encodings.iter_mut() {
+                zero_f32s(encoding);
+            }
+        }
+    }
+}
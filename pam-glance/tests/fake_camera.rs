@@ -0,0 +1,98 @@
+//! The auth frame loop end to end on replayed frames, with canned faces
+//! standing in for the dlib models:
+//!
+//!     cargo test --features fake-camera
+
+#![cfg(feature = "fake-camera")]
+
+use opencv::core::{Mat, Scalar, CV_8UC3};
+use pam_glance::auth::{self, AuthConfig, AuthResult, UserData};
+use pam_glance::embedder::{CannedEmbedder, FaceRect};
+use pam_glance::fake_camera::ImageFrameSource;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const FACE: FaceRect = (200, 120, 440, 360);
+
+fn alice() -> Vec<f32> {
+    (0..128).map(|i| (i as f32 / 128.0) - 0.5).collect()
+}
+
+fn bob() -> Vec<f32> {
+    alice().iter().map(|x| -x).collect()
+}
+
+/// A data directory with `alice` enrolled, as `glance enroll` writes it
+fn enrolled_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("glance-fake-camera-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = serde_json::json!({
+        "username": "alice",
+        "rgb_encodings": [{ "encoding": alice(), "camera_type": "rgb" }],
+    });
+    std::fs::write(dir.join("alice.json"), data.to_string()).unwrap();
+    dir
+}
+
+fn config(data_dir: PathBuf) -> AuthConfig {
+    AuthConfig {
+        models_dir: data_dir.clone(),
+        data_dir,
+        user_data: UserData::None,
+        target_user: Some("alice".to_string()),
+        timeout: Duration::from_secs(10),
+        frame_delay_ms: 0,
+        ..AuthConfig::default()
+    }
+}
+
+/// Mid-grey frames: neither covered nor washed out
+fn grey_camera() -> ImageFrameSource {
+    let frame = Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(128.0)).unwrap();
+    ImageFrameSource::new(vec![frame], false).unwrap()
+}
+
+fn run(test: &str, source: &mut ImageFrameSource, faces: Vec<(FaceRect, Vec<f32>)>, tweak: impl FnOnce(&mut AuthConfig)) -> AuthResult {
+    let dir = enrolled_dir(test);
+    let mut config = config(dir.clone());
+    tweak(&mut config);
+    let result = auth::authenticate_with_embedder(source, &config, Box::new(CannedEmbedder::new(faces)));
+    let _ = std::fs::remove_dir_all(dir);
+    result
+}
+
+#[test]
+fn enrolled_face_authenticates() {
+    let result = run("match", &mut grey_camera(), vec![(FACE, alice())], |_| {});
+    match result {
+        AuthResult::Success { username, .. } => assert_eq!(username, "alice"),
+        other => panic!("expected Success, got {:?}", other),
+    }
+}
+
+#[test]
+fn other_face_does_not_match() {
+    let result = run("no-match", &mut grey_camera(), vec![(FACE, bob())], |_| {});
+    assert!(matches!(result, AuthResult::NoMatch), "got {:?}", result);
+}
+
+#[test]
+fn black_frames_have_no_face() {
+    let mut camera = ImageFrameSource::black(640, 480).unwrap();
+    let result = run("black", &mut camera, Vec::new(), |config| config.detect_obstruction = false);
+    assert!(matches!(result, AuthResult::NoFaceDetected), "got {:?}", result);
+}
+
+#[test]
+fn black_frames_look_covered() {
+    let mut camera = ImageFrameSource::black(640, 480).unwrap();
+    let result = run("covered", &mut camera, Vec::new(), |_| {});
+    assert!(matches!(result, AuthResult::CameraObstructed), "got {:?}", result);
+}
+
+#[test]
+fn unenrolled_user_is_skipped() {
+    let result = run("not-enrolled", &mut grey_camera(), vec![(FACE, alice())],
+                     |config| config.target_user = Some("carol".to_string()));
+    assert!(matches!(result, AuthResult::NotEnrolled), "got {:?}", result);
+}
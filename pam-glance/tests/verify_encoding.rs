@@ -0,0 +1,52 @@
+//! Checking an encoding made elsewhere against enrolled templates, with no
+//! camera and no models installed
+
+use pam_glance::auth::{self, AuthConfig, UserData};
+use std::path::PathBuf;
+
+fn alice() -> Vec<f32> {
+    (0..128).map(|i| (i as f32 / 128.0) - 0.5).collect()
+}
+
+/// A data directory with only `alice` enrolled, as `glance enroll` writes it
+fn enrolled_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("glance-verify-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = serde_json::json!({
+        "username": "alice",
+        "rgb_encodings": [{ "encoding": alice(), "camera_type": "rgb" }],
+    });
+    std::fs::write(dir.join("alice.json"), data.to_string()).unwrap();
+    dir
+}
+
+fn verify(test: &str, encoding: &[f32]) -> anyhow::Result<Option<f64>> {
+    let dir = enrolled_dir(test);
+    let config = AuthConfig {
+        models_dir: dir.clone(),
+        data_dir: dir.clone(),
+        user_data: UserData::None,
+        ..AuthConfig::default()
+    };
+    let result = auth::verify_encoding_with(&config, "alice", encoding);
+    let _ = std::fs::remove_dir_all(dir);
+    result
+}
+
+#[test]
+fn close_encoding_verifies() {
+    let close: Vec<f32> = alice().iter().map(|x| x + 0.01).collect();
+    let distance = verify("close", &close).unwrap().expect("close encoding should match");
+    assert!(distance < 0.2, "distance {}", distance);
+}
+
+#[test]
+fn distant_encoding_does_not_verify() {
+    let distant: Vec<f32> = alice().iter().map(|x| -x).collect();
+    assert!(verify("distant", &distant).unwrap().is_none());
+}
+
+#[test]
+fn wrong_length_encoding_is_an_error() {
+    assert!(verify("short", &alice()[..64]).is_err());
+}
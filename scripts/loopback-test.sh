@@ -0,0 +1,115 @@
+#!/bin/bash
+#
+# End-to-end test of the camera -> detect -> match path without hardware.
+#
+# Feeds known videos into a v4l2loopback device, enrolls from one with
+# `glance enroll --camera`, then checks that `test_auth --camera` accepts
+# another clip of the same person and rejects a different person.
+#
+# Usage: sudo scripts/loopback-test.sh ENROLL_VIDEO SAME_PERSON_VIDEO OTHER_PERSON_VIDEO
+#
+# Needs root (to load v4l2loopback), ffmpeg, the face models, and release
+# builds of both crates. Refuses to run while v4l2loopback is already loaded
+# (e.g. for OBS): its parameters can't be changed then, and unloading it
+# afterwards would take the other user's device away.
+
+set -e
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+BLUE='\033[0;34m'
+NC='\033[0m'
+
+print_status() { echo -e "${GREEN}[✓]${NC} $1"; }
+print_error() { echo -e "${RED}[✗]${NC} $1"; }
+print_info() { echo -e "${BLUE}[i]${NC} $1"; }
+
+DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
+PROJECT_ROOT="$(dirname "$DIR")"
+GLANCE="$PROJECT_ROOT/glance/target/release/glance"
+TEST_AUTH="$PROJECT_ROOT/pam-glance/target/release/test_auth"
+
+VIDEO_NR="${GLANCE_LOOPBACK_NR:-42}"
+DEVICE="/dev/video$VIDEO_NR"
+TEST_USER="glance-loopback-test"
+
+if [ $# -ne 3 ]; then
+    echo "Usage: $0 ENROLL_VIDEO SAME_PERSON_VIDEO OTHER_PERSON_VIDEO"
+    exit 2
+fi
+
+if [ "$EUID" -ne 0 ]; then
+    print_error "Please run as root (v4l2loopback must be loaded)"
+    exit 1
+fi
+
+for bin in "$GLANCE" "$TEST_AUTH"; do
+    if [ ! -x "$bin" ]; then
+        print_error "$bin not found — build both crates with --release first"
+        exit 1
+    fi
+done
+
+command -v ffmpeg >/dev/null || { print_error "ffmpeg is required"; exit 1; }
+
+if lsmod | grep -q '^v4l2loopback '; then
+    print_error "v4l2loopback is already loaded — unload it first (modprobe -r v4l2loopback)"
+    exit 1
+fi
+
+WORK_DIR="$(mktemp -d)"
+FEED_PID=""
+LOADED=0
+
+# Enrollment goes to $XDG_DATA_HOME/glance; auth reads the same directory
+export XDG_DATA_HOME="$WORK_DIR"
+
+cleanup() {
+    [ -n "$FEED_PID" ] && kill "$FEED_PID" 2>/dev/null || true
+    wait 2>/dev/null || true
+    # As root, enrollment also saved to /var/lib/glance
+    "$GLANCE" erase "$TEST_USER" >/dev/null 2>&1 || true
+    [ "$LOADED" -eq 1 ] && modprobe -r v4l2loopback 2>/dev/null || true
+    rm -rf "$WORK_DIR"
+}
+trap cleanup EXIT
+
+# Stream a video into the loopback device in a loop until stopped
+feed() {
+    [ -n "$FEED_PID" ] && kill "$FEED_PID" 2>/dev/null && wait "$FEED_PID" 2>/dev/null || true
+    ffmpeg -loglevel error -re -stream_loop -1 -i "$1" \
+        -vf scale=640:480,format=yuyv422 -f v4l2 "$DEVICE" &
+    FEED_PID=$!
+    # Give the producer time to start so the first read isn't empty
+    sleep 2
+}
+
+modprobe v4l2loopback devices=1 video_nr="$VIDEO_NR" card_label="Glance Loopback" exclusive_caps=1
+LOADED=1
+print_status "Loopback device ready at $DEVICE"
+
+feed "$1"
+print_info "Enrolling $TEST_USER from $1"
+"$GLANCE" enroll --camera "$DEVICE" --user "$TEST_USER"
+
+FAILED=0
+
+feed "$2"
+print_info "Authenticating the same person from $2"
+if "$TEST_AUTH" --camera "$DEVICE" --user "$TEST_USER" --data-dir "$WORK_DIR/glance" --timeout 5; then
+    print_status "Same person accepted"
+else
+    print_error "Same person was rejected"
+    FAILED=1
+fi
+
+feed "$3"
+print_info "Authenticating a different person from $3"
+if "$TEST_AUTH" --camera "$DEVICE" --user "$TEST_USER" --data-dir "$WORK_DIR/glance" --timeout 5; then
+    print_error "Different person was accepted"
+    FAILED=1
+else
+    print_status "Different person rejected"
+fi
+
+exit $FAILED